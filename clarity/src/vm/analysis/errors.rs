@@ -54,6 +54,8 @@ pub enum CheckErrors {
     TypeError(TypeSignature, TypeSignature),
     TypeLiteralError(TypeSignature, TypeSignature),
     TypeValueError(TypeSignature, Value),
+    /// A function argument, identified by its position, did not match its type
+    TypeValueErrorAt(usize, TypeSignature, Value),
 
     NoSuperType(TypeSignature, TypeSignature),
     InvalidTypeDescription,
@@ -149,6 +151,7 @@ pub enum CheckErrors {
     // generic binding syntax
     BadSyntaxBinding,
     BadSyntaxExpectedListOfPairs,
+    BadArgumentName(String),
 
     MaxContextDepthReached,
     UndefinedFunction(String),
@@ -158,6 +161,11 @@ pub enum CheckErrors {
     RequiresAtLeastArguments(usize, usize),
     RequiresAtMostArguments(usize, usize),
     IncorrectArgumentCount(usize, usize),
+    /// A function that takes from the first to the second number of arguments,
+    /// or at least the first if there is no second, was given the third
+    IncorrectArgumentCountRange(usize, Option<usize>, usize),
+    MissingNamedArgument(String),
+    UnexpectedNamedArgument(String),
     IfArmsMustMatch(TypeSignature, TypeSignature),
     MatchArmsMustMatch(TypeSignature, TypeSignature),
     DefaultTypesMustMatch(TypeSignature, TypeSignature),
@@ -170,15 +178,26 @@ pub enum CheckErrors {
     TraitReferenceUnknown(String),
     TraitMethodUnknown(String, String),
     ExpectedTraitIdentifier,
+    ExpectedCallableContract(TraitIdentifier, Value),
+    /// A function argument, identified by its position, was a contract
+    /// reference for a trait parameter, but not one of that trait
+    TraitArgumentMismatchAt(usize, TraitIdentifier, Value),
     ImportTraitBadSignature,
     TraitReferenceNotAllowed,
     BadTraitImplementation(String, String),
+    /// A new version of a function does not accept, at the given position,
+    /// every argument its old version did: the new type, then the old one
+    IncompatibleUpgradeArgument(usize, TypeSignature, TypeSignature),
+    /// A new version of a function changed its define type from the first to
+    /// the second
+    IncompatibleUpgradeDefineType(String, String),
     DefineTraitBadSignature,
     DefineTraitDuplicateMethod(String),
     UnexpectedTraitOrFieldReference,
     TraitBasedContractCallInReadOnly,
     ContractOfExpectsTrait,
     IncompatibleTrait(TraitIdentifier, TraitIdentifier),
+    DynamicDispatchDisabled,
 
     // strings
     InvalidCharactersDetected,
@@ -366,6 +385,7 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::TypeError(expected_type, found_type) => format!("expecting expression of type '{}', found '{}'", expected_type, found_type),
             CheckErrors::TypeLiteralError(expected_type, found_type) => format!("expecting a literal of type '{}', found '{}'", expected_type, found_type),
             CheckErrors::TypeValueError(expected_type, found_value) => format!("expecting expression of type '{}', found '{}'", expected_type, found_value),
+            CheckErrors::TypeValueErrorAt(index, expected_type, found_value) => format!("expecting argument {} to be of type '{}', found '{}'", index, expected_type, found_value),
             CheckErrors::UnionTypeError(expected_types, found_type) => format!("expecting expression of type {}, found '{}'", formatted_expected_types(expected_types), found_type),
             CheckErrors::UnionTypeValueError(expected_types, found_type) => format!("expecting expression of type {}, found '{}'", formatted_expected_types(expected_types), found_type),
             CheckErrors::ExpectedOptionalType(found_type) => format!("expecting expression of type 'optional', found '{}'", found_type),
@@ -415,12 +435,17 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::BadLetSyntax => "invalid syntax of 'let'".into(),
             CheckErrors::CircularReference(references) => format!("detected circular reference: ({})", references.join(", ")),
             CheckErrors::BadSyntaxBinding => "invalid syntax binding".into(),
+            CheckErrors::BadArgumentName(name) => format!("invalid argument name '{}'", name),
             CheckErrors::MaxContextDepthReached => "reached depth limit".into(),
             CheckErrors::UndefinedVariable(var_name) => format!("use of unresolved variable '{}'", var_name),
             CheckErrors::UndefinedFunction(var_name) => format!("use of unresolved function '{}'", var_name),
             CheckErrors::RequiresAtLeastArguments(expected, found) => format!("expecting >= {} arguments, got {}", expected, found),
             CheckErrors::RequiresAtMostArguments(expected, found) => format!("expecting < {} arguments, got {}", expected, found),
             CheckErrors::IncorrectArgumentCount(expected_count, found_count) => format!("expecting {} arguments, got {}", expected_count, found_count),
+            CheckErrors::IncorrectArgumentCountRange(min_count, Some(max_count), found_count) => format!("expecting {} to {} arguments, got {}", min_count, max_count, found_count),
+            CheckErrors::IncorrectArgumentCountRange(min_count, None, found_count) => format!("expecting at least {} arguments, got {}", min_count, found_count),
+            CheckErrors::MissingNamedArgument(name) => format!("missing value for argument '{}'", name),
+            CheckErrors::UnexpectedNamedArgument(name) => format!("function has no argument named '{}'", name),
             CheckErrors::IfArmsMustMatch(type_1, type_2) => format!("expression types returned by the arms of 'if' must match (got '{}' and '{}')", type_1, type_2),
             CheckErrors::MatchArmsMustMatch(type_1, type_2) => format!("expression types returned by the arms of 'match' must match (got '{}' and '{}')", type_1, type_2),
             CheckErrors::DefaultTypesMustMatch(type_1, type_2) => format!("expression types passed in 'default-to' must match (got '{}' and '{}')", type_1, type_2),
@@ -440,13 +465,18 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::TraitMethodUnknown(trait_name, func_name) => format!("method '{}' unspecified in trait <{}>", func_name, trait_name),
             CheckErrors::ImportTraitBadSignature => "(use-trait ...) expects a trait name and a trait identifier".into(),
             CheckErrors::BadTraitImplementation(trait_name, func_name) => format!("invalid signature for method '{}' regarding trait's specification <{}>", func_name, trait_name),
+            CheckErrors::IncompatibleUpgradeArgument(index, new_type, old_type) => format!("argument {} of the upgraded function has type '{}', which does not admit its previous type '{}'", index, new_type, old_type),
+            CheckErrors::IncompatibleUpgradeDefineType(old_type, new_type) => format!("the upgraded function is {}, but was {}", new_type, old_type),
             CheckErrors::ExpectedTraitIdentifier => "expecting expression of type trait identifier".into(),
+            CheckErrors::ExpectedCallableContract(trait_identifier, value) => format!("expected a contract implementing <{}>, found {}", trait_identifier, value),
+            CheckErrors::TraitArgumentMismatchAt(index, trait_identifier, value) => format!("expecting argument {} to be a reference to a contract implementing <{}>, found '{}'", index, trait_identifier, value),
             CheckErrors::UnexpectedTraitOrFieldReference => "unexpected use of trait reference or field".into(),
             CheckErrors::DefineTraitBadSignature => "invalid trait definition".into(),
             CheckErrors::DefineTraitDuplicateMethod(method_name) => format!("duplicate method name '{}' in trait definition", method_name),
             CheckErrors::TraitReferenceNotAllowed => "trait references can not be stored".into(),
             CheckErrors::ContractOfExpectsTrait => "trait reference expected".into(),
             CheckErrors::IncompatibleTrait(expected_trait, actual_trait) => format!("trait '{}' is not a compatible with expected trait, '{}'", actual_trait, expected_trait),
+            CheckErrors::DynamicDispatchDisabled => "dynamic dispatch through trait references is disabled".into(),
             CheckErrors::InvalidCharactersDetected => "invalid characters detected".into(),
             CheckErrors::InvalidUTF8Encoding => "invalid UTF8 encoding".into(),
             CheckErrors::InvalidSecp65k1Signature => "invalid seckp256k1 signature".into(),
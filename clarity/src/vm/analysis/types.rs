@@ -223,6 +223,9 @@ impl ContractAnalysis {
         }
     }
 
+    /// Check that this contract implements every method of `trait_definition`.
+    /// Methods are checked in order of their names, and the first that is
+    /// missing or does not comply is reported as `BadTraitImplementation`.
     pub fn check_trait_compliance(
         &self,
         epoch: &StacksEpochId,
@@ -14,29 +14,41 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::fmt;
 use std::iter::FromIterator;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
 
 use stacks_common::types::StacksEpochId;
+use stacks_common::util::hash::Sha256Sum;
 
 use super::costs::{CostErrors, CostOverflowingMath};
 use super::errors::InterpreterError;
-use super::types::signatures::CallableSubtype;
+use super::types::signatures::{CallableSubtype, FunctionSignature};
 use super::ClarityVersion;
 use crate::vm::analysis::errors::CheckErrors;
-use crate::vm::contexts::ContractContext;
+use crate::vm::contexts::{ArgumentSizeLimit, ContractContext};
 use crate::vm::costs::cost_functions::ClarityCostFunction;
 use crate::vm::costs::{cost_functions, runtime_cost};
-use crate::vm::errors::{check_argument_count, Error, InterpreterResult as Result};
-use crate::vm::representations::{ClarityName, Span, SymbolicExpression};
+use crate::vm::errors::{
+    check_argument_count, Error, FunctionError, InterpreterResult as Result, RuntimeErrorType,
+    ShortReturnType,
+};
+use crate::vm::functions::NativeFunctions;
+use crate::vm::representations::{
+    ClarityName, Span, SymbolicExpression, SymbolicExpressionType, TraitDefinition,
+};
 use crate::vm::types::Value::UInt;
 use crate::vm::types::{
-    CallableData, FunctionType, ListData, ListTypeData, OptionalData, PrincipalData,
-    QualifiedContractIdentifier, ResponseData, SequenceData, SequenceSubtype, TraitIdentifier,
-    TupleData, TupleTypeSignature, TypeSignature,
+    CallableData, FixedFunction, FunctionArg, FunctionType, ListData, ListTypeData, OptionalData,
+    PrincipalData, QualifiedContractIdentifier, ResponseData, SequenceData, SequenceSubtype,
+    TraitIdentifier, TupleData, TupleTypeSignature, TypeSignature,
 };
+use crate::vm::variables::NativeVariables;
 use crate::vm::{eval, Environment, LocalContext, Value};
 
 pub enum CallableType {
@@ -55,8 +67,105 @@ pub enum CallableType {
         &'static str,
         &'static dyn Fn(&[SymbolicExpression], &mut Environment, &LocalContext) -> Result<Value>,
     ),
+    HostFunction(Rc<HostFunction>),
+}
+
+/// A native function registered by the host embedding Clarity, rather than
+/// built in. See `GlobalContext::register_host_function`.
+pub struct HostFunction {
+    name: ClarityName,
+    function: HostFunctionBody,
+}
+
+enum HostFunctionBody {
+    Immediate(Box<dyn Fn(&[Value]) -> Result<Value>>),
+    Suspending(Box<dyn Fn(&[Value]) -> Result<HostCall>>),
+}
+
+/// The outcome of a host function created with `HostFunction::new_suspending`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCall {
+    /// The function's result.
+    Value(Value),
+    /// The result depends on the host resolving `request`, e.g. by querying
+    /// an oracle, outside of evaluation.
+    Suspend(Value),
+}
+
+impl HostFunction {
+    pub fn new(
+        name: ClarityName,
+        function: Box<dyn Fn(&[Value]) -> Result<Value>>,
+    ) -> HostFunction {
+        HostFunction {
+            name,
+            function: HostFunctionBody::Immediate(function),
+        }
+    }
+
+    /// A host function that may suspend evaluation until the host resolves a
+    /// request. The evaluator cannot pause in the middle of an expression, so
+    /// suspending aborts the transaction with
+    /// `RuntimeErrorType::HostCallSuspended`, carrying the request. The host
+    /// resumes by passing the resolved value to
+    /// `GlobalContext::resume_host_call` and executing the transaction again:
+    /// evaluation is deterministic, so it suspends at the same points, and
+    /// each suspension returns the next resolved value instead.
+    pub fn new_suspending(
+        name: ClarityName,
+        function: Box<dyn Fn(&[Value]) -> Result<HostCall>>,
+    ) -> HostFunction {
+        HostFunction {
+            name,
+            function: HostFunctionBody::Suspending(function),
+        }
+    }
+
+    pub fn get_name(&self) -> &ClarityName {
+        &self.name
+    }
+
+    /// Apply this function to already evaluated arguments. No runtime cost is
+    /// charged beyond evaluating the arguments: the function is the host's
+    /// own, and so is charging for it.
+    pub fn apply(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
+        let request = match &self.function {
+            HostFunctionBody::Immediate(function) => return function(args),
+            HostFunctionBody::Suspending(function) => match function(args)? {
+                HostCall::Value(value) => return Ok(value),
+                HostCall::Suspend(request) => request,
+            },
+        };
+        match env.global_context.next_host_resolution() {
+            Some(value) => Ok(value),
+            None => Err(RuntimeErrorType::HostCallSuspended(request).into()),
+        }
+    }
 }
 
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+/// Static facts about a special function, so that tooling can reason about
+/// special forms without matching on their names. See
+/// `NativeFunctions::special_fn_meta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpecialFnMeta {
+    pub min_args: usize,
+    /// `None` if any number of arguments beyond `min_args` is accepted.
+    pub max_args: Option<usize>,
+    /// Whether the function may read persisted chain or contract state.
+    pub reads_state: bool,
+    /// Whether the function may write persisted chain or contract state.
+    pub writes_state: bool,
+}
+
+/// Serialized by its Rust variant name, as stored with each contract's
+/// functions. External formats can use the Clarity keyword instead, with
+/// `#[serde(with = "define_type_keyword")]`.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DefineType {
     ReadOnly,
@@ -64,6 +173,72 @@ pub enum DefineType {
     Private,
 }
 
+impl DefineType {
+    /// The Clarity keyword for this define type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DefineType::ReadOnly => "read-only",
+            DefineType::Public => "public",
+            DefineType::Private => "private",
+        }
+    }
+}
+
+impl fmt::Display for DefineType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for DefineType {
+    type Err = Error;
+
+    fn from_str(define_type: &str) -> Result<DefineType> {
+        match define_type {
+            "read-only" => Ok(DefineType::ReadOnly),
+            "public" => Ok(DefineType::Public),
+            "private" => Ok(DefineType::Private),
+            _ => Err(RuntimeErrorType::ParseError(
+                "Invalid define type. Valid define types are: public, read-only, private."
+                    .to_string(),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Serialize a `DefineType` as its Clarity keyword, for formats such as
+/// analysis output that are read outside the node. The variant names that
+/// `DefineType` serializes as by default are also accepted. Data stored in
+/// chain state keeps the default form.
+pub mod define_type_keyword {
+    use std::str::FromStr;
+
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::DefineType;
+
+    pub fn serialize<S: Serializer>(
+        define_type: &DefineType,
+        s: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(define_type.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> std::result::Result<DefineType, D::Error> {
+        let define_type = String::deserialize(d)?;
+        match define_type.as_str() {
+            "ReadOnly" => Ok(DefineType::ReadOnly),
+            "Public" => Ok(DefineType::Public),
+            "Private" => Ok(DefineType::Private),
+            keyword => DefineType::from_str(keyword).map_err(D::Error::custom),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DefinedFunction {
     identifier: FunctionIdentifier,
@@ -72,6 +247,56 @@ pub struct DefinedFunction {
     pub define_type: DefineType,
     arguments: Vec<ClarityName>,
     body: SymbolicExpression,
+    /// What the host has set on top of the definition. Not serialized, so a
+    /// function read back from storage behaves exactly like a newly analyzed
+    /// one, and the host has to set these again.
+    #[serde(skip)]
+    extensions: FunctionExtensions,
+}
+
+/// Properties of a `DefinedFunction` that are set by the host rather than by
+/// the contract defining it. They are kept in memory only.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct FunctionExtensions {
+    /// Optional default values for the trailing arguments, set with
+    /// `set_arg_defaults`. Empty unless defaults were supplied.
+    arg_defaults: Vec<Option<Value>>,
+    /// Whether the last argument is a rest parameter, set with
+    /// `set_rest_argument`. Extra arguments are collected into a list bound to it.
+    rest_argument: bool,
+    /// The return type inferred by analysis, cached with `set_return_type`.
+    /// `None` until inference has run. When set, the result of every
+    /// application is checked against it.
+    return_type: Option<TypeSignature>,
+    /// The trait this function implements a method of, recorded with
+    /// `set_implemented_trait`. `None` for ordinary functions.
+    implemented_trait: Option<TraitIdentifier>,
+    /// Whether analysis has checked every call site of this function, set
+    /// with `set_args_statically_checked`. When set, the arguments are bound
+    /// without being checked against their declared types, except for trait
+    /// references.
+    args_statically_checked: bool,
+    /// Why this function is deprecated, set with `set_deprecation`. See
+    /// `GlobalContext::enable_vm_events`.
+    deprecation: Option<String>,
+    /// Conditions checked before the body, each with the value returned if it
+    /// is false, set with `set_preconditions`.
+    preconditions: Vec<(SymbolicExpression, Value)>,
+}
+
+/// Two functions are equal if they are defined identically, including any
+/// return type set with `set_return_type` and trait set with
+/// `set_implemented_trait`.
+impl PartialEq for DefinedFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.identifier == other.identifier
+            && self.name == other.name
+            && self.arg_types == other.arg_types
+            && self.define_type == other.define_type
+            && self.arguments == other.arguments
+            && self.body == other.body
+            && self.extensions == other.extensions
+    }
 }
 
 /// This enum handles the actual invocation of the method
@@ -108,6 +333,16 @@ impl NativeHandle {
             Self::MoreArgEnv(function) => function(args, env),
         }
     }
+
+    /// The number of arguments this handle expects, or `None` if it accepts
+    /// a variable number of arguments.
+    pub fn get_arity(&self) -> Option<usize> {
+        match self {
+            Self::SingleArg(_) => Some(1),
+            Self::DoubleArg(_) => Some(2),
+            Self::MoreArg(_) | Self::MoreArgEnv(_) => None,
+        }
+    }
 }
 
 pub fn cost_input_sized_vararg(args: &[Value]) -> Result<u64> {
@@ -121,9 +356,104 @@ pub fn cost_input_sized_vararg(args: &[Value]) -> Result<u64> {
         .map_err(Error::from)
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+/// An event passed to a function tracer installed with
+/// `GlobalContext::set_function_tracer`.
+#[derive(Debug)]
+pub enum TraceEvent<'a> {
+    /// A user function is about to be applied to `args`.
+    Enter { args: &'a [Value] },
+    /// A user function has returned. An early return from the function body
+    /// is reported as its result.
+    Exit { result: &'a Result<Value> },
+}
+
+/// A callback invoked with every `TraceEvent`. Like eval hooks, it is borrowed
+/// rather than owned, so that it does not give `GlobalContext` drop glue that
+/// outlives the database it borrows.
+pub type FunctionTracer<'hooks> = &'hooks mut dyn FnMut(&FunctionIdentifier, TraceEvent);
+
+/// Whether a `FunctionIdentifier` names a native (or special) function or a
+/// user-defined one. See `FunctionIdentifier::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Native,
+    User,
+}
+
+/// Identifiers are shared: clones share one string, and so do identifiers of
+/// the same function constructed separately, through the tables below.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub struct FunctionIdentifier {
-    identifier: String,
+    #[serde(with = "interned_identifier")]
+    identifier: Arc<str>,
+}
+
+const NATIVE_PREFIX: &str = "_native_:";
+
+lazy_static! {
+    /// Identifiers of native and special functions, keyed by function name.
+    /// Native names come from a fixed set of `&'static str`s, so each identifier
+    /// is formatted once instead of on every lookup.
+    static ref NATIVE_FUNCTION_IDENTIFIERS: RwLock<HashMap<&'static str, Arc<str>>> =
+        RwLock::new(HashMap::new());
+    /// Identifiers of all other functions. See `IdentifierTable`.
+    static ref FUNCTION_IDENTIFIERS: Mutex<IdentifierTable> =
+        Mutex::new(IdentifierTable::default());
+}
+
+/// Interned identifiers, held weakly so that an identifier is freed with the
+/// last function naming it. Entries left dead are dropped whenever the table
+/// has doubled in size since they were last dropped.
+#[derive(Default)]
+struct IdentifierTable {
+    identifiers: HashMap<Box<str>, Weak<str>>,
+    pruned_len: usize,
+}
+
+impl IdentifierTable {
+    const MIN_PRUNE_LEN: usize = 64;
+
+    fn intern(&mut self, identifier: &str) -> Arc<str> {
+        if let Some(shared) = self.identifiers.get(identifier).and_then(Weak::upgrade) {
+            return shared;
+        }
+        if self.identifiers.len() >= 2 * self.pruned_len.max(Self::MIN_PRUNE_LEN) {
+            self.identifiers
+                .retain(|_, shared| shared.strong_count() > 0);
+            self.pruned_len = self.identifiers.len();
+        }
+        let shared: Arc<str> = Arc::from(identifier);
+        self.identifiers
+            .insert(identifier.into(), Arc::downgrade(&shared));
+        shared
+    }
+}
+
+fn intern_identifier(identifier: &str) -> Arc<str> {
+    match FUNCTION_IDENTIFIERS.lock() {
+        Ok(mut table) => table.intern(identifier),
+        // a poisoned table only costs us the sharing
+        Err(_) => Arc::from(identifier),
+    }
+}
+
+/// Serialized as the plain string, as the identifier was before it was shared.
+mod interned_identifier {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        identifier: &Arc<str>,
+        s: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(identifier)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Arc<str>, D::Error> {
+        let identifier = String::deserialize(d)?;
+        Ok(super::intern_identifier(&identifier))
+    }
 }
 
 impl fmt::Display for FunctionIdentifier {
@@ -149,10 +479,287 @@ impl DefinedFunction {
             define_type,
             body,
             arg_types: types,
+            extensions: FunctionExtensions::default(),
+        }
+    }
+
+    /// Like `new`, but with the argument names and their types given
+    /// separately. Fails unless there is exactly one type per name, and
+    /// every name is a valid Clarity identifier.
+    pub fn try_new(
+        arguments: Vec<ClarityName>,
+        arg_types: Vec<TypeSignature>,
+        body: SymbolicExpression,
+        define_type: DefineType,
+        name: &ClarityName,
+        context_name: &str,
+    ) -> Result<DefinedFunction> {
+        if arguments.len() != arg_types.len() {
+            return Err(CheckErrors::DefineFunctionBadSignature.into());
+        }
+        Self::check_argument_names(&arguments)?;
+        Ok(DefinedFunction::new(
+            arguments.into_iter().zip(arg_types).collect(),
+            body,
+            define_type,
+            name,
+            context_name,
+        ))
+    }
+
+    /// A copy of this function named `new_name` in the context `new_context`,
+    /// for generating contracts from a template without parsing them again.
+    /// Everything else is copied verbatim, except that the arguments are
+    /// checked again until `set_args_statically_checked` is called: their call
+    /// sites in the new contract have not been analyzed.
+    pub fn clone_with_name(&self, new_name: &ClarityName, new_context: &str) -> DefinedFunction {
+        let mut function = DefinedFunction {
+            identifier: FunctionIdentifier::new_user_function(new_name, new_context),
+            name: new_name.clone(),
+            ..self.clone()
+        };
+        function.extensions.args_statically_checked = false;
+        function
+    }
+
+    /// Check that this function's argument names are valid Clarity
+    /// identifiers and are distinct.
+    ///
+    /// Analysis accepts contracts that repeat an argument name, and such a
+    /// function only fails once it is called, so this is not enforced when a
+    /// contract is deployed: doing so would change which contracts are valid.
+    /// Hosts and tooling that construct functions directly can use it to reject
+    /// them at definition time instead.
+    pub fn validate(&self) -> Result<()> {
+        Self::check_argument_names(&self.arguments)?;
+        let mut seen = HashSet::new();
+        for name in self.arguments.iter() {
+            if !seen.insert(name) {
+                return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Parsing only produces valid names, but one deserialized as part of a
+    /// malformed AST is not checked.
+    fn check_argument_names(arguments: &[ClarityName]) -> Result<()> {
+        match arguments.iter().find(|name| !name.is_valid()) {
+            Some(name) => Err(CheckErrors::BadArgumentName(name.to_string()).into()),
+            None => Ok(()),
         }
     }
 
     pub fn execute_apply(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
+        self.inner_execute_apply(
+            Cow::Borrowed(args),
+            env,
+            !self.extensions.args_statically_checked,
+            &mut LocalContext::new(),
+            false,
+        )
+    }
+
+    /// Apply this function as in `execute_apply`, allowing it at most `budget`
+    /// units of runtime cost on top of what has already been spent. See
+    /// `Environment::with_runtime_budget`. A free cost tracker charges
+    /// nothing, so the whole budget is always left.
+    pub fn execute_apply_metered(
+        &self,
+        args: &[Value],
+        env: &mut Environment,
+        budget: u64,
+    ) -> Result<(Value, u64)> {
+        env.with_runtime_budget(budget, |env| self.execute_apply(args, env))
+    }
+
+    /// Like `execute_apply`, but any error is returned together with this
+    /// function's identifier, so that it can be reported with the contract and
+    /// function it came from. The error itself is unchanged, and is available
+    /// from `FunctionError::inner`.
+    pub fn execute_apply_with_context(
+        &self,
+        args: &[Value],
+        env: &mut Environment,
+    ) -> std::result::Result<Value, FunctionError> {
+        self.execute_apply(args, env)
+            .map_err(|error| FunctionError::new(self.identifier.clone(), error))
+    }
+
+    /// Apply this function to each of `arg_sets` in turn, as `execute_apply`
+    /// would, returning one result per set. A single `LocalContext` is reused
+    /// across runs, and cleared before each one, so no binding carries over
+    /// from one set of arguments to the next.
+    pub fn execute_apply_many(
+        &self,
+        arg_sets: &[Vec<Value>],
+        env: &mut Environment,
+    ) -> Vec<Result<Value>> {
+        let mut context = LocalContext::new();
+        arg_sets
+            .iter()
+            .map(|args| {
+                context.clear();
+                self.inner_execute_apply(
+                    Cow::Borrowed(args),
+                    env,
+                    !self.extensions.args_statically_checked,
+                    &mut context,
+                    false,
+                )
+            })
+            .collect()
+    }
+
+    /// Apply this function with its arguments bound on top of the variables
+    /// already in `base`, instead of in a fresh context. Fails with
+    /// `CheckErrors::NameAlreadyUsed` if an argument is named like one of them.
+    /// Otherwise, this behaves exactly like `execute_apply`.
+    pub fn execute_apply_in(
+        &self,
+        args: &[Value],
+        env: &mut Environment,
+        mut base: LocalContext,
+    ) -> Result<Value> {
+        self.inner_execute_apply(
+            Cow::Borrowed(args),
+            env,
+            !self.extensions.args_statically_checked,
+            &mut base,
+            false,
+        )
+    }
+
+    /// Apply this function to arguments supplied by name rather than by
+    /// position. Every argument must be named exactly once, except that an
+    /// argument with a default may be omitted, and the rest parameter, if any,
+    /// is supplied as a list of the values it collects. The arguments are then
+    /// ordered positionally and applied with `execute_apply`.
+    pub fn execute_apply_named(
+        &self,
+        named: &HashMap<ClarityName, Value>,
+        env: &mut Environment,
+    ) -> Result<Value> {
+        if let Some(unknown) = named
+            .keys()
+            .filter(|name| !self.arguments.contains(name))
+            .min()
+        {
+            return Err(CheckErrors::UnexpectedNamedArgument(unknown.to_string()).into());
+        }
+
+        let mut args = Vec::with_capacity(self.arguments.len());
+        for (index, name) in self.arguments.iter().enumerate() {
+            let is_rest = self.extensions.rest_argument && index + 1 == self.arguments.len();
+            match (named.get(name), is_rest) {
+                (Some(Value::Sequence(SequenceData::List(list))), true) => {
+                    args.extend(list.data.iter().cloned())
+                }
+                (Some(value), true) => {
+                    return Err(CheckErrors::TypeValueErrorAt(
+                        index,
+                        self.arg_types[index].clone(),
+                        value.clone(),
+                    )
+                    .into())
+                }
+                (Some(value), false) => args.push(value.clone()),
+                (None, true) => {}
+                (None, false) => match self.extensions.arg_defaults.get(index) {
+                    Some(Some(default)) => args.push(default.clone()),
+                    _ => return Err(CheckErrors::MissingNamedArgument(name.to_string()).into()),
+                },
+            }
+        }
+
+        self.execute_apply(&args, env)
+    }
+
+    /// Apply this function, skipping the per-argument `admits` checks if this
+    /// function has been registered as verified in
+    /// `ExecutionOptions::verified_functions`. Functions that are not registered
+    /// are applied exactly as in `execute_apply`.
+    ///
+    /// Trait-reference arguments are always handled, since the conformance of a
+    /// dynamically supplied contract cannot be statically guaranteed. The type
+    /// checking costs are charged either way, so this path is cost-equivalent
+    /// to `execute_apply`.
+    pub fn execute_apply_trusted(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
+        let check_types = !self.extensions.args_statically_checked
+            && !env.global_context.is_function_verified(&self.identifier);
+        self.inner_execute_apply(
+            Cow::Borrowed(args),
+            env,
+            check_types,
+            &mut LocalContext::new(),
+            false,
+        )
+    }
+
+    /// Like `execute_apply`, but if the body returns early, as with `unwrap!`
+    /// or `asserts!`, fail with the `Error::ShortReturn` instead of returning
+    /// its value, so that callers can tell the two apart. The value is still
+    /// checked against the return type, if one is set.
+    pub fn execute_apply_raw(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
+        self.inner_execute_apply(
+            Cow::Borrowed(args),
+            env,
+            !self.extensions.args_statically_checked,
+            &mut LocalContext::new(),
+            true,
+        )
+    }
+
+    /// Apply this function with `args` supplied as an owned, borrowed, or
+    /// defaulted list of values. Owned values are moved into `context`, while
+    /// borrowed ones are cloned into it as they are bound.
+    fn inner_execute_apply(
+        &self,
+        args: Cow<'_, [Value]>,
+        env: &mut Environment,
+        check_types: bool,
+        context: &mut LocalContext,
+        keep_short_return: bool,
+    ) -> Result<Value> {
+        if env.global_context.observes_applications() {
+            return env.apply_observed(self, args, check_types, context, keep_short_return);
+        }
+        self.bind_and_evaluate(args, env, check_types, context, keep_short_return)
+    }
+
+    pub(crate) fn bind_and_evaluate(
+        &self,
+        args: Cow<'_, [Value]>,
+        env: &mut Environment,
+        check_types: bool,
+        context: &mut LocalContext,
+        keep_short_return: bool,
+    ) -> Result<Value> {
+        self.bind(args, env, check_types, context)?;
+        self.evaluate_body(env, context, keep_short_return)
+    }
+
+    /// Charge for this application and bind `args` in `context`.
+    pub(crate) fn bind(
+        &self,
+        args: Cow<'_, [Value]>,
+        env: &mut Environment,
+        check_types: bool,
+        context: &mut LocalContext,
+    ) -> Result<()> {
+        // Every constructor pairs names with types, but a function read back
+        // from storage is not checked: refuse it rather than drop arguments.
+        debug_assert_eq!(self.arguments.len(), self.arg_types.len());
+        if self.arguments.len() != self.arg_types.len() {
+            return Err(InterpreterError::Expect(format!(
+                "Function {} has {} arguments but {} argument types",
+                self.identifier,
+                self.arguments.len(),
+                self.arg_types.len()
+            ))
+            .into());
+        }
+
         runtime_cost(
             ClarityCostFunction::UserFunctionApplication,
             env,
@@ -167,581 +774,4390 @@ impl DefinedFunction {
             )?;
         }
 
-        let mut context = LocalContext::new();
-        if args.len() != self.arguments.len() {
-            Err(CheckErrors::IncorrectArgumentCount(
-                self.arguments.len(),
-                args.len(),
-            ))?
+        // a nullary function has nothing to bind
+        if self.arguments.is_empty() && args.is_empty() {
+            if let Some(caller) = &env.caller {
+                context.set_caller(caller.clone());
+            }
+            return Ok(());
         }
 
-        let mut arg_iterator: Vec<_> = self
-            .arguments
-            .iter()
-            .zip(self.arg_types.iter())
-            .zip(args.iter())
-            .collect();
-
-        for arg in arg_iterator.drain(..) {
-            let ((name, type_sig), value) = arg;
-
-            // Clarity 1 behavior
-            if *env.contract_context.get_clarity_version() < ClarityVersion::Clarity2 {
-                match (type_sig, value) {
-                    // Epoch < 2.1 uses TraitReferenceType
-                    (
-                        TypeSignature::TraitReferenceType(trait_identifier),
-                        Value::Principal(PrincipalData::Contract(callee_contract_id)),
-                    ) if *env.epoch() < StacksEpochId::Epoch21 => {
-                        // Argument is a trait reference, probably leading to a dynamic contract call
-                        // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
-                        // The code fetching and checking the trait is implemented in the contract_call eval function.
-                        context.callable_contracts.insert(
-                            name.clone(),
-                            CallableData {
-                                contract_identifier: callee_contract_id.clone(),
-                                trait_identifier: Some(trait_identifier.clone()),
-                            },
-                        );
-                    }
-                    // Epoch >= 2.1 uses CallableType
-                    (
-                        TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
-                        Value::Principal(PrincipalData::Contract(callee_contract_id)),
-                    ) if *env.epoch() >= StacksEpochId::Epoch21 => {
-                        // Argument is a trait reference, probably leading to a dynamic contract call
-                        // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
-                        // The code fetching and checking the trait is implemented in the contract_call eval function.
-                        context.callable_contracts.insert(
-                            name.clone(),
-                            CallableData {
-                                contract_identifier: callee_contract_id.clone(),
-                                trait_identifier: Some(trait_identifier.clone()),
-                            },
-                        );
-                    }
-                    // Since this Clarity 1 contract may be called from a Clarity 2 contract,
-                    // we need to handle Clarity 2 values as well. Clarity 2 contracts can only
-                    // be executed in epoch 2.1, so we only need to handle `CallableType` here.
-                    (
-                        TypeSignature::CallableType(CallableSubtype::Trait(_)),
-                        Value::CallableContract(CallableData {
-                            contract_identifier,
-                            trait_identifier,
-                        }),
-                    ) => {
-                        context.callable_contracts.insert(
-                            name.clone(),
-                            CallableData {
-                                contract_identifier: contract_identifier.clone(),
-                                trait_identifier: trait_identifier.clone(),
-                            },
-                        );
-                    }
-                    _ => {
-                        if !type_sig.admits(env.epoch(), value)? {
-                            return Err(CheckErrors::TypeValueError(
-                                type_sig.clone(),
-                                value.clone(),
-                            )
-                            .into());
-                        }
-                        if let Some(_) = context.variables.insert(name.clone(), value.clone()) {
-                            return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
-                        }
-                    }
-                }
-            } else {
-                // Clarity 2+ behavior
-                // Arguments containing principal literals can be implicitly cast to traits
-                // to match parameter types.
-                // e.g. `(some .foo)` to `(optional <trait>`)
-                // and traits can be implicitly cast to sub-traits
-                // e.g. `<foo-and-bar>` to `<foo>`
-                let cast_value = clarity2_implicit_cast(type_sig, value)?;
-
-                match (&type_sig, &cast_value) {
-                    (
-                        TypeSignature::CallableType(CallableSubtype::Trait(_)),
-                        Value::CallableContract(CallableData {
-                            contract_identifier,
-                            trait_identifier,
-                        }),
-                    ) => {
-                        // Argument is a trait reference, probably leading to a dynamic contract call.
-                        // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
-                        // The trait compatibility has been checked by the type-checker.
-                        context.callable_contracts.insert(
-                            name.clone(),
-                            CallableData {
-                                contract_identifier: contract_identifier.clone(),
-                                trait_identifier: trait_identifier.clone(),
-                            },
-                        );
-                    }
-                    _ => {
-                        if !type_sig.admits(env.epoch(), &cast_value)? {
-                            return Err(
-                                CheckErrors::TypeValueError(type_sig.clone(), cast_value).into()
-                            );
-                        }
-                    }
-                }
+        self.validate_arity(args.len())?;
+        let args = self.fill_default_args(args)?;
+        let args = self.collect_rest_args(args, env.epoch())?;
 
-                if let Some(_) = context.variables.insert(name.clone(), cast_value) {
-                    return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
-                }
-            }
+        if let Some(caller) = &env.caller {
+            context.set_caller(caller.clone());
         }
+        let values: Vec<Cow<Value>> = match args {
+            Cow::Borrowed(args) => args.iter().map(Cow::Borrowed).collect(),
+            Cow::Owned(args) => args.into_iter().map(Cow::Owned).collect(),
+        };
+        bind_arguments(
+            context,
+            &self.arguments,
+            &self.arg_types,
+            values,
+            env,
+            check_types,
+        )
+    }
 
-        let result = eval(&self.body, env, &context);
+    pub(crate) fn evaluate_body(
+        &self,
+        env: &mut Environment,
+        context: &LocalContext,
+        keep_short_return: bool,
+    ) -> Result<Value> {
+        let result = self
+            .check_preconditions(env, context)
+            .and_then(|_| eval(&self.body, env, context));
 
         // if the error wasn't actually an error, but a function return,
         //    pull that out and return it.
-        match result {
-            Ok(r) => Ok(r),
+        let result = match result {
+            Ok(r) => r,
+            Err(Error::ShortReturn(v)) if keep_short_return => {
+                self.check_return_type(v.value(), env)?;
+                return Err(Error::ShortReturn(v));
+            }
             Err(e) => match e {
-                Error::ShortReturn(v) => Ok(v.into()),
-                _ => Err(e),
+                Error::ShortReturn(v) => v.into(),
+                _ => return Err(e),
             },
-        }
-    }
+        };
 
-    pub fn check_trait_expectations(
-        &self,
-        epoch: &StacksEpochId,
-        contract_defining_trait: &ContractContext,
-        trait_identifier: &TraitIdentifier,
-    ) -> Result<()> {
-        let trait_name = trait_identifier.name.to_string();
-        let constraining_trait = contract_defining_trait
-            .lookup_trait_definition(&trait_name)
-            .ok_or(CheckErrors::TraitReferenceUnknown(trait_name.to_string()))?;
-        let expected_sig =
-            constraining_trait
-                .get(&self.name)
-                .ok_or(CheckErrors::TraitMethodUnknown(
-                    trait_name.to_string(),
-                    self.name.to_string(),
-                ))?;
+        self.check_return_type(&result, env)?;
+        Ok(result)
+    }
 
-        let args = self.arg_types.iter().map(|a| a.clone()).collect();
-        if !expected_sig.check_args_trait_compliance(epoch, args)? {
-            return Err(
-                CheckErrors::BadTraitImplementation(trait_name, self.name.to_string()).into(),
-            );
+    /// Evaluate the preconditions in order with the arguments bound. The
+    /// first that is false returns its value early, as `asserts!` does.
+    fn check_preconditions(&self, env: &mut Environment, context: &LocalContext) -> Result<()> {
+        for (condition, thrown) in self.extensions.preconditions.iter() {
+            match eval(condition, env, context)? {
+                Value::Bool(true) => {}
+                Value::Bool(false) => {
+                    return Err(ShortReturnType::AssertionFailed(thrown.clone()).into())
+                }
+                value => {
+                    return Err(CheckErrors::TypeValueError(TypeSignature::BoolType, value).into())
+                }
+            }
         }
-
         Ok(())
     }
 
-    pub fn is_read_only(&self) -> bool {
-        self.define_type == DefineType::ReadOnly
+    fn check_return_type(&self, result: &Value, env: &mut Environment) -> Result<()> {
+        if let Some(return_type) = &self.extensions.return_type {
+            if !return_type.admits(env.epoch(), result)? {
+                return Err(
+                    CheckErrors::TypeValueError(return_type.clone(), result.clone()).into(),
+                );
+            }
+        }
+        Ok(())
     }
 
-    pub fn apply(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
-        match self.define_type {
-            DefineType::Private => self.execute_apply(args, env),
-            DefineType::Public => env.execute_function_as_transaction(self, args, None),
-            DefineType::ReadOnly => env.execute_function_as_transaction(self, args, None),
+    /// If fewer arguments than parameters were supplied, and every missing
+    /// trailing parameter has a default, append those defaults to `args`.
+    /// Otherwise `args` is returned as-is.
+    fn fill_default_args<'a>(&self, args: Cow<'a, [Value]>) -> Result<Cow<'a, [Value]>> {
+        let fixed_count = self.fixed_argument_count();
+        if args.len() >= fixed_count || self.extensions.arg_defaults.is_empty() {
+            return Ok(args);
+        }
+
+        let missing = &self.extensions.arg_defaults[args.len()..fixed_count];
+        if missing.iter().any(Option::is_none) {
+            return Ok(args);
         }
+        let mut filled = args.into_owned();
+        filled.extend(missing.iter().flatten().cloned());
+        Ok(Cow::Owned(filled))
     }
 
-    pub fn is_public(&self) -> bool {
-        match self.define_type {
-            DefineType::Public => true,
-            DefineType::Private => false,
-            DefineType::ReadOnly => true,
+    /// If this function has a rest parameter, collect every argument beyond the
+    /// fixed prefix into a list bound to it. Each collected value must admit the
+    /// rest parameter's list item type. If fewer arguments than the fixed prefix
+    /// were supplied, `args` is returned as-is.
+    fn collect_rest_args<'a>(
+        &self,
+        args: Cow<'a, [Value]>,
+        epoch: &StacksEpochId,
+    ) -> Result<Cow<'a, [Value]>> {
+        let fixed_count = self.fixed_argument_count();
+        if !self.extensions.rest_argument || args.len() < fixed_count {
+            return Ok(args);
+        }
+
+        let item_type = match self.arg_types.last() {
+            Some(TypeSignature::SequenceType(SequenceSubtype::ListType(list_type))) => {
+                list_type.get_list_item_type()
+            }
+            _ => {
+                return Err(
+                    InterpreterError::Expect("Rest parameter must have a list type".into()).into(),
+                )
+            }
+        };
+
+        let mut collected = args.into_owned();
+        let rest = collected.split_off(fixed_count);
+        for (index, value) in rest.iter().enumerate() {
+            if !item_type.admits(epoch, value)? {
+                return Err(CheckErrors::TypeValueErrorAt(
+                    fixed_count + index,
+                    item_type.clone(),
+                    value.clone(),
+                )
+                .into());
+            }
         }
+        let rest_len = u32::try_from(rest.len()).map_err(|_| CheckErrors::ValueTooLarge)?;
+        let rest_type = ListTypeData::new_list(item_type.clone(), rest_len)?;
+        collected.push(Value::Sequence(SequenceData::List(ListData {
+            data: rest,
+            type_signature: rest_type,
+        })));
+        Ok(Cow::Owned(collected))
     }
 
-    pub fn get_identifier(&self) -> FunctionIdentifier {
-        self.identifier.clone()
+    /// The number of arguments that must be supplied positionally, i.e. every
+    /// argument except the rest parameter, if any.
+    fn fixed_argument_count(&self) -> usize {
+        if self.extensions.rest_argument {
+            self.arguments.len().saturating_sub(1)
+        } else {
+            self.arguments.len()
+        }
     }
 
-    pub fn get_arguments(&self) -> &Vec<ClarityName> {
-        &self.arguments
+    /// The number of arguments this function declares, counting the rest
+    /// parameter, if any, as one.
+    pub fn arg_count(&self) -> usize {
+        self.arguments.len()
     }
 
-    pub fn get_arg_types(&self) -> &Vec<TypeSignature> {
-        &self.arg_types
+    /// Check that this function can be applied to `n` arguments, taking
+    /// defaults and the rest parameter into account. Fails otherwise with the
+    /// error `execute_apply` reports for the wrong number of arguments; see
+    /// `arity_error`.
+    pub fn validate_arity(&self, n: usize) -> Result<()> {
+        let (min, max) = self.accepted_argument_count();
+        if n < min || max.map_or(false, |max| n > max) {
+            return Err(self.arity_error(n).into());
+        }
+        Ok(())
     }
 
-    pub fn canonicalize_types(&mut self, epoch: &StacksEpochId) {
-        for i in 0..self.arguments.len() {
-            self.arg_types[i] = self.arg_types[i].canonicalize(epoch);
+    /// The error for applying this function to `n` arguments, if it cannot
+    /// take that many: `CheckErrors::IncorrectArgumentCount` if it takes a
+    /// fixed number of arguments, and `CheckErrors::IncorrectArgumentCountRange`
+    /// if defaults or a rest parameter let it take a range of them.
+    fn arity_error(&self, n: usize) -> CheckErrors {
+        match self.accepted_argument_count() {
+            (min, Some(max)) if min == max => CheckErrors::IncorrectArgumentCount(max, n),
+            (min, max) => CheckErrors::IncorrectArgumentCountRange(min, max, n),
         }
     }
 
-    #[cfg(feature = "developer-mode")]
-    pub fn get_span(&self) -> Span {
-        self.body.span.clone()
+    /// Check `args` as `execute_apply` would before evaluating the body in
+    /// `epoch`: the number of arguments, and the type of each argument, after
+    /// defaults are filled in and rest arguments are collected. Arguments for
+    /// trait parameters are not checked, since whether a contract implements a
+    /// trait can only be checked against the database.
+    pub fn validate_args(&self, epoch: &StacksEpochId, args: &[Value]) -> Result<()> {
+        self.validate_arity(args.len())?;
+        let args = self.fill_default_args(Cow::Borrowed(args))?;
+        let args = self.collect_rest_args(args, epoch)?;
+        for (index, (type_sig, value)) in self.arg_types.iter().zip(args.iter()).enumerate() {
+            if is_trait_reference(type_sig) {
+                continue;
+            }
+            let value = clarity2_implicit_cast(type_sig, value.clone())?;
+            if !type_sig.admits(epoch, &value)? {
+                return Err(argument_type_error(index, type_sig, value));
+            }
+        }
+        Ok(())
     }
-}
 
-impl CallableType {
-    pub fn get_identifier(&self) -> FunctionIdentifier {
-        match self {
-            CallableType::UserFunction(f) => f.get_identifier(),
-            CallableType::NativeFunction(s, _, _) => FunctionIdentifier::new_native_function(s),
-            CallableType::SpecialFunction(s, _) => FunctionIdentifier::new_native_function(s),
-            CallableType::NativeFunction205(s, _, _, _) => {
-                FunctionIdentifier::new_native_function(s)
+    /// Check that this function can replace `old`, its previous version, in
+    /// `epoch` without breaking callers: it must have the same define type,
+    /// the same number of arguments, and each argument type must admit the
+    /// type of the corresponding argument of `old`.
+    pub fn is_compatible_upgrade(
+        &self,
+        epoch: &StacksEpochId,
+        old: &DefinedFunction,
+    ) -> std::result::Result<(), CheckErrors> {
+        if self.define_type != old.define_type {
+            return Err(CheckErrors::IncompatibleUpgradeDefineType(
+                old.define_type.to_string(),
+                self.define_type.to_string(),
+            ));
+        }
+        if self.arguments.len() != old.arguments.len() {
+            return Err(CheckErrors::IncorrectArgumentCount(
+                old.arguments.len(),
+                self.arguments.len(),
+            ));
+        }
+        for (index, (new_type, old_type)) in
+            self.arg_types.iter().zip(old.arg_types.iter()).enumerate()
+        {
+            if !new_type.admits_type(epoch, old_type)? {
+                return Err(CheckErrors::IncompatibleUpgradeArgument(
+                    index,
+                    new_type.clone(),
+                    old_type.clone(),
+                ));
             }
         }
+        Ok(())
     }
-}
 
-impl FunctionIdentifier {
-    fn new_native_function(name: &str) -> FunctionIdentifier {
-        let identifier = format!("_native_:{}", name);
-        FunctionIdentifier {
-            identifier: identifier,
+    /// The fewest and most arguments this function can be applied to. There is
+    /// no most if it has a rest parameter.
+    fn accepted_argument_count(&self) -> (usize, Option<usize>) {
+        let fixed_count = self.fixed_argument_count();
+        let defaulted = self
+            .extensions
+            .arg_defaults
+            .iter()
+            .take(fixed_count)
+            .filter(|default| default.is_some())
+            .count();
+        let max = if self.extensions.rest_argument {
+            None
+        } else {
+            Some(fixed_count)
+        };
+        (fixed_count - defaulted, max)
+    }
+
+    /// Mark this function's last argument as a rest parameter. Its declared type
+    /// must be a list type: any arguments beyond the fixed prefix are collected
+    /// into a list of that type. The rest parameter cannot have a default.
+    pub fn set_rest_argument(&mut self) -> Result<()> {
+        match self.arg_types.last() {
+            Some(TypeSignature::SequenceType(SequenceSubtype::ListType(_))) => {}
+            _ => return Err(CheckErrors::DefineFunctionBadSignature.into()),
+        }
+        if let Some(Some(_)) = self.extensions.arg_defaults.last() {
+            return Err(CheckErrors::DefineFunctionBadSignature.into());
         }
+        self.extensions.rest_argument = true;
+        Ok(())
     }
 
-    fn new_user_function(name: &str, context: &str) -> FunctionIdentifier {
-        let identifier = format!("{}:{}", context, name);
-        FunctionIdentifier {
-            identifier: identifier,
+    /// Record whether analysis has checked the arguments at every call site
+    /// of this function. Once set, `execute_apply` and `apply` bind the
+    /// arguments without checking them against their declared types. Trait
+    /// references are still checked, since the contract they name is only
+    /// known at runtime. The type checking costs are charged either way.
+    pub fn set_args_statically_checked(&mut self, checked: bool) {
+        self.extensions.args_statically_checked = checked;
+    }
+
+    pub fn args_statically_checked(&self) -> bool {
+        self.extensions.args_statically_checked
+    }
+
+    pub fn has_rest_argument(&self) -> bool {
+        self.extensions.rest_argument
+    }
+
+    /// Set default values for this function's arguments, one entry per argument.
+    /// Only trailing arguments may have defaults. Defaults are bound exactly like
+    /// caller-supplied values, so they are type-checked on application.
+    pub fn set_arg_defaults(&mut self, defaults: Vec<Option<Value>>) -> Result<()> {
+        if defaults.len() != self.arguments.len() {
+            return Err(CheckErrors::DefineFunctionBadSignature.into());
+        }
+        if self.extensions.rest_argument {
+            if let Some(Some(_)) = defaults.last() {
+                return Err(CheckErrors::DefineFunctionBadSignature.into());
+            }
         }
+        let first_default = defaults
+            .iter()
+            .position(Option::is_some)
+            .unwrap_or(defaults.len());
+        if defaults[first_default..].iter().any(Option::is_none) {
+            return Err(CheckErrors::DefineFunctionBadSignature.into());
+        }
+        self.extensions.arg_defaults = if first_default == defaults.len() {
+            vec![]
+        } else {
+            defaults
+        };
+        Ok(())
     }
-}
 
-// Implicitly cast principals to traits and traits to other traits as needed,
-// recursing into compound types. This function does not check for legality of
-// these casts, as that is done in the type-checker. Note: depth of recursion
-// should be capped by earlier checks on the types/values.
-fn clarity2_implicit_cast(type_sig: &TypeSignature, value: &Value) -> Result<Value> {
-    Ok(match (type_sig, value) {
-        (
-            TypeSignature::OptionalType(inner_type),
-            Value::Optional(OptionalData {
-                data: Some(inner_value),
-            }),
-        ) => Value::Optional(OptionalData {
-            data: Some(Box::new(clarity2_implicit_cast(inner_type, inner_value)?)),
-        }),
-        (
-            TypeSignature::ResponseType(inner_types),
-            Value::Response(ResponseData { committed, data }),
-        ) => Value::Response(ResponseData {
-            committed: *committed,
-            data: Box::new(clarity2_implicit_cast(
-                if *committed {
-                    &inner_types.0
-                } else {
-                    &inner_types.1
-                },
-                data,
-            )?),
-        }),
-        (
-            TypeSignature::SequenceType(SequenceSubtype::ListType(list_type)),
-            Value::Sequence(SequenceData::List(ListData {
-                data,
-                type_signature,
-            })),
-        ) => {
-            let mut values = Vec::with_capacity(data.len());
-            for elem in data {
-                values.push(clarity2_implicit_cast(
-                    list_type.get_list_item_type(),
-                    elem,
-                )?);
+    pub fn get_arg_defaults(&self) -> &[Option<Value>] {
+        &self.extensions.arg_defaults
+    }
+
+    pub fn check_trait_expectations(
+        &self,
+        epoch: &StacksEpochId,
+        contract_defining_trait: &ContractContext,
+        trait_identifier: &TraitIdentifier,
+    ) -> Result<()> {
+        let expected_sig =
+            self.expected_trait_signature(contract_defining_trait, trait_identifier)?;
+        self.check_trait_signature(epoch, &trait_identifier.name, &expected_sig)
+    }
+
+    /// Like `check_trait_expectations`, but charges `env` for the comparison:
+    /// an `InnerTypeCheckCost` for each argument, sized by both its declared
+    /// type and the type the trait expects. This is not charged during
+    /// dynamic dispatch, where it would change the cost of existing calls.
+    pub fn check_trait_expectations_metered(
+        &self,
+        env: &mut Environment,
+        contract_defining_trait: &ContractContext,
+        trait_identifier: &TraitIdentifier,
+    ) -> Result<()> {
+        let expected_sig =
+            self.expected_trait_signature(contract_defining_trait, trait_identifier)?;
+        for (arg_type, expected_type) in self.arg_types.iter().zip(expected_sig.args.iter()) {
+            let compared_size =
+                u64::from(arg_type.size()?).cost_overflow_add(u64::from(expected_type.size()?))?;
+            runtime_cost(ClarityCostFunction::InnerTypeCheckCost, env, compared_size)?;
+        }
+        let epoch = *env.epoch();
+        self.check_trait_signature(&epoch, &trait_identifier.name, &expected_sig)
+    }
+
+    /// The signature of the method named like this function in the trait
+    /// `trait_identifier`, which `contract_defining_trait` defines.
+    fn expected_trait_signature(
+        &self,
+        contract_defining_trait: &ContractContext,
+        trait_identifier: &TraitIdentifier,
+    ) -> Result<FunctionSignature> {
+        let trait_name = trait_identifier.name.to_string();
+        let mut constraining_trait = contract_defining_trait
+            .lookup_trait_definition(&trait_name)
+            .ok_or(CheckErrors::TraitReferenceUnknown(trait_name.to_string()))?;
+        Ok(constraining_trait
+            .remove(&self.name)
+            .ok_or(CheckErrors::TraitMethodUnknown(
+                trait_name,
+                self.name.to_string(),
+            ))?)
+    }
+
+    /// Check that this function's arguments comply with `expected_sig`, the
+    /// signature of the method of the same name in the trait `trait_name`.
+    pub(crate) fn check_trait_signature(
+        &self,
+        epoch: &StacksEpochId,
+        trait_name: &str,
+        expected_sig: &FunctionSignature,
+    ) -> Result<()> {
+        let args = self.arg_types.iter().map(|a| a.clone()).collect();
+        if !expected_sig.check_args_trait_compliance(epoch, args)? {
+            return Err(CheckErrors::BadTraitImplementation(
+                trait_name.to_string(),
+                self.name.to_string(),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.define_type == DefineType::ReadOnly
+    }
+
+    /// Check that this function's body never writes to chainstate, returning
+    /// `CheckErrors::WriteAttemptedInReadOnly` otherwise. This is the check the
+    /// `ReadOnlyChecker` analysis pass performs on each function, but it can be
+    /// run directly on a single `DefinedFunction`. The body may not apply a
+    /// writing native (such as `map-set` or `stx-transfer?`), a function of this
+    /// contract whose body writes, a non-read-only function of another contract,
+    /// or a trait-based `contract-call?`.
+    pub fn assert_read_only_safe(&self, env: &mut Environment) -> Result<()> {
+        let mut visited = HashSet::from([self.name.clone()]);
+        if Self::reads_only(&self.body, env, &mut visited)? {
+            Ok(())
+        } else {
+            Err(CheckErrors::WriteAttemptedInReadOnly.into())
+        }
+    }
+
+    fn reads_only(
+        expr: &SymbolicExpression,
+        env: &mut Environment,
+        visited: &mut HashSet<ClarityName>,
+    ) -> Result<bool> {
+        let expressions = match expr.expr {
+            SymbolicExpressionType::List(ref expressions) => expressions,
+            _ => return Ok(true),
+        };
+        match expressions.split_first() {
+            Some((function, args)) => match function.match_atom() {
+                Some(function_name) => {
+                    Self::application_reads_only(function_name, args, env, visited)
+                }
+                None => Self::all_read_only(expressions, env, visited),
+            },
+            None => Ok(true),
+        }
+    }
+
+    fn all_read_only(
+        expressions: &[SymbolicExpression],
+        env: &mut Environment,
+        visited: &mut HashSet<ClarityName>,
+    ) -> Result<bool> {
+        for expr in expressions.iter() {
+            if !Self::reads_only(expr, env, visited)? {
+                return Ok(false);
             }
-            let cast_list_type_data = ListTypeData::new_list(
-                list_type.get_list_item_type().clone(),
-                type_signature.get_max_len(),
-            )?;
-            Value::Sequence(SequenceData::List(ListData {
-                data: values,
-                type_signature: cast_list_type_data,
-            }))
         }
-        (
-            TypeSignature::TupleType(tuple_type),
-            Value::Tuple(TupleData {
-                type_signature: _,
-                data_map,
-            }),
-        ) => {
-            let mut cast_data_map = BTreeMap::new();
-            for (name, field_value) in data_map {
-                let to_type = match tuple_type.get_type_map().get(name) {
-                    Some(ty) => ty,
-                    None => {
-                        // This should be unreachable if the type-checker has already run successfully
-                        return Err(
-                            CheckErrors::TypeValueError(type_sig.clone(), value.clone()).into()
-                        );
+        Ok(true)
+    }
+
+    /// Check the values of `(name value)` pairs, as in `let` bindings and
+    /// tuples. The names are not evaluated.
+    fn bound_values_read_only(
+        pairs: &[SymbolicExpression],
+        env: &mut Environment,
+        visited: &mut HashSet<ClarityName>,
+    ) -> Result<bool> {
+        for pair in pairs.iter() {
+            if let Some([_, value]) = pair.match_list() {
+                if !Self::reads_only(value, env, visited)? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn application_reads_only(
+        function_name: &str,
+        args: &[SymbolicExpression],
+        env: &mut Environment,
+        visited: &mut HashSet<ClarityName>,
+    ) -> Result<bool> {
+        use crate::vm::functions::NativeFunctions::*;
+
+        let version = *env.contract_context.get_clarity_version();
+        let native = match NativeFunctions::lookup_by_name_at_version(function_name, &version) {
+            Some(native) => native,
+            None => {
+                let reads_only = match env.contract_context.lookup_function(function_name) {
+                    // a function already visited is checked where it was
+                    // first visited, which also stops at recursion
+                    Some(function) if !visited.insert(function.name.clone()) => true,
+                    Some(function) => Self::reads_only(&function.body, env, visited)?,
+                    // not a function, e.g. a type or a binding
+                    None => true,
+                };
+                return Ok(reads_only && Self::all_read_only(args, env, visited)?);
+            }
+        };
+
+        match native {
+            StxTransfer | StxTransferMemo | StxBurn | SetEntry | DeleteEntry | InsertEntry
+            | SetVar | MintAsset | MintToken | TransferAsset | TransferToken | BurnAsset
+            | BurnToken => Ok(false),
+            Let => match args.split_first() {
+                Some((bindings, body)) => {
+                    let bindings = bindings.match_list().unwrap_or(&[]);
+                    Ok(Self::bound_values_read_only(bindings, env, visited)?
+                        && Self::all_read_only(body, env, visited)?)
+                }
+                None => Ok(true),
+            },
+            TupleCons => Self::bound_values_read_only(args, env, visited),
+            // the first argument is a type
+            FromConsensusBuff => Self::all_read_only(args.get(1..).unwrap_or(&[]), env, visited),
+            // the first argument names the function being applied
+            Map | Filter | Fold => match args.split_first() {
+                Some((function, rest)) => match function.match_atom() {
+                    Some(function_name) => {
+                        Self::application_reads_only(function_name, rest, env, visited)
                     }
+                    None => Self::all_read_only(args, env, visited),
+                },
+                None => Ok(true),
+            },
+            ContractCall => {
+                let function_name = args.get(1).and_then(|name| name.match_atom());
+                let callee_reads_only = match (args.first().map(|c| &c.expr), function_name) {
+                    (
+                        Some(SymbolicExpressionType::LiteralValue(Value::Principal(
+                            PrincipalData::Contract(contract_identifier),
+                        ))),
+                        Some(function_name),
+                    ) => env
+                        .global_context
+                        .database
+                        .load_contract_analysis(contract_identifier)?
+                        .ok_or_else(|| {
+                            CheckErrors::NoSuchContract(contract_identifier.to_string())
+                        })?
+                        .get_read_only_function_type(function_name)
+                        .is_some(),
+                    // dynamic dispatch can't be known to be read-only
+                    _ => false,
                 };
-                cast_data_map.insert(name.clone(), clarity2_implicit_cast(to_type, field_value)?);
+                Ok(callee_reads_only
+                    && Self::all_read_only(args.get(2..).unwrap_or(&[]), env, visited)?)
             }
-            Value::Tuple(TupleData {
-                type_signature: tuple_type.clone(),
-                data_map: cast_data_map,
-            })
+            _ => Self::all_read_only(args, env, visited),
         }
-        (
-            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
-            Value::CallableContract(callable_data),
-        ) => Value::CallableContract(CallableData {
-            contract_identifier: callable_data.contract_identifier.clone(),
-            trait_identifier: Some(trait_identifier.clone()),
-        }),
-        // N.B. it seems like this should be illegal, since it is converting a
-        // principal to a callable trait, and only principal literals should be
-        // allowed to do that. The case that this is handling is when principal
-        // values are passed in from the initial contract-call, which by
-        // definition must be a literal. Other scenarios where a principal is
-        // passed will have been caught by the type checker. This could
-        // alternatively be checked with
-        // `FunctionType::check_args_by_allowing_trait_cast` before execution.
-        (
-            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
-            Value::Principal(PrincipalData::Contract(contract_identifier)),
-        ) => Value::CallableContract(CallableData {
-            contract_identifier: contract_identifier.clone(),
-            trait_identifier: Some(trait_identifier.clone()),
-        }),
-        _ => value.clone(),
-    })
-}
+    }
+
+    /// The contracts this function's body names in a `contract-call?`, in the
+    /// order they first appear. Calls through a trait reference are not
+    /// included, since their target is only known at run time; see
+    /// `references_dynamic_dispatch`.
+    pub fn referenced_contracts(&self) -> Vec<QualifiedContractIdentifier> {
+        let mut contracts = vec![];
+        Self::visit_contract_calls(&self.body, &mut |target| {
+            if let SymbolicExpressionType::LiteralValue(Value::Principal(
+                PrincipalData::Contract(ref contract_identifier),
+            )) = target.expr
+            {
+                if !contracts.contains(contract_identifier) {
+                    contracts.push(contract_identifier.clone());
+                }
+            }
+        });
+        contracts
+    }
+
+    /// Whether this function's body makes a `contract-call?` through a trait
+    /// reference, whose target cannot be resolved statically.
+    pub fn references_dynamic_dispatch(&self) -> bool {
+        let mut dynamic = false;
+        Self::visit_contract_calls(&self.body, &mut |target| {
+            if !matches!(
+                target.expr,
+                SymbolicExpressionType::LiteralValue(Value::Principal(PrincipalData::Contract(_)))
+            ) {
+                dynamic = true;
+            }
+        });
+        dynamic
+    }
+
+    /// Find whether this function can call itself, following the functions of
+    /// `contract_context` named in each body as in
+    /// `ContractContext::called_functions`. Analysis rejects such contracts,
+    /// but this can be applied to functions that were not analyzed.
+    pub fn recursion_kind(&self, contract_context: &ContractContext) -> RecursionKind {
+        let callees = contract_context.called_functions(self);
+        if callees.iter().any(|callee| callee.name == self.name) {
+            return RecursionKind::SelfDirect;
+        }
+
+        // breadth-first, so that the shortest cycle is found
+        let mut parents: HashMap<&ClarityName, &ClarityName> = HashMap::new();
+        let mut pending = VecDeque::new();
+        for callee in callees {
+            if !parents.contains_key(&callee.name) {
+                parents.insert(&callee.name, &self.name);
+                pending.push_back(callee);
+            }
+        }
+        while let Some(function) = pending.pop_front() {
+            for callee in contract_context.called_functions(function) {
+                if callee.name == self.name {
+                    let mut path = vec![function.name.clone()];
+                    let mut current = &function.name;
+                    while let Some(parent) = parents.get(current).copied() {
+                        if *parent == self.name {
+                            break;
+                        }
+                        path.push(parent.clone());
+                        current = parent;
+                    }
+                    path.push(self.name.clone());
+                    path.reverse();
+                    return RecursionKind::Mutual(path);
+                }
+                if !parents.contains_key(&callee.name) {
+                    parents.insert(&callee.name, &function.name);
+                    pending.push_back(callee);
+                }
+            }
+        }
+        RecursionKind::None
+    }
+
+    /// Whether this function is pure: a private function whose result only
+    /// depends on its arguments. Neither it nor any function of
+    /// `contract_context` it calls, as in `ContractContext::called_functions`,
+    /// may read or write persisted state (see `SpecialFnMeta`), print, or read
+    /// the transaction context, such as `tx-sender` or `block-height`.
+    pub fn is_pure(&self, contract_context: &ContractContext) -> bool {
+        if self.define_type != DefineType::Private {
+            return false;
+        }
+        let version = contract_context.get_clarity_version();
+        let mut visited = HashSet::new();
+        let mut pending = vec![self];
+        while let Some(function) = pending.pop() {
+            if !visited.insert(&function.name) {
+                continue;
+            }
+            if !Self::is_pure_expression(&function.body, version)
+                || !function
+                    .extensions
+                    .preconditions
+                    .iter()
+                    .all(|(condition, _)| Self::is_pure_expression(condition, version))
+            {
+                return false;
+            }
+            pending.extend(contract_context.called_functions(function));
+        }
+        true
+    }
+
+    fn is_pure_expression(expr: &SymbolicExpression, version: &ClarityVersion) -> bool {
+        use crate::vm::variables::NativeVariables::*;
+
+        if let Some(name) = expr.match_atom() {
+            if let Some(function) = NativeFunctions::lookup_by_name_at_version(name, version) {
+                let effects = function
+                    .special_fn_meta()
+                    .map_or(false, |meta| meta.reads_state || meta.writes_state);
+                return !effects && function != NativeFunctions::Print;
+            }
+            return !matches!(
+                NativeVariables::lookup_by_name_at_version(name, version),
+                Some(
+                    ContractCaller
+                        | TxSender
+                        | TxSponsor
+                        | BlockHeight
+                        | BurnBlockHeight
+                        | TotalLiquidMicroSTX
+                )
+            );
+        }
+        expr.match_list().map_or(true, |expressions| {
+            expressions
+                .iter()
+                .all(|expr| Self::is_pure_expression(expr, version))
+        })
+    }
+
+    /// Whether every path through this function's body returns an `err`
+    /// response, as in a stub that returns `(err u1)`. Only `err`, `if`,
+    /// `begin`, `let`, `match` and `try!` are followed: when any other form
+    /// decides the result, this conservatively returns false.
+    pub fn always_errors(&self) -> bool {
+        Self::always_errors_expression(&self.body)
+    }
+
+    fn always_errors_expression(expr: &SymbolicExpression) -> bool {
+        let (function, args) = match expr.match_list().and_then(|list| list.split_first()) {
+            Some((function, args)) => (function, args),
+            None => return false,
+        };
+        let function = match function
+            .match_atom()
+            .and_then(|name| NativeFunctions::lookup_by_name(name))
+        {
+            Some(function) => function,
+            None => return false,
+        };
+        match (function, args) {
+            (NativeFunctions::ConsError, [_]) => true,
+            (NativeFunctions::If, [_, then_branch, else_branch]) => {
+                Self::always_errors_expression(then_branch)
+                    && Self::always_errors_expression(else_branch)
+            }
+            (NativeFunctions::Begin, [.., last]) | (NativeFunctions::Let, [_, .., last]) => {
+                Self::always_errors_expression(last)
+            }
+            // `(match opt some-name some-branch none-branch)`
+            (NativeFunctions::Match, [_, _, some_branch, none_branch]) => {
+                Self::always_errors_expression(some_branch)
+                    && Self::always_errors_expression(none_branch)
+            }
+            // `(match res ok-name ok-branch err-name err-branch)`
+            (NativeFunctions::Match, [_, _, ok_branch, _, err_branch]) => {
+                Self::always_errors_expression(ok_branch)
+                    && Self::always_errors_expression(err_branch)
+            }
+            // an `err` passed to `try!` is returned from the function
+            (NativeFunctions::TryRet, [value]) => Self::always_errors_expression(value),
+            _ => false,
+        }
+    }
+
+    /// Call `visit` with the target expression of every `contract-call?` in `expr`.
+    fn visit_contract_calls<F>(expr: &SymbolicExpression, visit: &mut F)
+    where
+        F: FnMut(&SymbolicExpression),
+    {
+        let expressions = match expr.match_list() {
+            Some(expressions) => expressions,
+            None => return,
+        };
+        if let Some((function, args)) = expressions.split_first() {
+            let is_contract_call = function
+                .match_atom()
+                .and_then(|function_name| NativeFunctions::lookup_by_name(function_name))
+                == Some(NativeFunctions::ContractCall);
+            if is_contract_call {
+                if let Some(target) = args.first() {
+                    visit(target);
+                }
+            }
+        }
+        for expr in expressions.iter() {
+            Self::visit_contract_calls(expr, visit);
+        }
+    }
+
+    /// Apply this function to `args`. A private function is applied as in
+    /// `execute_apply_trusted`, with `args` moved into its context rather
+    /// than cloned.
+    pub fn apply(&self, args: Vec<Value>, env: &mut Environment) -> Result<Value> {
+        match self.define_type {
+            DefineType::Private => {
+                let check_types = !self.extensions.args_statically_checked
+                    && !env.global_context.is_function_verified(&self.identifier);
+                self.inner_execute_apply(
+                    Cow::Owned(args),
+                    env,
+                    check_types,
+                    &mut LocalContext::new(),
+                    false,
+                )
+            }
+            DefineType::Public | DefineType::ReadOnly => {
+                let sponsor = env.sponsor.clone();
+                self.apply_with_sponsor(&args, env, sponsor)
+            }
+        }
+    }
+
+    /// Invoke this public or read-only function as a transaction, with `sponsor`
+    /// as the transaction sponsor instead of the environment's current one.
+    /// Private functions cannot be invoked this way.
+    pub fn apply_with_sponsor(
+        &self,
+        args: &[Value],
+        env: &mut Environment,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<Value> {
+        if self.is_private() {
+            return Err(CheckErrors::NoSuchPublicFunction(
+                env.contract_context.contract_identifier.to_string(),
+                self.name.to_string(),
+            )
+            .into());
+        }
+
+        let mut sponsored_env = Environment::new(
+            env.global_context,
+            env.contract_context,
+            env.call_stack,
+            env.sender.clone(),
+            env.caller.clone(),
+            sponsor,
+        );
+        sponsored_env.execute_function_as_transaction(self, args, None)
+    }
+
+    pub fn is_public(&self) -> bool {
+        match self.define_type {
+            DefineType::Public => true,
+            DefineType::Private => false,
+            DefineType::ReadOnly => true,
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.define_type == DefineType::Private
+    }
+
+    /// The Clarity keyword for this function's define type, for logging.
+    pub fn define_type_name(&self) -> &'static str {
+        self.define_type.as_str()
+    }
+
+    pub fn get_identifier(&self) -> FunctionIdentifier {
+        self.identifier.clone()
+    }
+
+    pub fn get_name(&self) -> &ClarityName {
+        &self.name
+    }
+
+    pub fn get_arguments(&self) -> &[ClarityName] {
+        &self.arguments
+    }
+
+    pub fn get_arg_types(&self) -> &[TypeSignature] {
+        &self.arg_types
+    }
+
+    /// The name of each argument that is a trait reference, with the name of
+    /// the trait it expects, in argument order. Arguments of compound types
+    /// containing trait references, such as `(optional <trait>)`, are not
+    /// included.
+    pub fn trait_reference_args(&self) -> Vec<(ClarityName, ClarityName)> {
+        self.arguments
+            .iter()
+            .zip(self.arg_types.iter())
+            .filter_map(|(name, type_sig)| match type_sig {
+                TypeSignature::TraitReferenceType(trait_identifier)
+                | TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)) => {
+                    Some((name.clone(), trait_identifier.name.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get_define_type(&self) -> &DefineType {
+        &self.define_type
+    }
+
+    pub fn get_body(&self) -> &SymbolicExpression {
+        &self.body
+    }
+
+    /// Assemble the `FunctionType` of this function from its argument types and
+    /// the given return type.
+    pub fn to_function_type(&self, return_type: TypeSignature) -> FunctionType {
+        let args = self
+            .arguments
+            .iter()
+            .zip(self.arg_types.iter())
+            .map(|(name, arg_type)| FunctionArg::new(arg_type.clone(), name.clone()))
+            .collect();
+        FunctionType::Fixed(FixedFunction {
+            args,
+            returns: return_type,
+        })
+    }
+
+    /// Cache the return type inferred for this function, so that its
+    /// `FunctionType` can be recovered with `get_function_type`. From then
+    /// on, `execute_apply` fails with `CheckErrors::TypeValueError` if the
+    /// function returns a value that the type does not admit.
+    pub fn set_return_type(&mut self, return_type: TypeSignature) {
+        self.extensions.return_type = Some(return_type);
+    }
+
+    pub fn get_return_type(&self) -> Option<&TypeSignature> {
+        self.extensions.return_type.as_ref()
+    }
+
+    /// Record that this function implements a method of `trait_identifier`.
+    /// This is informational, e.g. for ABI generators, and does not change
+    /// how the function is applied.
+    pub fn set_implemented_trait(&mut self, trait_identifier: TraitIdentifier) {
+        self.extensions.implemented_trait = Some(trait_identifier);
+    }
+
+    pub fn implemented_trait(&self) -> Option<&TraitIdentifier> {
+        self.extensions.implemented_trait.as_ref()
+    }
+
+    /// Mark this function deprecated, with `note` explaining why or what to
+    /// use instead. It is still applied as usual, but while VM events are
+    /// enabled with `GlobalContext::enable_vm_events`, each application records
+    /// a `VmEvent::Deprecated`, which the host can collect with
+    /// `Environment::take_vm_events`.
+    pub fn set_deprecation(&mut self, note: String) {
+        self.extensions.deprecation = Some(note);
+    }
+
+    pub fn deprecation(&self) -> Option<&str> {
+        self.extensions.deprecation.as_deref()
+    }
+
+    /// Check each `(condition, thrown)` in order before evaluating the body,
+    /// with the arguments bound. If a condition is false, the function returns
+    /// `thrown` instead, as if the body began with `(asserts! condition
+    /// thrown)`. Of the static helpers that walk the body, only `is_pure`
+    /// considers the conditions.
+    pub fn set_preconditions(&mut self, preconditions: Vec<(SymbolicExpression, Value)>) {
+        self.extensions.preconditions = preconditions;
+    }
+
+    pub fn preconditions(&self) -> &[(SymbolicExpression, Value)] {
+        &self.extensions.preconditions
+    }
+
+    /// The `FunctionType` of this function, if its return type has been cached.
+    pub fn get_function_type(&self) -> Option<FunctionType> {
+        self.extensions
+            .return_type
+            .clone()
+            .map(|return_type| self.to_function_type(return_type))
+    }
+
+    /// A JSON description of this function's signature, for ABI tooling:
+    /// `{ "name", "access", "args": [{ "name", "type" }], "outputs": { "type" } }`,
+    /// with types in Clarity syntax. `outputs` is only present once the
+    /// return type has been set with `set_return_type`.
+    pub fn to_signature_json(&self) -> serde_json::Value {
+        let args: Vec<serde_json::Value> = self
+            .arguments
+            .iter()
+            .zip(self.arg_types.iter())
+            .map(|(name, arg_type)| {
+                json!({
+                    "name": name.as_str(),
+                    "type": arg_type.to_string(),
+                })
+            })
+            .collect();
+        let mut signature = json!({
+            "name": self.name.as_str(),
+            "access": self.define_type.as_str(),
+            "args": args,
+        });
+        if let Some(return_type) = &self.extensions.return_type {
+            signature["outputs"] = json!({ "type": return_type.to_string() });
+        }
+        signature
+    }
+
+    pub fn canonicalize_types(&mut self, epoch: &StacksEpochId) {
+        for i in 0..self.arguments.len() {
+            self.arg_types[i] = self.arg_types[i].canonicalize(epoch);
+        }
+    }
+
+    /// A SHA-256 hash of this function's name, define type, arguments, and
+    /// body, for telling whether a function's code has changed. The contract
+    /// the function is defined in is not included, so the same function
+    /// defined in two contracts hashes equally. Neither are the expression ids
+    /// and source locations of the body, nor any settings made by the host
+    /// after the function was defined.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut buf = vec![];
+        write_hashed_str(&mut buf, &self.name);
+        write_hashed_str(&mut buf, self.define_type.as_str());
+        write_hashed_len(&mut buf, self.arguments.len());
+        for (name, arg_type) in self.arguments.iter().zip(self.arg_types.iter()) {
+            write_hashed_str(&mut buf, name);
+            write_hashed_str(&mut buf, &arg_type.to_string());
+        }
+        write_hashed_expression(&mut buf, &self.body);
+        Sha256Sum::from_data(&buf).to_bytes()
+    }
+
+    #[cfg(feature = "developer-mode")]
+    pub fn get_span(&self) -> Span {
+        self.body.span.clone()
+    }
+}
+
+fn write_hashed_len(buf: &mut Vec<u8>, len: usize) {
+    buf.extend_from_slice(&(len as u64).to_be_bytes());
+}
+
+fn write_hashed_str(buf: &mut Vec<u8>, s: &str) {
+    write_hashed_len(buf, s.len());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Write `expr` for `DefinedFunction::content_hash`: each expression is a tag
+/// byte followed by its contents, so distinct expressions never write the same
+/// bytes.
+fn write_hashed_expression(buf: &mut Vec<u8>, expr: &SymbolicExpression) {
+    match &expr.expr {
+        SymbolicExpressionType::AtomValue(value) => {
+            buf.push(0);
+            write_hashed_str(buf, &value.to_string());
+        }
+        SymbolicExpressionType::Atom(name) => {
+            buf.push(1);
+            write_hashed_str(buf, name);
+        }
+        SymbolicExpressionType::List(expressions) => {
+            buf.push(2);
+            write_hashed_len(buf, expressions.len());
+            for expr in expressions.iter() {
+                write_hashed_expression(buf, expr);
+            }
+        }
+        SymbolicExpressionType::LiteralValue(value) => {
+            buf.push(3);
+            write_hashed_str(buf, &value.to_string());
+        }
+        SymbolicExpressionType::Field(trait_identifier) => {
+            buf.push(4);
+            write_hashed_str(buf, &trait_identifier.to_string());
+        }
+        SymbolicExpressionType::TraitReference(name, definition) => {
+            let trait_identifier = match definition {
+                TraitDefinition::Defined(trait_identifier) => {
+                    buf.push(5);
+                    trait_identifier
+                }
+                TraitDefinition::Imported(trait_identifier) => {
+                    buf.push(6);
+                    trait_identifier
+                }
+            };
+            write_hashed_str(buf, name);
+            write_hashed_str(buf, &trait_identifier.to_string());
+        }
+    }
+}
+
+/// Whether and how a function calls itself, as found by
+/// `DefinedFunction::recursion_kind`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecursionKind {
+    None,
+    /// The function's body names the function itself.
+    SelfDirect,
+    /// The function calls itself through other functions. These are the
+    /// functions along the shortest such cycle, starting with this one.
+    Mutual(Vec<ClarityName>),
+}
+
+/// An argument of a partial application: a value, or a hole to be filled
+/// later. See `PartialApplication::with_holes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Value(Value),
+    Hole,
+}
+
+/// The result of filling the holes of a `PartialApplication`: the function's
+/// result if none remain, and otherwise a new partial application.
+#[derive(Debug, Clone)]
+pub enum Application {
+    Complete(Value),
+    Partial(PartialApplication),
+}
+
+/// A `DefinedFunction` applied to some of its arguments, to be completed
+/// with the remaining ones later.
+#[derive(Debug, Clone)]
+pub struct PartialApplication {
+    function: Rc<DefinedFunction>,
+    /// The arguments supplied so far, in order, without the holes.
+    bound: Vec<Value>,
+    /// The positions of the holes among the arguments, in increasing order.
+    holes: Vec<usize>,
+}
+
+impl PartialApplication {
+    /// Bind `bound` as the first arguments of `function`. Fails as in
+    /// `DefinedFunction::validate_arity` if the function cannot take that
+    /// many arguments.
+    pub fn new(function: Rc<DefinedFunction>, bound: Vec<Value>) -> Result<PartialApplication> {
+        Self::with_holes(function, bound.into_iter().map(Arg::Value).collect())
+    }
+
+    /// Bind `args` as the first arguments of `function`, where each
+    /// `Arg::Hole` is left for a later `fill` or `complete`. Fails like `new`
+    /// if the function cannot take that many arguments. Argument types are
+    /// checked when the application is completed.
+    pub fn with_holes(function: Rc<DefinedFunction>, args: Vec<Arg>) -> Result<PartialApplication> {
+        if let (_, Some(max)) = function.accepted_argument_count() {
+            if args.len() > max {
+                return Err(function.arity_error(args.len()).into());
+            }
+        }
+        let mut bound = Vec::with_capacity(args.len());
+        let mut holes = vec![];
+        for (position, arg) in args.into_iter().enumerate() {
+            match arg {
+                Arg::Value(value) => bound.push(value),
+                Arg::Hole => holes.push(position),
+            }
+        }
+        Ok(PartialApplication {
+            function,
+            bound,
+            holes,
+        })
+    }
+
+    pub fn function(&self) -> &Rc<DefinedFunction> {
+        &self.function
+    }
+
+    /// The arguments supplied so far, in order, without the holes.
+    pub fn bound_args(&self) -> &[Value] {
+        &self.bound
+    }
+
+    /// The number of holes left to fill.
+    pub fn hole_count(&self) -> usize {
+        self.holes.len()
+    }
+
+    /// Fill the holes in order with `args`, appending any arguments beyond
+    /// the last hole. Holes in `args` stay holes. If no holes remain, the
+    /// function is applied as with `complete`.
+    pub fn fill(&self, args: Vec<Arg>, env: &mut Environment) -> Result<Application> {
+        let filled = Self::with_holes(self.function.clone(), self.merge(args))?;
+        if filled.holes.is_empty() {
+            filled.complete(&[], env).map(Application::Complete)
+        } else {
+            Ok(Application::Partial(filled))
+        }
+    }
+
+    /// Apply the function to the bound arguments, with the holes filled in
+    /// order from `rest` and the remainder of `rest` appended, with
+    /// `DefinedFunction::execute_apply`. Fails as in
+    /// `DefinedFunction::validate_arity` if together they are too few or too
+    /// many arguments for the function, or a hole is left unfilled.
+    pub fn complete(&self, rest: &[Value], env: &mut Environment) -> Result<Value> {
+        let count = self.bound.len() + rest.len();
+        let args = self
+            .merge(rest.iter().cloned().map(Arg::Value).collect())
+            .into_iter()
+            .map(|arg| match arg {
+                Arg::Value(value) => Some(value),
+                Arg::Hole => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| self.function.arity_error(count))?;
+        self.function.validate_arity(count)?;
+        self.function.execute_apply(&args, env)
+    }
+
+    /// The bound arguments, with the holes filled in order from `args`, and
+    /// the remaining `args` appended.
+    fn merge(&self, args: Vec<Arg>) -> Vec<Arg> {
+        let mut args = args.into_iter();
+        let mut bound = self.bound.iter();
+        let len = self.bound.len() + self.holes.len();
+        let mut merged: Vec<Arg> = (0..len)
+            .map(|position| {
+                if self.holes.binary_search(&position).is_ok() {
+                    args.next().unwrap_or(Arg::Hole)
+                } else {
+                    // there is a bound argument for every position but the holes
+                    bound.next().cloned().map_or(Arg::Hole, Arg::Value)
+                }
+            })
+            .collect();
+        merged.extend(args);
+        merged
+    }
+}
+
+impl CallableType {
+    pub fn get_identifier(&self) -> FunctionIdentifier {
+        match self {
+            CallableType::UserFunction(f) => f.get_identifier(),
+            CallableType::NativeFunction(s, _, _) => FunctionIdentifier::new_native_function(s),
+            CallableType::SpecialFunction(s, _) => FunctionIdentifier::new_native_function(s),
+            CallableType::NativeFunction205(s, _, _, _) => {
+                FunctionIdentifier::new_native_function(s)
+            }
+            CallableType::HostFunction(f) => FunctionIdentifier::new_host_function(&f.name),
+        }
+    }
+
+    pub fn as_user_function(&self) -> Option<&DefinedFunction> {
+        match self {
+            CallableType::UserFunction(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a native or special function. User functions and host
+    /// functions are not.
+    pub fn is_native(&self) -> bool {
+        matches!(
+            self,
+            CallableType::NativeFunction(..)
+                | CallableType::NativeFunction205(..)
+                | CallableType::SpecialFunction(..)
+        )
+    }
+
+    /// The number of arguments this callable expects, if fixed. Special
+    /// functions, variadic natives and user functions with a rest parameter
+    /// return `None`.
+    pub fn get_arity(&self) -> Option<usize> {
+        match self {
+            CallableType::UserFunction(f) if f.extensions.rest_argument => None,
+            CallableType::UserFunction(f) => Some(f.arguments.len()),
+            CallableType::NativeFunction(_, handle, _) => handle.get_arity(),
+            CallableType::NativeFunction205(_, handle, _, _) => handle.get_arity(),
+            CallableType::SpecialFunction(_, _) | CallableType::HostFunction(_) => None,
+        }
+    }
+}
+
+impl FunctionIdentifier {
+    fn new_native_function(name: &'static str) -> FunctionIdentifier {
+        if let Ok(cache) = NATIVE_FUNCTION_IDENTIFIERS.read() {
+            if let Some(identifier) = cache.get(name) {
+                return FunctionIdentifier {
+                    identifier: identifier.clone(),
+                };
+            }
+        }
+
+        let identifier = format!("{}{}", NATIVE_PREFIX, name);
+        match NATIVE_FUNCTION_IDENTIFIERS.write() {
+            Ok(mut cache) => {
+                let identifier = cache
+                    .entry(name)
+                    .or_insert_with(|| Arc::from(identifier))
+                    .clone();
+                FunctionIdentifier { identifier }
+            }
+            // a poisoned cache only costs us the allocation
+            Err(_) => FunctionIdentifier {
+                identifier: Arc::from(identifier),
+            },
+        }
+    }
+
+    /// Host functions are identified like natives.
+    fn new_host_function(name: &str) -> FunctionIdentifier {
+        FunctionIdentifier {
+            identifier: intern_identifier(&format!("{}{}", NATIVE_PREFIX, name)),
+        }
+    }
+
+    fn new_user_function(name: &str, context: &str) -> FunctionIdentifier {
+        let identifier = format!("{}:{}", context, name);
+        FunctionIdentifier {
+            identifier: intern_identifier(&identifier),
+        }
+    }
+
+    /// Whether this identifies a native function or a user function.
+    ///
+    /// This is read from the identifier's `_native_:` prefix. User function
+    /// contexts are contract identifiers, which cannot be `_native_`, but a
+    /// function constructed directly with that context reads as native.
+    pub fn kind(&self) -> FunctionKind {
+        if self.identifier.starts_with(NATIVE_PREFIX) {
+            FunctionKind::Native
+        } else {
+            FunctionKind::User
+        }
+    }
+
+    /// Split a user function's identifier into its context and function name.
+    /// Returns `None` for native and special functions.
+    pub fn parse_user(&self) -> Option<(&str, &str)> {
+        match self.kind() {
+            FunctionKind::Native => None,
+            FunctionKind::User => self.identifier.split_once(':'),
+        }
+    }
+}
+
+/// Bind `values` to the parameters `arguments` of types `arg_types` in
+/// `context`, as a user function's arguments are bound when it is applied.
+/// Contract references passed for trait parameters are bound as callable
+/// contracts. Values are only checked against their types if `check_types`
+/// is set, except for trait parameters. See `LocalContext::extend_with_args`.
+pub(crate) fn bind_arguments(
+    context: &mut LocalContext,
+    arguments: &[ClarityName],
+    arg_types: &[TypeSignature],
+    mut values: Vec<Cow<Value>>,
+    env: &mut Environment,
+    check_types: bool,
+) -> Result<()> {
+    if arguments.len() != arg_types.len() {
+        return Err(InterpreterError::Expect(format!(
+            "{} arguments but {} argument types",
+            arguments.len(),
+            arg_types.len()
+        ))
+        .into());
+    }
+    if values.len() != arguments.len() {
+        return Err(CheckErrors::IncorrectArgumentCount(arguments.len(), values.len()).into());
+    }
+    let options = env.global_context.execution_options();
+    if let Some(limit) = options.arg_size_limit {
+        for value in values.iter() {
+            check_argument_size(&limit, value)?;
+        }
+    }
+
+    // Clarity 1 trait references are bound without checking for an existing
+    // binding, so check every name against the base context up front.
+    let strict_shadowing = options.strict_shadowing;
+    let coerce_integer_args = options.coerce_integer_args;
+    let wrap_args = options.wrap_args;
+    for name in arguments.iter() {
+        let shadows = strict_shadowing
+            && (context.lookup_variable(name).is_some()
+                || context.lookup_callable_contract(name).is_some());
+        if shadows
+            || context.variables.contains_key(name)
+            || context.callable_contracts.contains_key(name)
+        {
+            return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
+        }
+    }
+
+    if coerce_integer_args {
+        for (index, (type_sig, value)) in arg_types.iter().zip(values.iter_mut()).enumerate() {
+            if let Some(coerced) = coerce_integer_arg(index, type_sig, value)? {
+                *value = Cow::Owned(coerced);
+            }
+        }
+    }
+    if wrap_args {
+        for (type_sig, value) in arg_types.iter().zip(values.iter_mut()) {
+            if let Some(wrapped) = wrap_arg(env.epoch(), type_sig, value)? {
+                *value = Cow::Owned(wrapped);
+            }
+        }
+    }
+    let arg_iterator = arguments.iter().zip(arg_types.iter()).zip(values);
+
+    for (index, ((name, type_sig), value)) in arg_iterator.enumerate() {
+        // Clarity 1 behavior
+        if *env.contract_context.get_clarity_version() < ClarityVersion::Clarity2 {
+            match (type_sig, value.as_ref()) {
+                // Epoch < 2.1 uses TraitReferenceType
+                (
+                    TypeSignature::TraitReferenceType(trait_identifier),
+                    Value::Principal(PrincipalData::Contract(callee_contract_id)),
+                ) if *env.epoch() < StacksEpochId::Epoch21 => {
+                    // Argument is a trait reference, probably leading to a dynamic contract call
+                    // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
+                    // The code fetching and checking the trait is implemented in the contract_call eval function.
+                    check_dynamic_dispatch_allowed(env)?;
+                    check_trait_argument(env, callee_contract_id, Some(trait_identifier))?;
+                    context.callable_contracts.insert(
+                        name.clone(),
+                        CallableData {
+                            contract_identifier: callee_contract_id.clone(),
+                            trait_identifier: Some(trait_identifier.clone()),
+                        },
+                    );
+                }
+                // Epoch >= 2.1 uses CallableType
+                (
+                    TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+                    Value::Principal(PrincipalData::Contract(callee_contract_id)),
+                ) if *env.epoch() >= StacksEpochId::Epoch21 => {
+                    // Argument is a trait reference, probably leading to a dynamic contract call
+                    // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
+                    // The code fetching and checking the trait is implemented in the contract_call eval function.
+                    check_dynamic_dispatch_allowed(env)?;
+                    check_trait_argument(env, callee_contract_id, Some(trait_identifier))?;
+                    context.callable_contracts.insert(
+                        name.clone(),
+                        CallableData {
+                            contract_identifier: callee_contract_id.clone(),
+                            trait_identifier: Some(trait_identifier.clone()),
+                        },
+                    );
+                }
+                // Since this Clarity 1 contract may be called from a Clarity 2 contract,
+                // we need to handle Clarity 2 values as well. Clarity 2 contracts can only
+                // be executed in epoch 2.1, so we only need to handle `CallableType` here.
+                (
+                    TypeSignature::CallableType(CallableSubtype::Trait(_)),
+                    Value::CallableContract(CallableData {
+                        contract_identifier,
+                        trait_identifier,
+                    }),
+                ) => {
+                    check_dynamic_dispatch_allowed(env)?;
+                    check_trait_argument(env, contract_identifier, trait_identifier.as_ref())?;
+                    context.callable_contracts.insert(
+                        name.clone(),
+                        CallableData {
+                            contract_identifier: contract_identifier.clone(),
+                            trait_identifier: trait_identifier.clone(),
+                        },
+                    );
+                }
+                // A standard principal can never implement a trait
+                (
+                    TypeSignature::TraitReferenceType(trait_identifier)
+                    | TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+                    Value::Principal(PrincipalData::Standard(_)),
+                ) => {
+                    return Err(CheckErrors::ExpectedCallableContract(
+                        trait_identifier.clone(),
+                        value.into_owned(),
+                    )
+                    .into());
+                }
+                _ => {
+                    if (check_types || is_trait_reference(type_sig))
+                        && !type_sig.admits(env.epoch(), &value)?
+                    {
+                        return Err(argument_type_error(index, type_sig, value.into_owned()));
+                    }
+                    if let Some(_) = context.variables.insert(name.clone(), value.into_owned()) {
+                        return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
+                    }
+                }
+            }
+        } else {
+            // Clarity 2+ behavior
+            // Arguments containing principal literals can be implicitly cast to traits
+            // to match parameter types.
+            // e.g. `(some .foo)` to `(optional <trait>`)
+            // and traits can be implicitly cast to sub-traits
+            // e.g. `<foo-and-bar>` to `<foo>`
+            let cast_value = clarity2_implicit_cast(type_sig, value.into_owned())?;
+
+            match (&type_sig, &cast_value) {
+                (
+                    TypeSignature::CallableType(CallableSubtype::Trait(_)),
+                    Value::CallableContract(CallableData {
+                        contract_identifier,
+                        trait_identifier,
+                    }),
+                ) => {
+                    // Argument is a trait reference, probably leading to a dynamic contract call.
+                    // We keep a reference of the mapping (var-name: (callee_contract_id, trait_id)) in the context.
+                    // The trait compatibility has been checked by the type-checker.
+                    check_dynamic_dispatch_allowed(env)?;
+                    check_trait_argument(env, contract_identifier, trait_identifier.as_ref())?;
+                    context.callable_contracts.insert(
+                        name.clone(),
+                        CallableData {
+                            contract_identifier: contract_identifier.clone(),
+                            trait_identifier: trait_identifier.clone(),
+                        },
+                    );
+                }
+                (
+                    TypeSignature::TraitReferenceType(trait_identifier)
+                    | TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+                    Value::Principal(PrincipalData::Standard(_)),
+                ) => {
+                    return Err(CheckErrors::ExpectedCallableContract(
+                        trait_identifier.clone(),
+                        cast_value,
+                    )
+                    .into());
+                }
+                _ => {
+                    if (check_types || is_trait_reference(type_sig))
+                        && !type_sig.admits(env.epoch(), &cast_value)?
+                    {
+                        return Err(argument_type_error(index, type_sig, cast_value));
+                    }
+                }
+            }
+
+            if let Some(_) = context.variables.insert(name.clone(), cast_value) {
+                return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Convert an `int` argument supplied for a `uint` parameter, or the reverse,
+/// if the value is representable in the parameter's type. Returns `None` for
+/// any other argument, and `TypeValueErrorAt` if the value is out of range.
+/// Only top-level integers are converted, not those in lists, tuples, etc.
+fn coerce_integer_arg(
+    index: usize,
+    type_sig: &TypeSignature,
+    value: &Value,
+) -> Result<Option<Value>> {
+    let coerced = match (type_sig, value) {
+        (TypeSignature::IntType, Value::UInt(x)) => i128::try_from(*x).ok().map(Value::Int),
+        (TypeSignature::UIntType, Value::Int(x)) => u128::try_from(*x).ok().map(Value::UInt),
+        _ => return Ok(None),
+    };
+    match coerced {
+        Some(coerced) => Ok(Some(coerced)),
+        None => Err(CheckErrors::TypeValueErrorAt(index, type_sig.clone(), value.clone()).into()),
+    }
+}
+
+/// Check that `value` is within `limit`, walking it only as far as needed to
+/// find that it is not.
+fn check_argument_size(limit: &ArgumentSizeLimit, value: &Value) -> Result<()> {
+    let mut nodes = 0usize;
+    let mut pending = vec![(value, 1usize)];
+    while let Some((value, depth)) = pending.pop() {
+        nodes += 1;
+        if depth > limit.max_depth || nodes > limit.max_nodes {
+            return Err(CheckErrors::ValueTooLarge.into());
+        }
+        match value {
+            Value::Sequence(SequenceData::List(ListData { data, .. })) => {
+                pending.extend(data.iter().map(|item| (item, depth + 1)));
+            }
+            Value::Tuple(TupleData { data_map, .. }) => {
+                pending.extend(data_map.values().map(|item| (item, depth + 1)));
+            }
+            Value::Optional(OptionalData { data: Some(inner) })
+            | Value::Response(ResponseData { data: inner, .. }) => {
+                pending.push((inner.as_ref(), depth + 1));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Wrap `value` in `some` for an `optional` parameter, or in `ok` for a
+/// `response` parameter if only the `ok` type admits it. Returns `None` if the
+/// parameter's type already admits `value`, or wrapping would not help.
+fn wrap_arg(
+    epoch: &StacksEpochId,
+    type_sig: &TypeSignature,
+    value: &Value,
+) -> Result<Option<Value>> {
+    if type_sig.admits(epoch, value)? {
+        return Ok(None);
+    }
+    match type_sig {
+        TypeSignature::OptionalType(inner_type) if inner_type.admits(epoch, value)? => {
+            Ok(Some(Value::some(value.clone())?))
+        }
+        TypeSignature::ResponseType(inner_types) => {
+            let (ok_type, err_type) = inner_types.as_ref();
+            if ok_type.admits(epoch, value)? && !err_type.admits(epoch, value)? {
+                Ok(Some(Value::okay(value.clone())?))
+            } else {
+                Ok(None)
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// With `ExecutionOptions::eager_trait_checks`, check that `contract`,
+/// passed for a parameter of the trait `trait_identifier`, implements it.
+/// The result is shared with `contract-call?` through the transaction's
+/// trait conformance cache.
+fn check_trait_argument(
+    env: &mut Environment,
+    contract: &QualifiedContractIdentifier,
+    trait_identifier: Option<&TraitIdentifier>,
+) -> Result<()> {
+    let trait_identifier = match trait_identifier {
+        Some(trait_identifier) if env.global_context.execution_options().eager_trait_checks => {
+            trait_identifier
+        }
+        _ => return Ok(()),
+    };
+    if env
+        .global_context
+        .trait_conformance(contract, trait_identifier)
+        == Some(true)
+    {
+        return Ok(());
+    }
+
+    let implementing = env
+        .global_context
+        .database
+        .get_contract(contract)
+        .map_err(|_e| CheckErrors::NoSuchContract(contract.to_string()))?
+        .contract_context;
+    if implementing.is_explicitly_implementing_trait(trait_identifier) {
+        return Ok(());
+    }
+    let trait_name = trait_identifier.name.to_string();
+    let trait_def = env
+        .global_context
+        .database
+        .get_contract(&trait_identifier.contract_identifier)
+        .map_err(|_e| {
+            CheckErrors::NoSuchContract(trait_identifier.contract_identifier.to_string())
+        })?
+        .contract_context
+        .lookup_trait_definition(&trait_name)
+        .ok_or(CheckErrors::TraitReferenceUnknown(trait_name))?;
+
+    let result = implementing.implements_trait(env.epoch(), trait_identifier, &trait_def);
+    env.global_context.record_trait_conformance(
+        contract.clone(),
+        trait_identifier.clone(),
+        result.is_ok(),
+    );
+    result
+}
+
+fn check_dynamic_dispatch_allowed(env: &Environment) -> Result<()> {
+    if env
+        .global_context
+        .execution_options()
+        .allow_dynamic_dispatch
+    {
+        Ok(())
+    } else {
+        Err(CheckErrors::DynamicDispatchDisabled.into())
+    }
+}
+
+/// Whether a value bound to an argument of this type must always be checked,
+/// even when the function's arguments are otherwise trusted.
+fn is_trait_reference(type_sig: &TypeSignature) -> bool {
+    matches!(
+        type_sig,
+        TypeSignature::TraitReferenceType(_)
+            | TypeSignature::CallableType(CallableSubtype::Trait(_))
+    )
+}
+
+/// The error for argument `index` not being admitted by its type: a contract
+/// reference given for a trait parameter is `TraitArgumentMismatchAt`, so that
+/// it can be told apart from a value of the wrong type, `TypeValueErrorAt`.
+///
+/// Only `CallableType` parameters report `TraitArgumentMismatchAt`. A Clarity 1
+/// parameter stored as a `TraitReferenceType` reports `TypeValueErrorAt`, as it
+/// always has: in epoch 2.2 such parameters reject contract references, and
+/// the error is part of those transactions' receipts.
+fn argument_type_error(index: usize, type_sig: &TypeSignature, value: Value) -> Error {
+    match (type_sig, &value) {
+        (
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+            Value::CallableContract(_) | Value::Principal(PrincipalData::Contract(_)),
+        ) => CheckErrors::TraitArgumentMismatchAt(index, trait_identifier.clone(), value).into(),
+        _ => CheckErrors::TypeValueErrorAt(index, type_sig.clone(), value).into(),
+    }
+}
+
+// Implicitly cast principals to traits and traits to other traits as needed,
+// recursing into compound types. This function does not check for legality of
+// these casts, as that is done in the type-checker. Note: depth of recursion
+// should be capped by earlier checks on the types/values.
+fn clarity2_implicit_cast(type_sig: &TypeSignature, value: Value) -> Result<Value> {
+    Ok(match (type_sig, value) {
+        (
+            TypeSignature::OptionalType(inner_type),
+            Value::Optional(OptionalData {
+                data: Some(inner_value),
+            }),
+        ) => Value::Optional(OptionalData {
+            data: Some(Box::new(clarity2_implicit_cast(inner_type, *inner_value)?)),
+        }),
+        (
+            TypeSignature::ResponseType(inner_types),
+            Value::Response(ResponseData { committed, data }),
+        ) => Value::Response(ResponseData {
+            committed,
+            data: Box::new(clarity2_implicit_cast(
+                if committed {
+                    &inner_types.0
+                } else {
+                    &inner_types.1
+                },
+                *data,
+            )?),
+        }),
+        (
+            TypeSignature::SequenceType(SequenceSubtype::ListType(list_type)),
+            Value::Sequence(SequenceData::List(ListData {
+                data,
+                type_signature,
+            })),
+        ) => {
+            let mut values = Vec::with_capacity(data.len());
+            for elem in data {
+                values.push(clarity2_implicit_cast(
+                    list_type.get_list_item_type(),
+                    elem,
+                )?);
+            }
+            let cast_list_type_data = ListTypeData::new_list(
+                list_type.get_list_item_type().clone(),
+                type_signature.get_max_len(),
+            )?;
+            Value::Sequence(SequenceData::List(ListData {
+                data: values,
+                type_signature: cast_list_type_data,
+            }))
+        }
+        (TypeSignature::TupleType(tuple_type), Value::Tuple(tuple_data)) => {
+            let type_map = tuple_type.get_type_map();
+            if tuple_data
+                .data_map
+                .keys()
+                .any(|name| !type_map.contains_key(name))
+            {
+                // This should be unreachable if the type-checker has already run successfully
+                return Err(CheckErrors::TypeValueError(
+                    type_sig.clone(),
+                    Value::Tuple(tuple_data),
+                )
+                .into());
+            }
+            let mut cast_data_map = BTreeMap::new();
+            for (name, field_value) in tuple_data.data_map {
+                let to_type = type_map.get(&name).ok_or_else(|| {
+                    InterpreterError::Expect("Tuple field missing from its type".into())
+                })?;
+                let cast_field = clarity2_implicit_cast(to_type, field_value)?;
+                cast_data_map.insert(name, cast_field);
+            }
+            Value::Tuple(TupleData {
+                type_signature: tuple_type.clone(),
+                data_map: cast_data_map,
+            })
+        }
+        (
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+            Value::CallableContract(callable_data),
+        ) => Value::CallableContract(CallableData {
+            contract_identifier: callable_data.contract_identifier,
+            trait_identifier: Some(trait_identifier.clone()),
+        }),
+        // N.B. it seems like this should be illegal, since it is converting a
+        // principal to a callable trait, and only principal literals should be
+        // allowed to do that. The case that this is handling is when principal
+        // values are passed in from the initial contract-call, which by
+        // definition must be a literal. Other scenarios where a principal is
+        // passed will have been caught by the type checker. This could
+        // alternatively be checked with
+        // `FunctionType::check_args_by_allowing_trait_cast` before execution.
+        (
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+            Value::Principal(PrincipalData::Contract(contract_identifier)),
+        ) => Value::CallableContract(CallableData {
+            contract_identifier,
+            trait_identifier: Some(trait_identifier.clone()),
+        }),
+        (_, value) => value,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use stacks_common::consts::CHAIN_ID_TESTNET;
+
+    use super::*;
+    use crate::vm::contexts::{ExecutionOptions, GlobalContext, VmEvent};
+    use crate::vm::costs::LimitedCostTracker;
+    use crate::vm::database::MemoryBackingStore;
+    use crate::vm::errors::ShortReturnType;
+    use crate::vm::types::StandardPrincipalData;
+    use crate::vm::CallStack;
+
+    fn with_test_env<F>(contract_context: &ContractContext, epoch: StacksEpochId, f: F)
+    where
+        F: FnOnce(&mut Environment),
+    {
+        let mut marf = MemoryBackingStore::new();
+        let mut global_context = GlobalContext::new(
+            false,
+            CHAIN_ID_TESTNET,
+            marf.as_clarity_db(),
+            LimitedCostTracker::new_free(),
+            epoch,
+        );
+        let mut call_stack = CallStack::new();
+        let mut env = Environment::new(
+            &mut global_context,
+            contract_context,
+            &mut call_stack,
+            None,
+            None,
+            None,
+        );
+        f(&mut env)
+    }
+
+    /// Parse `source`, a single Clarity 2 expression.
+    fn parse(source: &str) -> SymbolicExpression {
+        crate::vm::ast::parse(
+            &QualifiedContractIdentifier::transient(),
+            source,
+            ClarityVersion::Clarity2,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap()
+        .remove(0)
+    }
+
+    /// The function `name` of the contract `testing`, with the body `source`.
+    fn parse_named_function(
+        name: &str,
+        args: Vec<(ClarityName, TypeSignature)>,
+        define_type: DefineType,
+        source: &str,
+    ) -> DefinedFunction {
+        DefinedFunction::new(args, parse(source), define_type, &name.into(), "testing")
+    }
+
+    /// As `parse_named_function`, for a function named `foo`.
+    fn parse_function(
+        args: Vec<(ClarityName, TypeSignature)>,
+        define_type: DefineType,
+        source: &str,
+    ) -> DefinedFunction {
+        parse_named_function("foo", args, define_type, source)
+    }
+
+    #[test]
+    fn test_implicit_cast() {
+        // principal -> <trait>
+        let trait_identifier = TraitIdentifier::parse_fully_qualified(
+            "SP2PABAF9FTAJYNFZH93XENAJ8FVY99RRM50D2JG9.nft-trait.nft-trait",
+        )
+        .unwrap();
+        let trait_ty =
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier.clone()));
+        let contract_identifier = QualifiedContractIdentifier::parse(
+            "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR.contract",
+        )
+        .unwrap();
+        let contract_identifier2 = QualifiedContractIdentifier::parse(
+            "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR.contract2",
+        )
+        .unwrap();
+        let contract = Value::CallableContract(CallableData {
+            contract_identifier: contract_identifier.clone(),
+            trait_identifier: None,
+        });
+        let contract2 = Value::CallableContract(CallableData {
+            contract_identifier: contract_identifier2.clone(),
+            trait_identifier: None,
+        });
+        let cast_contract = clarity2_implicit_cast(&trait_ty, contract.clone()).unwrap();
+        let cast_trait = cast_contract.expect_callable().unwrap();
+        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
+        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+
+        // (optional principal) -> (optional <trait>)
+        let optional_ty = TypeSignature::new_option(trait_ty.clone()).unwrap();
+        let optional_contract = Value::some(contract.clone()).unwrap();
+        let cast_optional =
+            clarity2_implicit_cast(&optional_ty, optional_contract.clone()).unwrap();
+        match &cast_optional.expect_optional().unwrap().unwrap() {
+            Value::CallableContract(CallableData {
+                contract_identifier: contract_id,
+                trait_identifier: trait_id,
+            }) => {
+                assert_eq!(contract_id, &contract_identifier);
+                assert_eq!(trait_id.as_ref().unwrap(), &trait_identifier);
+            }
+            other => panic!("expected Value::CallableContract, got {:?}", other),
+        }
+
+        // (ok principal) -> (ok <trait>)
+        let response_ok_ty =
+            TypeSignature::new_response(trait_ty.clone(), TypeSignature::UIntType).unwrap();
+        let response_contract = Value::okay(contract.clone()).unwrap();
+        let cast_response =
+            clarity2_implicit_cast(&response_ok_ty, response_contract.clone()).unwrap();
+        let cast_trait = cast_response
+            .expect_result_ok()
+            .unwrap()
+            .expect_callable()
+            .unwrap();
+        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
+        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+
+        // (err principal) -> (err <trait>)
+        let response_err_ty =
+            TypeSignature::new_response(TypeSignature::UIntType, trait_ty.clone()).unwrap();
+        let response_contract = Value::error(contract.clone()).unwrap();
+        let cast_response =
+            clarity2_implicit_cast(&response_err_ty, response_contract.clone()).unwrap();
+        let cast_trait = cast_response
+            .expect_result_err()
+            .unwrap()
+            .expect_callable()
+            .unwrap();
+        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
+        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+
+        // (list principal) -> (list <trait>)
+        let list_ty = TypeSignature::list_of(trait_ty.clone(), 4).unwrap();
+        let list_contract = Value::list_from(vec![contract.clone(), contract2.clone()]).unwrap();
+        let cast_list = clarity2_implicit_cast(&list_ty, list_contract.clone()).unwrap();
+        let items = cast_list.expect_list().unwrap();
+        for item in items {
+            let cast_trait = item.expect_callable().unwrap();
+            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+        }
+
+        // {a: principal} -> {a: <trait>}
+        let a_name = ClarityName::from("a");
+        let tuple_ty = TypeSignature::TupleType(
+            TupleTypeSignature::try_from(vec![(a_name.clone(), trait_ty.clone())]).unwrap(),
+        );
+        let contract_tuple_ty = TypeSignature::TupleType(
+            TupleTypeSignature::try_from(vec![(a_name.clone(), TypeSignature::PrincipalType)])
+                .unwrap(),
+        );
+        let mut data_map = BTreeMap::new();
+        data_map.insert(a_name.clone(), contract.clone());
+        let tuple_contract = Value::Tuple(TupleData {
+            type_signature: TupleTypeSignature::try_from(vec![(
+                a_name.clone(),
+                TypeSignature::PrincipalType,
+            )])
+            .unwrap(),
+            data_map,
+        });
+        let cast_tuple = clarity2_implicit_cast(&tuple_ty, tuple_contract.clone()).unwrap();
+        let cast_trait = cast_tuple
+            .expect_tuple()
+            .unwrap()
+            .get(&a_name)
+            .unwrap()
+            .clone()
+            .expect_callable()
+            .unwrap();
+        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
+        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+
+        // (list (optional principal)) -> (list (optional <trait>))
+        let list_opt_ty = TypeSignature::list_of(optional_ty.clone(), 4).unwrap();
+        let list_opt_contract = Value::list_from(vec![
+            Value::some(contract.clone()).unwrap(),
+            Value::some(contract2.clone()).unwrap(),
+            Value::none(),
+        ])
+        .unwrap();
+        let cast_list = clarity2_implicit_cast(&list_opt_ty, list_opt_contract.clone()).unwrap();
+        let items = cast_list.expect_list().unwrap();
+        for item in items {
+            match item.expect_optional().unwrap() {
+                Some(cast_opt) => {
+                    let cast_trait = cast_opt.expect_callable().unwrap();
+                    assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+                }
+                None => (),
+            }
+        }
+
+        // (list (response principal uint)) -> (list (response <trait> uint))
+        let list_res_ty = TypeSignature::list_of(response_ok_ty.clone(), 4).unwrap();
+        let list_res_contract = Value::list_from(vec![
+            Value::okay(contract.clone()).unwrap(),
+            Value::okay(contract2.clone()).unwrap(),
+            Value::okay(contract2.clone()).unwrap(),
+        ])
+        .unwrap();
+        let cast_list = clarity2_implicit_cast(&list_res_ty, list_res_contract.clone()).unwrap();
+        let items = cast_list.expect_list().unwrap();
+        for item in items {
+            let cast_trait = item.expect_result_ok().unwrap().expect_callable().unwrap();
+            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+        }
+
+        // (list (response uint principal)) -> (list (response uint <trait>))
+        let list_res_ty = TypeSignature::list_of(response_err_ty.clone(), 4).unwrap();
+        let list_res_contract = Value::list_from(vec![
+            Value::error(contract.clone()).unwrap(),
+            Value::error(contract2.clone()).unwrap(),
+            Value::error(contract2.clone()).unwrap(),
+        ])
+        .unwrap();
+        let cast_list = clarity2_implicit_cast(&list_res_ty, list_res_contract.clone()).unwrap();
+        let items = cast_list.expect_list().unwrap();
+        for item in items {
+            let cast_trait = item.expect_result_err().unwrap().expect_callable().unwrap();
+            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+        }
+
+        // (optional (list (response uint principal))) -> (optional (list (response uint <trait>)))
+        let list_res_ty = TypeSignature::list_of(response_err_ty.clone(), 4).unwrap();
+        let opt_list_res_ty = TypeSignature::new_option(list_res_ty).unwrap();
+        let list_res_contract = Value::list_from(vec![
+            Value::error(contract.clone()).unwrap(),
+            Value::error(contract2.clone()).unwrap(),
+            Value::error(contract2.clone()).unwrap(),
+        ])
+        .unwrap();
+        let opt_list_res_contract = Value::some(list_res_contract).unwrap();
+        let cast_opt =
+            clarity2_implicit_cast(&opt_list_res_ty, opt_list_res_contract.clone()).unwrap();
+        let inner = cast_opt.expect_optional().unwrap().unwrap();
+        let items = inner.expect_list().unwrap();
+        for item in items {
+            let cast_trait = item.expect_result_err().unwrap().expect_callable().unwrap();
+            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+        }
+
+        // (optional (optional principal)) -> (optional (optional <trait>))
+        let optional_optional_ty = TypeSignature::new_option(optional_ty.clone()).unwrap();
+        let optional_contract = Value::some(contract.clone()).unwrap();
+        let optional_optional_contract = Value::some(optional_contract.clone()).unwrap();
+        let cast_optional =
+            clarity2_implicit_cast(&optional_optional_ty, optional_optional_contract.clone())
+                .unwrap();
+
+        match &cast_optional
+            .expect_optional()
+            .unwrap()
+            .unwrap()
+            .expect_optional()
+            .unwrap()
+            .unwrap()
+        {
+            Value::CallableContract(CallableData {
+                contract_identifier: contract_id,
+                trait_identifier: trait_id,
+            }) => {
+                assert_eq!(contract_id, &contract_identifier);
+                assert_eq!(trait_id.as_ref().unwrap(), &trait_identifier);
+            }
+            other => panic!("expected Value::CallableContract, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_defined_function() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "my-contract".into(),
+            "my-trait".into(),
+        );
+        let mut f = parse_function(
+            vec![(
+                "a".into(),
+                TypeSignature::TraitReferenceType(trait_id.clone()),
+            )],
+            DefineType::Public,
+            "3",
+        );
+        f.canonicalize_types(&StacksEpochId::Epoch21);
+        assert_eq!(
+            f.arg_types[0],
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone()))
+        );
+    }
+
+    #[test]
+    fn test_execute_apply_trusted() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::Private,
+            "a",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // functions that were not verified are still type-checked
+            let err = f.execute_apply_trusted(&[Value::UInt(1)], env).unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Unchecked(CheckErrors::TypeValueErrorAt(0, TypeSignature::IntType, _))
+            ));
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    verified_functions: [f.get_identifier()].into_iter().collect(),
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            assert_eq!(
+                f.execute_apply_trusted(&[Value::UInt(1)], env).unwrap(),
+                Value::UInt(1)
+            );
+
+            // `execute_apply` always checks its arguments
+            let err = f.execute_apply(&[Value::UInt(1)], env).unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Unchecked(CheckErrors::TypeValueErrorAt(0, TypeSignature::IntType, _))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_in() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::Private,
+            "(+ a captured)",
+        );
+        let make_base = |name: &str| {
+            let mut base = LocalContext::new();
+            base.variables.insert(name.into(), Value::Int(10));
+            base
+        };
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // the arguments are layered on top of the base bindings
+            assert_eq!(
+                f.execute_apply_in(&[Value::Int(1)], env, make_base("captured")),
+                Ok(Value::Int(11))
+            );
+            // without them, the body cannot be evaluated
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1)], env),
+                Err(CheckErrors::UndefinedVariable("captured".into()).into())
+            );
+            // an argument cannot shadow a base binding
+            assert_eq!(
+                f.execute_apply_in(&[Value::Int(1)], env, make_base("a")),
+                Err(CheckErrors::NameAlreadyUsed("a".into()).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_strict_shadowing() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::Private,
+            "a",
+        );
+        // as bound by an enclosing `(let ((a 10)) ...)`
+        let mut outer = LocalContext::new();
+        outer.variables.insert("a".into(), Value::Int(10));
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert!(!env.global_context.execution_options().strict_shadowing);
+            assert_eq!(
+                f.execute_apply_in(&[Value::Int(1)], env, outer.extend().unwrap()),
+                Ok(Value::Int(1))
+            );
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    strict_shadowing: true,
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            assert_eq!(
+                f.execute_apply_in(&[Value::Int(1)], env, outer.extend().unwrap()),
+                Err(CheckErrors::NameAlreadyUsed("a".into()).into())
+            );
+            // names that shadow nothing are still fine
+            assert_eq!(f.execute_apply(&[Value::Int(1)], env), Ok(Value::Int(1)));
+        });
+    }
+
+    #[test]
+    fn test_integer_arg_coercion() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::UIntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert!(!env.global_context.execution_options().coerce_integer_args);
+            assert_eq!(
+                f.execute_apply(&[Value::UInt(1), Value::UInt(2)], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(0, TypeSignature::IntType, Value::UInt(1)).into()
+                )
+            );
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    coerce_integer_args: true,
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            // bound as an `int`
+            assert_eq!(
+                f.execute_apply(&[Value::UInt(1), Value::Int(2)], env),
+                Ok(Value::Int(1))
+            );
+            assert_eq!(
+                f.execute_apply(&[Value::UInt(u128::MAX), Value::UInt(2)], env),
+                Err(CheckErrors::TypeValueErrorAt(
+                    0,
+                    TypeSignature::IntType,
+                    Value::UInt(u128::MAX)
+                )
+                .into())
+            );
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(-2)], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(1, TypeSignature::UIntType, Value::Int(-2))
+                        .into()
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn test_argument_wrapping() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function = |arg_type: TypeSignature| {
+            parse_function(vec![("a".into(), arg_type)], DefineType::Private, "a")
+        };
+        let optional_type = TypeSignature::OptionalType(Box::new(TypeSignature::UIntType));
+        let takes_optional = make_function(optional_type.clone());
+        let response_type =
+            |err_type| TypeSignature::ResponseType(Box::new((TypeSignature::UIntType, err_type)));
+        let takes_response = make_function(response_type(TypeSignature::IntType));
+        let ambiguous_type = response_type(TypeSignature::UIntType);
+        let takes_ambiguous = make_function(ambiguous_type.clone());
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // strict by default
+            assert!(!env.global_context.execution_options().wrap_args);
+            assert_eq!(
+                takes_optional.execute_apply(&[Value::UInt(1)], env),
+                Err(CheckErrors::TypeValueErrorAt(0, optional_type, Value::UInt(1)).into())
+            );
+            assert_eq!(
+                takes_response.execute_apply(&[Value::UInt(1)], env),
+                Err(CheckErrors::TypeValueErrorAt(
+                    0,
+                    response_type(TypeSignature::IntType),
+                    Value::UInt(1)
+                )
+                .into())
+            );
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    wrap_args: true,
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            assert_eq!(
+                takes_optional.execute_apply(&[Value::UInt(1)], env),
+                Ok(Value::some(Value::UInt(1)).unwrap())
+            );
+            assert_eq!(
+                takes_optional.execute_apply(&[Value::none()], env),
+                Ok(Value::none())
+            );
+            assert_eq!(
+                takes_response.execute_apply(&[Value::UInt(1)], env),
+                Ok(Value::okay(Value::UInt(1)).unwrap())
+            );
+            // `(ok u1)` and `(err u1)` would both do
+            assert_eq!(
+                takes_ambiguous.execute_apply(&[Value::UInt(1)], env),
+                Err(CheckErrors::TypeValueErrorAt(0, ambiguous_type, Value::UInt(1)).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_argument_size_limit() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function = |value: &Value| {
+            parse_function(
+                vec![("a".into(), TypeSignature::type_of(value).unwrap())],
+                DefineType::Private,
+                "a",
+            )
+        };
+        // a tuple of depth 8: `{ inner: { inner: ... u1 } }`
+        let mut deep = Value::UInt(1);
+        for _ in 0..7 {
+            deep = Value::Tuple(TupleData::from_data(vec![("inner".into(), deep)]).unwrap());
+        }
+        let takes_deep = make_function(&deep);
+        let shallow = Value::Tuple(
+            TupleData::from_data(vec![
+                ("a".into(), Value::UInt(1)),
+                ("b".into(), Value::some(Value::Int(2)).unwrap()),
+            ])
+            .unwrap(),
+        );
+        let takes_shallow = make_function(&shallow);
+        let wide = Value::cons_list_unsanitized((0..20).map(Value::UInt).collect()).unwrap();
+        let takes_wide = make_function(&wide);
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // no limit by default
+            assert_eq!(env.global_context.execution_options().arg_size_limit, None);
+            assert_eq!(
+                takes_deep.execute_apply(&[deep.clone()], env),
+                Ok(deep.clone())
+            );
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    arg_size_limit: Some(ArgumentSizeLimit {
+                        max_depth: 4,
+                        max_nodes: 16,
+                    }),
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            assert_eq!(
+                takes_deep.execute_apply(&[deep.clone()], env),
+                Err(CheckErrors::ValueTooLarge.into())
+            );
+            assert_eq!(
+                takes_wide.execute_apply(&[wide.clone()], env),
+                Err(CheckErrors::ValueTooLarge.into())
+            );
+            assert_eq!(
+                takes_shallow.execute_apply(&[shallow.clone()], env),
+                Ok(shallow.clone())
+            );
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_many() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "(+ a b)",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            let results = f.execute_apply_many(
+                &[
+                    vec![Value::Int(1), Value::Int(2)],
+                    // fails after `a` is bound
+                    vec![Value::Int(3), Value::UInt(4)],
+                    // so `a` must not be bound here
+                    vec![Value::Int(5), Value::Int(6)],
+                    vec![Value::Int(7)],
+                ],
+                env,
+            );
+            assert_eq!(
+                results,
+                vec![
+                    Ok(Value::Int(3)),
+                    Err(
+                        CheckErrors::TypeValueErrorAt(1, TypeSignature::IntType, Value::UInt(4))
+                            .into()
+                    ),
+                    Ok(Value::Int(11)),
+                    Err(CheckErrors::IncorrectArgumentCount(2, 1).into()),
+                ]
+            );
+            assert!(f.execute_apply_many(&[], env).is_empty());
+        });
+    }
+
+    #[test]
+    fn test_args_statically_checked() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let trait_type = TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone()));
+        let make_function = |arg_type: TypeSignature| {
+            let mut f = parse_function(vec![("a".into(), arg_type)], DefineType::Private, "1");
+            f.set_args_statically_checked(true);
+            f
+        };
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // the declared type is trusted
+            let f = make_function(TypeSignature::IntType);
+            assert!(f.args_statically_checked());
+            assert_eq!(f.execute_apply(&[Value::UInt(1)], env), Ok(Value::Int(1)));
+            assert_eq!(f.apply(vec![Value::UInt(1)], env), Ok(Value::Int(1)));
+
+            // but trait references are always checked
+            let f = make_function(trait_type.clone());
+            assert_eq!(
+                f.execute_apply(&[Value::UInt(1)], env),
+                Err(CheckErrors::TypeValueErrorAt(0, trait_type.clone(), Value::UInt(1)).into())
+            );
+            assert_eq!(
+                f.apply(vec![Value::UInt(1)], env),
+                Err(CheckErrors::TypeValueErrorAt(0, trait_type.clone(), Value::UInt(1)).into())
+            );
+            let standard =
+                Value::Principal(PrincipalData::Standard(StandardPrincipalData::transient()));
+            assert_eq!(
+                f.execute_apply(&[standard.clone()], env),
+                Err(CheckErrors::ExpectedCallableContract(trait_id.clone(), standard).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_disallow_dynamic_dispatch() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let f = parse_function(
+            vec![(
+                "a".into(),
+                TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone())),
+            )],
+            DefineType::Private,
+            "1",
+        );
+        let callee = Value::CallableContract(CallableData {
+            contract_identifier: QualifiedContractIdentifier::local("callee").unwrap(),
+            trait_identifier: Some(trait_id),
+        });
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert!(
+                env.global_context
+                    .execution_options()
+                    .allow_dynamic_dispatch
+            );
+            assert_eq!(f.execute_apply(&[callee.clone()], env), Ok(Value::Int(1)));
+
+            env.global_context
+                .set_execution_options(ExecutionOptions {
+                    allow_dynamic_dispatch: false,
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            assert_eq!(
+                f.execute_apply(&[callee.clone()], env),
+                Err(CheckErrors::DynamicDispatchDisabled.into())
+            );
+
+            env.global_context
+                .set_execution_options(ExecutionOptions::default())
+                .unwrap();
+            assert_eq!(f.execute_apply(&[callee.clone()], env), Ok(Value::Int(1)));
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_reports_argument_index() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::BoolType),
+                ("c".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2), Value::Int(3)], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(1, TypeSignature::BoolType, Value::Int(2)).into()
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_with_context() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = DefinedFunction::new(
+            vec![("a".into(), TypeSignature::IntType)],
+            SymbolicExpression::atom("a".into()),
+            DefineType::Private,
+            &"foo".into(),
+            "S1G2081040G2081040G2081040G208105NK8PE5.other",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                f.execute_apply_with_context(&[Value::Int(1)], env),
+                Ok(Value::Int(1))
+            );
+
+            let err = f
+                .execute_apply_with_context(&[Value::UInt(1)], env)
+                .unwrap_err();
+            assert_eq!(err.function(), &f.get_identifier());
+            assert!(err
+                .to_string()
+                .starts_with("S1G2081040G2081040G2081040G208105NK8PE5.other:foo: "));
+            assert_eq!(
+                err.into_inner(),
+                CheckErrors::TypeValueErrorAt(0, TypeSignature::IntType, Value::UInt(1)).into()
+            );
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_named() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "(- a b)",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            let reordered =
+                HashMap::from([("b".into(), Value::Int(3)), ("a".into(), Value::Int(10))]);
+            assert_eq!(f.execute_apply_named(&reordered, env), Ok(Value::Int(7)));
+
+            let missing = HashMap::from([("a".into(), Value::Int(10))]);
+            assert_eq!(
+                f.execute_apply_named(&missing, env),
+                Err(CheckErrors::MissingNamedArgument("b".into()).into())
+            );
+
+            let extra = HashMap::from([
+                ("a".into(), Value::Int(10)),
+                ("b".into(), Value::Int(3)),
+                ("c".into(), Value::Int(1)),
+            ]);
+            assert_eq!(
+                f.execute_apply_named(&extra, env),
+                Err(CheckErrors::UnexpectedNamedArgument("c".into()).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_apply_owned_arguments() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let buff_type =
+            TypeSignature::SequenceType(SequenceSubtype::BufferType(4096_u32.try_into().unwrap()));
+        let extend = parse_function(
+            vec![("b".into(), buff_type.clone())],
+            DefineType::Private,
+            "(let ((extended (concat b 0x01))) (len extended))",
+        );
+        let rebind = parse_function(
+            vec![("b".into(), buff_type)],
+            DefineType::Private,
+            "(let ((b 0x01)) b)",
+        );
+        let buffer = Value::buff_from(vec![0; 4000]).unwrap();
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // moving the arguments into the context behaves exactly like
+            // borrowing them
+            assert_eq!(
+                extend.apply(vec![buffer.clone()], env),
+                Ok(Value::UInt(4001))
+            );
+            assert_eq!(
+                extend.execute_apply(&[buffer.clone()], env),
+                Ok(Value::UInt(4001))
+            );
+            // the function's arguments cannot be rebound
+            assert_eq!(
+                rebind.apply(vec![buffer.clone()], env),
+                Err(CheckErrors::NameAlreadyUsed("b".into()).into())
+            );
+            assert_eq!(
+                rebind.execute_apply(&[buffer.clone()], env),
+                Err(CheckErrors::NameAlreadyUsed("b".into()).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_partial_application() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = Rc::new(parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+                ("c".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "(- a (* b c))",
+        ));
+
+        assert_eq!(
+            PartialApplication::new(f.clone(), vec![Value::Int(1); 4]).unwrap_err(),
+            CheckErrors::IncorrectArgumentCount(3, 4).into()
+        );
+        let partial = PartialApplication::new(f.clone(), vec![Value::Int(10)]).unwrap();
+        assert_eq!(partial.bound_args(), &[Value::Int(10)]);
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // the bound prefix comes first, and can be completed repeatedly
+            assert_eq!(
+                partial.complete(&[Value::Int(2), Value::Int(3)], env),
+                Ok(Value::Int(4))
+            );
+            assert_eq!(
+                partial.complete(&[Value::Int(3), Value::Int(3)], env),
+                Ok(Value::Int(1))
+            );
+            assert_eq!(
+                partial.complete(&[Value::Int(2)], env),
+                Err(CheckErrors::IncorrectArgumentCount(3, 2).into())
+            );
+            assert_eq!(
+                partial.complete(&[Value::Int(2), Value::Int(3), Value::Int(4)], env),
+                Err(CheckErrors::IncorrectArgumentCount(3, 4).into())
+            );
+            // arguments are still type-checked
+            assert_eq!(
+                partial.complete(&[Value::Int(2), Value::UInt(3)], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(2, TypeSignature::IntType, Value::UInt(3)).into()
+                )
+            );
+        });
+
+        // a default may be left for `complete` to fill in
+        let mut with_default = (*f).clone();
+        with_default
+            .set_arg_defaults(vec![None, None, Some(Value::Int(1))])
+            .unwrap();
+        let partial = PartialApplication::new(Rc::new(with_default), vec![Value::Int(10)]).unwrap();
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(partial.complete(&[Value::Int(2)], env), Ok(Value::Int(8)));
+            assert_eq!(
+                partial.complete(&[], env),
+                Err(CheckErrors::IncorrectArgumentCountRange(2, Some(3), 1).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_partial_application_with_holes() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = Rc::new(parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+                ("c".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "(- a (* b c))",
+        ));
+
+        // a hole in the middle
+        let middle = PartialApplication::with_holes(
+            f.clone(),
+            vec![
+                Arg::Value(Value::Int(10)),
+                Arg::Hole,
+                Arg::Value(Value::Int(3)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(middle.hole_count(), 1);
+        assert_eq!(middle.bound_args(), &[Value::Int(10), Value::Int(3)]);
+
+        // every argument a hole
+        let empty = PartialApplication::with_holes(f.clone(), vec![Arg::Hole; 3]).unwrap();
+        assert_eq!(empty.hole_count(), 3);
+        assert!(empty.bound_args().is_empty());
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(middle.complete(&[Value::Int(2)], env), Ok(Value::Int(4)));
+            assert_eq!(
+                middle.complete(&[], env),
+                Err(CheckErrors::IncorrectArgumentCount(3, 2).into())
+            );
+            // a filled hole is still type-checked
+            assert_eq!(
+                middle.complete(&[Value::UInt(2)], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(1, TypeSignature::IntType, Value::UInt(2)).into()
+                )
+            );
+
+            // filling some holes leaves a partial application
+            let partial = match empty
+                .fill(vec![Arg::Hole, Arg::Value(Value::Int(2))], env)
+                .unwrap()
+            {
+                Application::Partial(partial) => partial,
+                Application::Complete(value) => panic!("unexpectedly completed with {}", value),
+            };
+            assert_eq!(partial.hole_count(), 2);
+            assert_eq!(partial.bound_args(), &[Value::Int(2)]);
+            match partial
+                .fill(
+                    vec![Arg::Value(Value::Int(10)), Arg::Value(Value::Int(3))],
+                    env,
+                )
+                .unwrap()
+            {
+                Application::Complete(value) => assert_eq!(value, Value::Int(4)),
+                Application::Partial(_) => panic!("holes left unfilled"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_apply_large_list_argument() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let list_type =
+            TypeSignature::list_of(TypeSignature::IntType, 10000).expect("valid list type");
+        // the body modifies a copy of its argument, then reads the argument
+        let f = parse_function(
+            vec![("xs".into(), list_type)],
+            DefineType::Private,
+            "(let ((ys (unwrap-panic (replace-at? xs u0 -1))))
+                { copy: (element-at? ys u0), original: (element-at? xs u0), len: (len xs) })",
+        );
+        let list = Value::cons_list_unsanitized((0..10000).map(Value::Int).collect()).unwrap();
+        let expected = Value::from(
+            TupleData::from_data(vec![
+                ("copy".into(), Value::some(Value::Int(-1)).unwrap()),
+                ("original".into(), Value::some(Value::Int(0)).unwrap()),
+                ("len".into(), Value::UInt(10000)),
+            ])
+            .unwrap(),
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // `apply` moves the list into the function's context and
+            // `execute_apply` copies it: either way, the copy is independent
+            assert_eq!(f.apply(vec![list.clone()], env), Ok(expected.clone()));
+            assert_eq!(f.execute_apply(&[list.clone()], env), Ok(expected));
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_undeclared_trait_reference() {
+        // The trait is never declared by any contract: binding the argument
+        // and attempting the dynamic dispatch must fail with an error rather
+        // than a panic.
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "undeclared".into(),
+            "missing-trait".into(),
+        );
+        let callee = QualifiedContractIdentifier::local("callee").unwrap();
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_named_function(
+            "bar",
+            vec![(
+                "t".into(),
+                TypeSignature::CallableType(CallableSubtype::Trait(trait_id)),
+            )],
+            DefineType::Private,
+            "(contract-call? t foo)",
+        );
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            let err = f
+                .execute_apply(&[Value::Principal(PrincipalData::Contract(callee))], env)
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Unchecked(CheckErrors::NoSuchContract(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_execute_apply_standard_principal_for_trait() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "my-contract".into(),
+            "my-trait".into(),
+        );
+        let standard =
+            Value::Principal(PrincipalData::Standard(StandardPrincipalData::transient()));
+        let make_function =
+            |arg_type| parse_function(vec![("t".into(), arg_type)], DefineType::Private, "1");
+
+        for (version, epoch, arg_type) in [
+            (
+                ClarityVersion::Clarity1,
+                StacksEpochId::Epoch2_05,
+                TypeSignature::TraitReferenceType(trait_id.clone()),
+            ),
+            (
+                ClarityVersion::Clarity1,
+                StacksEpochId::Epoch21,
+                TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone())),
+            ),
+            (
+                ClarityVersion::Clarity2,
+                StacksEpochId::Epoch21,
+                TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone())),
+            ),
+        ] {
+            let contract_context =
+                ContractContext::new(QualifiedContractIdentifier::transient(), version);
+            let f = make_function(arg_type);
+            with_test_env(&contract_context, epoch, |env| {
+                assert_eq!(
+                    f.execute_apply(&[standard.clone()], env),
+                    Err(
+                        CheckErrors::ExpectedCallableContract(trait_id.clone(), standard.clone())
+                            .into()
+                    )
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_check_trait_expectations() {
+        let defining_contract = QualifiedContractIdentifier::local("defining").unwrap();
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let mut contract_defining_trait =
+            ContractContext::new(defining_contract, ClarityVersion::Clarity2);
+        let mut trait_functions = BTreeMap::new();
+        trait_functions.insert(
+            "foo".into(),
+            FunctionSignature {
+                args: vec![TypeSignature::UIntType],
+                returns: TypeSignature::new_response(
+                    TypeSignature::UIntType,
+                    TypeSignature::UIntType,
+                )
+                .unwrap(),
+            },
+        );
+        contract_defining_trait
+            .defined_traits
+            .insert("my-trait".into(), trait_functions);
+
+        let make_function = |name: &str| {
+            parse_named_function(
+                name,
+                vec![("a".into(), TypeSignature::UIntType)],
+                DefineType::Public,
+                "(ok u1)",
+            )
+        };
+
+        // conforming method
+        make_function("foo")
+            .check_trait_expectations(&StacksEpochId::Epoch21, &contract_defining_trait, &trait_id)
+            .unwrap();
+
+        // method missing from the trait
+        let err = make_function("bar")
+            .check_trait_expectations(&StacksEpochId::Epoch21, &contract_defining_trait, &trait_id)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::TraitMethodUnknown("my-trait".into(), "bar".into()).into()
+        );
+
+        // trait not declared by the defining contract
+        let unknown_trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "other-trait".into(),
+        );
+        let err = make_function("foo")
+            .check_trait_expectations(
+                &StacksEpochId::Epoch21,
+                &contract_defining_trait,
+                &unknown_trait_id,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::TraitReferenceUnknown("other-trait".into()).into()
+        );
+    }
+
+    #[test]
+    fn test_callable_arity() {
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+        assert_eq!(CallableType::UserFunction(f).get_arity(), Some(2));
+
+        let lookup = |name| {
+            crate::vm::functions::lookup_reserved_functions(name, &ClarityVersion::Clarity2)
+                .unwrap()
+        };
+        assert_eq!(lookup("not").get_arity(), Some(1));
+        assert_eq!(lookup("mod").get_arity(), Some(2));
+        assert_eq!(lookup("+").get_arity(), None);
+        assert_eq!(lookup("is-eq").get_arity(), None);
+        assert_eq!(lookup("if").get_arity(), None);
+    }
+
+    #[test]
+    fn test_validate_arity() {
+        let mut f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+        assert_eq!(f.arg_count(), 2);
+        assert_eq!(f.validate_arity(2), Ok(()));
+        for n in [0, 1, 3] {
+            assert_eq!(
+                f.validate_arity(n),
+                Err(CheckErrors::IncorrectArgumentCount(2, n).into())
+            );
+        }
+
+        // a defaulted argument may be left out
+        f.set_arg_defaults(vec![None, Some(Value::Int(0))]).unwrap();
+        assert_eq!(f.arg_count(), 2);
+        assert_eq!(f.validate_arity(1), Ok(()));
+        for n in [0, 3] {
+            assert_eq!(
+                f.validate_arity(n),
+                Err(CheckErrors::IncorrectArgumentCountRange(1, Some(2), n).into())
+            );
+        }
+
+        // a rest parameter takes any number of extra arguments
+        let mut g = parse_named_function(
+            "bar",
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                (
+                    "rest".into(),
+                    TypeSignature::list_of(TypeSignature::IntType, 10).unwrap(),
+                ),
+            ],
+            DefineType::Private,
+            "a",
+        );
+        g.set_rest_argument().unwrap();
+        assert_eq!(g.validate_arity(5), Ok(()));
+        assert_eq!(
+            g.validate_arity(0),
+            Err(CheckErrors::IncorrectArgumentCountRange(1, None, 0).into())
+        );
+    }
+
+    #[test]
+    fn test_as_user_function() {
+        let f = parse_function(vec![], DefineType::Private, "1");
+        let user = CallableType::UserFunction(f);
+        assert!(!user.is_native());
+        assert_eq!(
+            user.as_user_function()
+                .map(|f| f.get_identifier().to_string()),
+            Some("testing:foo".to_string())
+        );
+
+        let lookup = |name| {
+            crate::vm::functions::lookup_reserved_functions(name, &ClarityVersion::Clarity2)
+                .unwrap()
+        };
+        // native, native with 2.05 costs, and special functions
+        for name in ["not", "sha256", "if"] {
+            let callable = lookup(name);
+            assert!(callable.is_native());
+            assert!(callable.as_user_function().is_none());
+        }
+
+        let host = CallableType::HostFunction(Rc::new(HostFunction::new(
+            "host".into(),
+            Box::new(|_: &[Value]| Ok(Value::Int(1))),
+        )));
+        assert!(!host.is_native());
+        assert!(host.as_user_function().is_none());
+    }
+
+    #[test]
+    fn test_parse_user_identifier() {
+        let f = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::Int(1)),
+            DefineType::Private,
+            &"foo".into(),
+            "S1G2081040G2081040G2081040G208105NK8PE5.contract",
+        );
+        assert_eq!(f.get_name().as_str(), "foo");
+        assert_eq!(
+            f.get_identifier().parse_user(),
+            Some(("S1G2081040G2081040G2081040G208105NK8PE5.contract", "foo"))
+        );
+
+        let native =
+            crate::vm::functions::lookup_reserved_functions("+", &ClarityVersion::Clarity2)
+                .unwrap()
+                .get_identifier();
+        assert_eq!(native.parse_user(), None);
+    }
+
+    #[test]
+    fn test_function_kind() {
+        let make_function = |context| {
+            DefinedFunction::new(
+                vec![],
+                SymbolicExpression::atom_value(Value::Int(1)),
+                DefineType::Private,
+                &"foo".into(),
+                context,
+            )
+        };
+        let user = make_function("S1G2081040G2081040G2081040G208105NK8PE5.contract");
+        assert_eq!(user.get_identifier().kind(), FunctionKind::User);
+
+        for name in ["+", "if"] {
+            let native =
+                crate::vm::functions::lookup_reserved_functions(name, &ClarityVersion::Clarity2)
+                    .unwrap()
+                    .get_identifier();
+            assert_eq!(native.kind(), FunctionKind::Native);
+        }
+
+        // a context named `_native_` cannot be told apart from a native function
+        let edge = make_function("_native_");
+        assert_eq!(edge.get_identifier().to_string(), "_native_:foo");
+        assert_eq!(edge.get_identifier().kind(), FunctionKind::Native);
+        assert_eq!(edge.get_identifier().parse_user(), None);
+        // but a context that merely starts with it can
+        let prefixed = make_function("_native_x");
+        assert_eq!(prefixed.get_identifier().kind(), FunctionKind::User);
+    }
+
+    #[test]
+    fn test_native_function_identifiers() {
+        let lookup = |name| {
+            crate::vm::functions::lookup_reserved_functions(name, &ClarityVersion::Clarity2)
+                .unwrap()
+        };
+        let first = lookup("+").get_identifier();
+        let second = lookup("+").get_identifier();
+        assert_eq!(first.to_string(), "_native_:native_add");
+        // repeated lookups share the cached string
+        assert!(std::ptr::eq(
+            first.identifier.as_ref(),
+            second.identifier.as_ref()
+        ));
+        assert_eq!(
+            lookup("if").get_identifier().to_string(),
+            "_native_:special_if"
+        );
+
+        // a cached identifier is indistinguishable from one that is not
+        let owned: FunctionIdentifier =
+            serde_json::from_str(&serde_json::to_string(&first).unwrap()).unwrap();
+        assert!(!std::ptr::eq(
+            owned.identifier.as_ref(),
+            first.identifier.as_ref()
+        ));
+        assert_eq!(owned, first);
+        let mut set = HashSet::new();
+        set.insert(first);
+        assert!(set.contains(&owned));
+    }
+
+    #[test]
+    fn test_user_function_identifiers() {
+        let make_function = |name: &str| {
+            DefinedFunction::new(
+                vec![],
+                SymbolicExpression::atom_value(Value::Int(1)),
+                DefineType::Private,
+                &name.into(),
+                "interned",
+            )
+        };
+        let first = make_function("foo").get_identifier();
+        let second = make_function("foo").get_identifier();
+        assert_eq!(first, second);
+        assert!(Arc::ptr_eq(&first.identifier, &second.identifier));
+        assert!(!Arc::ptr_eq(
+            &first.identifier,
+            &make_function("bar").get_identifier().identifier
+        ));
+
+        // serialized as before, and shared once read back
+        let json = serde_json::to_string(&first).unwrap();
+        assert_eq!(json, r#"{"identifier":"interned:foo"}"#);
+        let read: FunctionIdentifier = serde_json::from_str(&json).unwrap();
+        assert!(Arc::ptr_eq(&first.identifier, &read.identifier));
+        assert_eq!(read.to_string(), "interned:foo");
+
+        // an identifier no longer in use is not kept alive by the table
+        let weak = Arc::downgrade(&make_function("baz").get_identifier().identifier);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_default_arguments() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let mut f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+                ("c".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "(+ a b c)",
+        );
+
+        // defaults must cover every argument and only trailing arguments
+        assert_eq!(
+            f.set_arg_defaults(vec![None, Some(Value::Int(10))]),
+            Err(CheckErrors::DefineFunctionBadSignature.into())
+        );
+        assert_eq!(
+            f.set_arg_defaults(vec![None, Some(Value::Int(10)), None]),
+            Err(CheckErrors::DefineFunctionBadSignature.into())
+        );
+        f.set_arg_defaults(vec![None, Some(Value::Int(10)), Some(Value::Int(100))])
+            .unwrap();
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1)], env).unwrap(),
+                Value::Int(111)
+            );
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2)], env)
+                    .unwrap(),
+                Value::Int(103)
+            );
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2), Value::Int(3)], env)
+                    .unwrap(),
+                Value::Int(6)
+            );
+
+            // the non-defaulted argument is still required
+            assert_eq!(
+                f.execute_apply(&[], env).unwrap_err(),
+                CheckErrors::IncorrectArgumentCountRange(1, Some(3), 0).into()
+            );
+
+            // a supplied override must still match the declared type
+            let err = f
+                .execute_apply(&[Value::Int(1), Value::UInt(2)], env)
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Unchecked(CheckErrors::TypeValueErrorAt(1, TypeSignature::IntType, _))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_rest_argument() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let rest_type = TypeSignature::list_of(TypeSignature::IntType, 5).unwrap();
+        let mut f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("rest".into(), rest_type),
+            ],
+            DefineType::Private,
+            "rest",
+        );
+
+        // a rest parameter must be declared as a list
+        let mut g = parse_named_function(
+            "bar",
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::Private,
+            "a",
+        );
+        assert_eq!(
+            g.set_rest_argument(),
+            Err(CheckErrors::DefineFunctionBadSignature.into())
+        );
+
+        f.set_rest_argument().unwrap();
+        assert!(f.has_rest_argument());
+        assert_eq!(CallableType::UserFunction(f.clone()).get_arity(), None);
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // no extra arguments
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1)], env).unwrap(),
+                Value::cons_list_unsanitized(vec![]).unwrap()
+            );
+
+            // several extra arguments
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2), Value::Int(3)], env)
+                    .unwrap(),
+                Value::cons_list_unsanitized(vec![Value::Int(2), Value::Int(3)]).unwrap()
+            );
+
+            // the fixed prefix is still required
+            assert_eq!(
+                f.execute_apply(&[], env).unwrap_err(),
+                CheckErrors::IncorrectArgumentCountRange(1, None, 0).into()
+            );
+
+            // every collected value must match the rest item type
+            let err = f
+                .execute_apply(&[Value::Int(1), Value::Int(2), Value::UInt(3)], env)
+                .unwrap_err();
+            assert_eq!(
+                err,
+                CheckErrors::TypeValueErrorAt(2, TypeSignature::IntType, Value::UInt(3)).into()
+            );
+        });
+    }
+
+    #[test]
+    fn test_assert_read_only_safe() {
+        let epoch = StacksEpochId::Epoch21;
+        let make_function = |name: &str, define_type, body: &str| {
+            parse_named_function(
+                name,
+                vec![("a".into(), TypeSignature::IntType)],
+                define_type,
+                body,
+            )
+        };
+
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        contract_context.functions.insert(
+            "write-private".into(),
+            make_function(
+                "write-private",
+                DefineType::Private,
+                "(map-set m { k: a } { v: a })",
+            ),
+        );
+
+        let reads = make_function(
+            "reads",
+            DefineType::ReadOnly,
+            "(let ((x (map-get? m { k: a }))) (map + (list a) (list (default-to 0 (get v x)))))",
+        );
+        let writes = make_function(
+            "writes",
+            DefineType::ReadOnly,
+            "(begin (map-set m { k: a } { v: a }) a)",
+        );
+        let writes_in_binding = make_function(
+            "writes-in-binding",
+            DefineType::ReadOnly,
+            "(let ((x (var-set v a))) a)",
+        );
+        let calls_writer = make_function(
+            "calls-writer",
+            DefineType::ReadOnly,
+            "(begin (write-private a) a)",
+        );
+        let maps_writer = make_function(
+            "maps-writer",
+            DefineType::ReadOnly,
+            "(map write-private (list a))",
+        );
+
+        with_test_env(&contract_context, epoch, |env| {
+            assert_eq!(reads.assert_read_only_safe(env), Ok(()));
+            for f in [&writes, &writes_in_binding, &calls_writer, &maps_writer] {
+                assert_eq!(
+                    f.assert_read_only_safe(env),
+                    Err(CheckErrors::WriteAttemptedInReadOnly.into())
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_assert_read_only_safe_recursion() {
+        let epoch = StacksEpochId::Epoch21;
+        let make_function = |name: &str, body: &str| {
+            parse_named_function(
+                name,
+                vec![("a".into(), TypeSignature::IntType)],
+                DefineType::ReadOnly,
+                body,
+            )
+        };
+
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        for (name, body) in [
+            ("loops", "(loops a)"),
+            ("ping", "(pong a)"),
+            ("pong", "(begin (ping a) (var-set v a))"),
+        ] {
+            contract_context
+                .functions
+                .insert(name.into(), make_function(name, body));
+        }
+
+        // recursion is rejected by analysis, not by overflowing the stack
+        with_test_env(&contract_context, epoch, |env| {
+            let loops = contract_context.lookup_function("loops").unwrap();
+            assert_eq!(loops.assert_read_only_safe(env), Ok(()));
+            assert_eq!(
+                make_function("calls-ping", "(ping a)").assert_read_only_safe(env),
+                Err(CheckErrors::WriteAttemptedInReadOnly.into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_referenced_contracts() {
+        let make_function = |body: &str| {
+            parse_function(
+                vec![("t".into(), TypeSignature::PrincipalType)],
+                DefineType::Public,
+                body,
+            )
+        };
+        let other = QualifiedContractIdentifier::local("other").unwrap();
+        let third = QualifiedContractIdentifier::local("third").unwrap();
+
+        let direct = make_function(
+            "(begin (try! (contract-call? .other f)) (let ((x (contract-call? .third g))) (contract-call? .other f)))",
+        );
+        assert_eq!(direct.referenced_contracts(), vec![other.clone(), third]);
+        assert!(!direct.references_dynamic_dispatch());
+
+        let dynamic =
+            make_function("(begin (try! (contract-call? .other f)) (contract-call? t f))");
+        assert_eq!(dynamic.referenced_contracts(), vec![other]);
+        assert!(dynamic.references_dynamic_dispatch());
+
+        let none = make_function("(ok (+ 1 2))");
+        assert!(none.referenced_contracts().is_empty());
+        assert!(!none.references_dynamic_dispatch());
+    }
+
+    #[test]
+    fn test_recursion_kind() {
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        for (name, body) in [
+            ("plain", "(+ a 1)"),
+            ("countdown", "(if (> a 0) (countdown (- a 1)) 0)"),
+            ("ping", "(pong a)"),
+            ("pong", "(if (> a 0) (relay (- a 1)) (plain a))"),
+            ("relay", "(ping a)"),
+            ("maps-itself", "(begin (map maps-itself (list a)) a)"),
+        ] {
+            contract_context.functions.insert(
+                name.into(),
+                parse_named_function(
+                    name,
+                    vec![("a".into(), TypeSignature::IntType)],
+                    DefineType::Private,
+                    body,
+                ),
+            );
+        }
+        let kind = |name: &str| {
+            contract_context
+                .functions
+                .get(name)
+                .unwrap()
+                .recursion_kind(&contract_context)
+        };
+
+        assert_eq!(kind("plain"), RecursionKind::None);
+        assert_eq!(kind("countdown"), RecursionKind::SelfDirect);
+        assert_eq!(kind("maps-itself"), RecursionKind::SelfDirect);
+        assert_eq!(
+            kind("ping"),
+            RecursionKind::Mutual(vec!["ping".into(), "pong".into(), "relay".into()])
+        );
+        assert_eq!(
+            kind("relay"),
+            RecursionKind::Mutual(vec!["relay".into(), "ping".into(), "pong".into()])
+        );
+    }
+
+    #[test]
+    fn test_get_body() {
+        let f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::ReadOnly,
+            "(+ a 1)",
+        );
+        assert_eq!(f.get_body(), &parse("(+ a 1)"));
+        assert_eq!(f.get_body().match_list().map(|list| list.len()), Some(3));
+    }
+
+    #[test]
+    fn test_to_function_type() {
+        let mut f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::BoolType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+        let expected = FunctionType::Fixed(FixedFunction {
+            args: vec![
+                FunctionArg::new(TypeSignature::IntType, "a".into()),
+                FunctionArg::new(TypeSignature::BoolType, "b".into()),
+            ],
+            returns: TypeSignature::IntType,
+        });
+        assert_eq!(f.to_function_type(TypeSignature::IntType), expected);
+
+        assert_eq!(f.get_function_type(), None);
+        f.set_return_type(TypeSignature::IntType);
+        assert_eq!(f.get_return_type(), Some(&TypeSignature::IntType));
+        assert_eq!(f.get_function_type(), Some(expected));
+    }
+
+    #[test]
+    fn test_execute_apply_raw() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let mut f = parse_function(
+            vec![(
+                "a".into(),
+                TypeSignature::new_option(TypeSignature::UIntType).unwrap(),
+            )],
+            DefineType::Private,
+            "(ok (unwrap! a (err u1)))",
+        );
+        let thrown = Value::error(Value::UInt(1)).unwrap();
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // falling off the end is the same either way
+            let some = Value::some(Value::UInt(2)).unwrap();
+            let returned = Value::okay(Value::UInt(2)).unwrap();
+            assert_eq!(f.execute_apply(&[some.clone()], env), Ok(returned.clone()));
+            assert_eq!(f.execute_apply_raw(&[some], env), Ok(returned));
+
+            assert_eq!(f.execute_apply(&[Value::none()], env), Ok(thrown.clone()));
+            assert_eq!(
+                f.execute_apply_raw(&[Value::none()], env),
+                Err(ShortReturnType::ExpectedValue(thrown.clone()).into())
+            );
+
+            // the early return is still checked against the return type
+            let return_type =
+                TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::IntType)
+                    .unwrap();
+            f.set_return_type(return_type.clone());
+            assert_eq!(
+                f.execute_apply_raw(&[Value::none()], env),
+                Err(CheckErrors::TypeValueError(return_type, thrown).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_short_return_polarity() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function = |body: &str| {
+            parse_function(
+                vec![("a".into(), TypeSignature::IntType)],
+                DefineType::Private,
+                body,
+            )
+        };
+        let early_ok = make_function("(begin (unwrap! none (ok a)) (err 0))");
+        let early_err = make_function("(begin (try! (if true (err a) (ok a))) (ok 0))");
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                early_ok.execute_apply(&[Value::Int(1)], env),
+                Ok(Value::okay(Value::Int(1)).unwrap())
+            );
+            assert_eq!(
+                early_err.execute_apply(&[Value::Int(2)], env),
+                Ok(Value::error(Value::Int(2)).unwrap())
+            );
+        });
+    }
+
+    #[test]
+    fn test_return_type_check() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function = |return_type| {
+            let mut f = parse_function(
+                vec![("a".into(), TypeSignature::IntType)],
+                DefineType::Private,
+                "a",
+            );
+            if let Some(return_type) = return_type {
+                f.set_return_type(return_type);
+            }
+            f
+        };
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // unchecked
+            assert_eq!(
+                make_function(None).execute_apply(&[Value::Int(1)], env),
+                Ok(Value::Int(1))
+            );
+            // matching
+            assert_eq!(
+                make_function(Some(TypeSignature::IntType)).execute_apply(&[Value::Int(1)], env),
+                Ok(Value::Int(1))
+            );
+            // mismatching
+            assert_eq!(
+                make_function(Some(TypeSignature::UIntType)).execute_apply(&[Value::Int(1)], env),
+                Err(CheckErrors::TypeValueError(TypeSignature::UIntType, Value::Int(1)).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_defined_function_eq() {
+        let f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
+            DefineType::Private,
+            "a",
+        );
+
+        let mut typed = f.clone();
+        assert_eq!(typed, f);
+        typed.set_return_type(TypeSignature::IntType);
+        assert_ne!(typed, f);
+
+        let mut renamed = f.clone();
+        renamed.name = "bar".into();
+        assert_ne!(renamed, f);
+
+        let mut rebodied = f.clone();
+        rebodied.body = SymbolicExpression::atom_value(Value::Int(1));
+        assert_ne!(rebodied, f);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let make_function = |body: &str, context_name: &str| {
+            // parse something first, so that the expression ids differ
+            let source = format!("{} {}", context_name, body);
+            let body = crate::vm::ast::parse(
+                &QualifiedContractIdentifier::transient(),
+                &source,
+                ClarityVersion::Clarity2,
+                StacksEpochId::Epoch21,
+            )
+            .unwrap()
+            .remove(1);
+            DefinedFunction::new(
+                vec![("a".into(), TypeSignature::IntType)],
+                body,
+                DefineType::Public,
+                &"foo".into(),
+                context_name,
+            )
+        };
+
+        let f = make_function("(ok (+ a 1))", "one");
+        let g = make_function("(ok (+ a 1))", "another");
+        assert_ne!(f.get_identifier(), g.get_identifier());
+        assert_eq!(f.content_hash(), g.content_hash());
+
+        let changed = make_function("(ok (+ a 2))", "one");
+        assert_ne!(f.content_hash(), changed.content_hash());
+        let changed = make_function("(ok (+ a u1))", "one");
+        assert_ne!(f.content_hash(), changed.content_hash());
+
+        let mut renamed = f.clone();
+        renamed.name = "bar".into();
+        assert_ne!(f.content_hash(), renamed.content_hash());
+        let mut retyped = f.clone();
+        retyped.arg_types[0] = TypeSignature::UIntType;
+        assert_ne!(f.content_hash(), retyped.content_hash());
+        let mut redefined = f.clone();
+        redefined.define_type = DefineType::ReadOnly;
+        assert_ne!(f.content_hash(), redefined.content_hash());
+    }
+
+    #[test]
+    fn test_implemented_trait() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let mut f = parse_function(vec![], DefineType::Public, "(ok u1)");
+        assert_eq!(f.implemented_trait(), None);
+        // not recorded for ordinary functions
+        let serialized = serde_json::to_string(&f).unwrap();
+        assert!(!serialized.contains("implemented_trait"));
+
+        let ordinary = f.clone();
+        f.set_implemented_trait(trait_id.clone());
+        assert_eq!(f.implemented_trait(), Some(&trait_id));
+        assert_ne!(f, ordinary);
+
+        // kept in memory only
+        let serialized = serde_json::to_string(&f).unwrap();
+        let deserialized: DefinedFunction = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.implemented_trait(), None);
+    }
+
+    #[test]
+    fn test_extensions_not_serialized() {
+        let list_type = TypeSignature::list_of(TypeSignature::IntType, 5).unwrap();
+        let analyzed = parse_function(
+            vec![("xs".into(), list_type)],
+            DefineType::Private,
+            "(ok u1)",
+        );
+        let mut extended = analyzed.clone();
+        extended.set_rest_argument().unwrap();
+        extended.set_return_type(TypeSignature::BoolType);
+        extended.set_args_statically_checked(true);
+        extended.set_deprecation("use foo-v2".into());
+        extended.set_preconditions(vec![(
+            SymbolicExpression::atom_value(Value::Bool(true)),
+            Value::error(Value::UInt(1)).unwrap(),
+        )]);
+        assert_ne!(extended, analyzed);
+
+        // a stored function is stored as if nothing had been set, and is read
+        // back as the analyzed one
+        let serialized = serde_json::to_string(&extended).unwrap();
+        assert_eq!(serialized, serde_json::to_string(&analyzed).unwrap());
+        let deserialized: DefinedFunction = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, analyzed);
+    }
+
+    #[test]
+    fn test_try_new() {
+        let make_function = |arg_types| {
+            DefinedFunction::try_new(
+                vec!["a".into(), "b".into()],
+                arg_types,
+                SymbolicExpression::atom("a".into()),
+                DefineType::Private,
+                &"foo".into(),
+                "testing",
+            )
+        };
+
+        assert_eq!(
+            make_function(vec![TypeSignature::IntType, TypeSignature::UIntType]).unwrap(),
+            parse_function(
+                vec![
+                    ("a".into(), TypeSignature::IntType),
+                    ("b".into(), TypeSignature::UIntType)
+                ],
+                DefineType::Private,
+                "a"
+            )
+        );
+        // too few types
+        assert_eq!(
+            make_function(vec![TypeSignature::IntType]).unwrap_err(),
+            CheckErrors::DefineFunctionBadSignature.into()
+        );
+        // too many types
+        assert_eq!(
+            make_function(vec![TypeSignature::IntType; 3]).unwrap_err(),
+            CheckErrors::DefineFunctionBadSignature.into()
+        );
+
+        // a name that did not come from the parser
+        let bad_name: ClarityName = serde_json::from_str("\"1a\"").unwrap();
+        assert!(!bad_name.is_valid());
+        assert_eq!(
+            DefinedFunction::try_new(
+                vec!["a".into(), bad_name.clone()],
+                vec![TypeSignature::IntType; 2],
+                SymbolicExpression::atom("a".into()),
+                DefineType::Private,
+                &"foo".into(),
+                "testing",
+            )
+            .unwrap_err(),
+            CheckErrors::BadArgumentName("1a".into()).into()
+        );
+        let f = parse_function(
+            vec![(bad_name, TypeSignature::IntType)],
+            DefineType::Private,
+            "a",
+        );
+        assert_eq!(
+            f.validate(),
+            Err(CheckErrors::BadArgumentName("1a".into()).into())
+        );
+    }
+
+    #[test]
+    fn test_to_signature_json() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "traits".into(),
+            "getter".into(),
+        );
+        let pair = TupleTypeSignature::try_from(vec![
+            ("a".into(), TypeSignature::IntType),
+            ("b".into(), TypeSignature::BoolType),
+        ])
+        .unwrap();
+        let mut f = parse_function(
+            vec![
+                ("pair".into(), TypeSignature::TupleType(pair)),
+                (
+                    "g".into(),
+                    TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone())),
+                ),
+            ],
+            DefineType::Public,
+            "(ok true)",
+        );
+        let without_outputs = concat!(
+            r#"{"access":"public","args":["#,
+            r#"{"name":"pair","type":"(tuple (a int) (b bool))"},"#,
+            r#"{"name":"g","type":"<S1G2081040G2081040G2081040G208105NK8PE5.traits.getter>"}"#,
+            r#"],"name":"foo"}"#
+        );
+        assert_eq!(f.to_signature_json().to_string(), without_outputs);
+
+        f.set_return_type(
+            TypeSignature::new_response(TypeSignature::BoolType, TypeSignature::UIntType).unwrap(),
+        );
+        assert_eq!(
+            f.to_signature_json()["outputs"],
+            json!({ "type": "(response bool uint)" })
+        );
+    }
+
+    #[test]
+    fn test_argument_type_errors() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity1,
+        );
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "traits".into(),
+            "getter".into(),
+        );
+        // a Clarity 1 signature that was not canonicalized for epoch 2.1
+        let f = parse_function(
+            vec![
+                ("n".into(), TypeSignature::IntType),
+                (
+                    "g".into(),
+                    TypeSignature::TraitReferenceType(trait_id.clone()),
+                ),
+            ],
+            DefineType::Public,
+            "(ok true)",
+        );
+        let contract = Value::Principal(PrincipalData::Contract(
+            QualifiedContractIdentifier::local("impl").unwrap(),
+        ));
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            // a value of the wrong type
+            assert_eq!(
+                f.execute_apply(&[Value::UInt(1), contract.clone()], env),
+                Err(
+                    CheckErrors::TypeValueErrorAt(0, TypeSignature::IntType, Value::UInt(1)).into()
+                )
+            );
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2)], env),
+                Err(CheckErrors::TypeValueErrorAt(
+                    1,
+                    TypeSignature::TraitReferenceType(trait_id.clone()),
+                    Value::Int(2)
+                )
+                .into())
+            );
+        });
+
+        // a contract is not admitted by a stored Clarity 1 trait parameter
+        // from epoch 2.1, and keeps the error it has always had
+        let callable = Value::CallableContract(CallableData {
+            contract_identifier: QualifiedContractIdentifier::local("impl").unwrap(),
+            trait_identifier: Some(trait_id.clone()),
+        });
+        for epoch in [StacksEpochId::Epoch21, StacksEpochId::Epoch22] {
+            with_test_env(&contract_context, epoch, |env| {
+                for value in [contract.clone(), callable.clone()] {
+                    assert_eq!(
+                        f.execute_apply(&[Value::Int(1), value.clone()], env),
+                        Err(CheckErrors::TypeValueErrorAt(
+                            1,
+                            TypeSignature::TraitReferenceType(trait_id.clone()),
+                            value
+                        )
+                        .into())
+                    );
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_nullary_function() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function = |body: &str| parse_function(vec![], DefineType::Private, body);
+        let getter = make_function("(ok u2)");
+        let early = make_function("(begin (asserts! false (err u1)) (ok u2))");
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                getter.execute_apply(&[], env),
+                Ok(Value::okay(Value::UInt(2)).unwrap())
+            );
+            assert_eq!(
+                early.execute_apply(&[], env),
+                Ok(Value::error(Value::UInt(1)).unwrap())
+            );
+            assert_eq!(
+                early.execute_apply_raw(&[], env),
+                Err(Error::ShortReturn(ShortReturnType::AssertionFailed(
+                    Value::error(Value::UInt(1)).unwrap()
+                )))
+            );
+            // arguments are still refused
+            assert_eq!(
+                getter.execute_apply(&[Value::UInt(1)], env),
+                Err(CheckErrors::IncorrectArgumentCount(0, 1).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_clone_with_name() {
+        let mut f = DefinedFunction::new(
+            vec![("a".into(), TypeSignature::IntType)],
+            SymbolicExpression::atom("a".into()),
+            DefineType::ReadOnly,
+            &"foo".into(),
+            "template",
+        );
+        f.set_args_statically_checked(true);
+
+        let clone = f.clone_with_name(&"prefix-foo".into(), "generated");
+        assert_eq!(clone.get_name().as_str(), "prefix-foo");
+        assert_eq!(clone.get_identifier().to_string(), "generated:prefix-foo");
+        assert_eq!(clone.get_body(), f.get_body());
+        assert_eq!(clone.get_arg_types(), f.get_arg_types());
+        assert_eq!(clone.define_type, f.define_type);
+        assert!(!clone.args_statically_checked());
+        // the original is unchanged
+        assert_eq!(f.get_identifier().to_string(), "template:foo");
+    }
+
+    #[test]
+    fn test_define_type_predicates() {
+        let make_function = |define_type| parse_function(vec![], define_type, "1");
+
+        let f = make_function(DefineType::Private);
+        assert!(f.is_private() && !f.is_public() && !f.is_read_only());
+        assert_eq!(f.define_type_name(), "private");
+
+        let f = make_function(DefineType::Public);
+        assert!(!f.is_private() && f.is_public() && !f.is_read_only());
+        assert_eq!(f.define_type_name(), "public");
+
+        let f = make_function(DefineType::ReadOnly);
+        assert!(!f.is_private() && f.is_public() && f.is_read_only());
+        assert_eq!(f.define_type_name(), "read-only");
+    }
+
+    #[test]
+    fn test_validate_duplicate_arguments() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let f = parse_function(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+                ("a".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+
+        // rejected without ever being applied
+        assert_eq!(
+            f.validate(),
+            Err(CheckErrors::NameAlreadyUsed("a".into()).into())
+        );
+
+        // the runtime check remains as a backstop
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1), Value::Int(2), Value::Int(3)], env),
+                Err(CheckErrors::NameAlreadyUsed("a".into()).into())
+            );
+        });
+
+        let g = parse_named_function(
+            "bar",
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::IntType),
+            ],
+            DefineType::Private,
+            "a",
+        );
+        assert_eq!(g.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_define_type_round_trip() {
+        for (define_type, keyword) in [
+            (DefineType::Public, "public"),
+            (DefineType::ReadOnly, "read-only"),
+            (DefineType::Private, "private"),
+        ] {
+            assert_eq!(define_type.to_string(), keyword);
+            assert_eq!(DefineType::from_str(keyword).unwrap(), define_type);
+        }
+
+        for bad in ["", "Public", "readonly", "define-public"] {
+            assert!(matches!(
+                DefineType::from_str(bad).unwrap_err(),
+                Error::Runtime(RuntimeErrorType::ParseError(_), _)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_define_type_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct External(#[serde(with = "define_type_keyword")] DefineType);
+
+        for (define_type, keyword, stored) in [
+            (DefineType::Public, "\"public\"", "\"Public\""),
+            (DefineType::ReadOnly, "\"read-only\"", "\"ReadOnly\""),
+            (DefineType::Private, "\"private\"", "\"Private\""),
+        ] {
+            // the stored form is unchanged
+            assert_eq!(serde_json::to_string(&define_type).unwrap(), stored);
+            assert_eq!(
+                serde_json::from_str::<DefineType>(stored).unwrap(),
+                define_type
+            );
+            assert!(serde_json::from_str::<DefineType>(keyword).is_err());
+
+            let external = External(define_type.clone());
+            assert_eq!(serde_json::to_string(&external).unwrap(), keyword);
+            assert_eq!(serde_json::from_str::<External>(keyword).unwrap(), external);
+            assert_eq!(serde_json::from_str::<External>(stored).unwrap(), external);
+        }
+        assert!(serde_json::from_str::<External>("\"readonly\"").is_err());
+    }
+
+    #[test]
+    fn test_apply_with_sponsor() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let make_function =
+            |define_type| parse_named_function("get-sponsor", vec![], define_type, "tx-sponsor?");
+        let sponsor = PrincipalData::Standard(StandardPrincipalData::transient());
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::vm::types::StandardPrincipalData;
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            let f = make_function(DefineType::ReadOnly);
+            assert_eq!(f.apply(vec![], env).unwrap(), Value::none());
+            assert_eq!(
+                f.apply_with_sponsor(&[], env, Some(sponsor.clone()))
+                    .unwrap(),
+                Value::some(Value::Principal(sponsor.clone())).unwrap()
+            );
+            // the environment's own sponsor is left untouched
+            assert_eq!(env.sponsor, None);
+
+            let f = make_function(DefineType::Private);
+            assert!(matches!(
+                f.apply_with_sponsor(&[], env, Some(sponsor)).unwrap_err(),
+                Error::Unchecked(CheckErrors::NoSuchPublicFunction(..))
+            ));
+        });
+    }
 
     #[test]
-    fn test_implicit_cast() {
-        // principal -> <trait>
+    fn test_extend_with_args() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
         let trait_identifier = TraitIdentifier::parse_fully_qualified(
             "SP2PABAF9FTAJYNFZH93XENAJ8FVY99RRM50D2JG9.nft-trait.nft-trait",
         )
         .unwrap();
-        let trait_ty =
-            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier.clone()));
-        let contract_identifier = QualifiedContractIdentifier::parse(
-            "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR.contract",
-        )
-        .unwrap();
-        let contract_identifier2 = QualifiedContractIdentifier::parse(
-            "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR.contract2",
-        )
-        .unwrap();
-        let contract = Value::CallableContract(CallableData {
-            contract_identifier: contract_identifier.clone(),
-            trait_identifier: None,
-        });
-        let contract2 = Value::CallableContract(CallableData {
-            contract_identifier: contract_identifier2.clone(),
-            trait_identifier: None,
+        let contract_identifier =
+            QualifiedContractIdentifier::parse("SP000000000000000000002Q6VF78.nft-contract")
+                .unwrap();
+        let arguments: Vec<ClarityName> = vec!["amount".into(), "nft".into()];
+        let arg_types = vec![
+            TypeSignature::UIntType,
+            TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier.clone())),
+        ];
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            let mut context = LocalContext::new();
+            context
+                .extend_with_args(
+                    &arguments,
+                    &arg_types,
+                    &[
+                        Value::UInt(5),
+                        Value::Principal(PrincipalData::Contract(contract_identifier.clone())),
+                    ],
+                    env,
+                )
+                .unwrap();
+            assert_eq!(context.lookup_variable("amount"), Some(&Value::UInt(5)));
+            assert_eq!(
+                context.lookup_callable_contract("nft"),
+                Some(&CallableData {
+                    contract_identifier: contract_identifier.clone(),
+                    trait_identifier: Some(trait_identifier.clone()),
+                })
+            );
+
+            let mut context = LocalContext::new();
+            assert_eq!(
+                context.extend_with_args(
+                    &arguments,
+                    &arg_types,
+                    &[
+                        Value::Int(5),
+                        Value::Principal(PrincipalData::Contract(contract_identifier.clone())),
+                    ],
+                    env,
+                ),
+                Err(
+                    CheckErrors::TypeValueErrorAt(0, TypeSignature::UIntType, Value::Int(5)).into()
+                )
+            );
+            assert_eq!(
+                context.extend_with_args(&arguments, &arg_types, &[Value::UInt(5)], env),
+                Err(CheckErrors::IncorrectArgumentCount(2, 1).into())
+            );
         });
-        let cast_contract = clarity2_implicit_cast(&trait_ty, &contract).unwrap();
-        let cast_trait = cast_contract.expect_callable().unwrap();
-        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
-        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+    }
 
-        // (optional principal) -> (optional <trait>)
-        let optional_ty = TypeSignature::new_option(trait_ty.clone()).unwrap();
-        let optional_contract = Value::some(contract.clone()).unwrap();
-        let cast_optional = clarity2_implicit_cast(&optional_ty, &optional_contract).unwrap();
-        match &cast_optional.expect_optional().unwrap().unwrap() {
-            Value::CallableContract(CallableData {
-                contract_identifier: contract_id,
-                trait_identifier: trait_id,
-            }) => {
-                assert_eq!(contract_id, &contract_identifier);
-                assert_eq!(trait_id.as_ref().unwrap(), &trait_identifier);
-            }
-            other => panic!("expected Value::CallableContract, got {:?}", other),
-        }
+    #[test]
+    fn test_validate_args() {
+        let trait_identifier = TraitIdentifier::parse_fully_qualified(
+            "SP2PABAF9FTAJYNFZH93XENAJ8FVY99RRM50D2JG9.nft-trait.nft-trait",
+        )
+        .unwrap();
+        let function = parse_named_function(
+            "transfer",
+            vec![
+                ("amount".into(), TypeSignature::UIntType),
+                (
+                    "nft".into(),
+                    TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier)),
+                ),
+            ],
+            DefineType::Public,
+            "(ok u1)",
+        );
+        let epoch = StacksEpochId::Epoch21;
+        let nft = Value::Principal(PrincipalData::Contract(
+            QualifiedContractIdentifier::parse("SP000000000000000000002Q6VF78.nft-contract")
+                .unwrap(),
+        ));
 
-        // (ok principal) -> (ok <trait>)
-        let response_ok_ty =
-            TypeSignature::new_response(trait_ty.clone(), TypeSignature::UIntType).unwrap();
-        let response_contract = Value::okay(contract.clone()).unwrap();
-        let cast_response = clarity2_implicit_cast(&response_ok_ty, &response_contract).unwrap();
-        let cast_trait = cast_response
-            .expect_result_ok()
-            .unwrap()
-            .expect_callable()
+        function
+            .validate_args(&epoch, &[Value::UInt(1), nft.clone()])
             .unwrap();
-        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
-        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+        assert_eq!(
+            function.validate_args(&epoch, &[Value::UInt(1)]),
+            Err(CheckErrors::IncorrectArgumentCount(2, 1).into())
+        );
+        assert_eq!(
+            function.validate_args(&epoch, &[Value::Int(1), nft]),
+            Err(CheckErrors::TypeValueErrorAt(0, TypeSignature::UIntType, Value::Int(1)).into())
+        );
+    }
 
-        // (err principal) -> (err <trait>)
-        let response_err_ty =
-            TypeSignature::new_response(TypeSignature::UIntType, trait_ty.clone()).unwrap();
-        let response_contract = Value::error(contract.clone()).unwrap();
-        let cast_response = clarity2_implicit_cast(&response_err_ty, &response_contract).unwrap();
-        let cast_trait = cast_response
-            .expect_result_err()
-            .unwrap()
-            .expect_callable()
-            .unwrap();
-        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
-        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+    #[test]
+    fn test_deprecation() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let mut f = parse_function(vec![], DefineType::Public, "(ok u1)");
+        assert_eq!(f.deprecation(), None);
 
-        // (list principal) -> (list <trait>)
-        let list_ty = TypeSignature::list_of(trait_ty.clone(), 4).unwrap();
-        let list_contract = Value::list_from(vec![contract.clone(), contract2.clone()]).unwrap();
-        let cast_list = clarity2_implicit_cast(&list_ty, &list_contract).unwrap();
-        let items = cast_list.expect_list().unwrap();
-        for item in items {
-            let cast_trait = item.expect_callable().unwrap();
-            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
-        }
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            f.set_deprecation("use foo-v2".into());
+            // nothing is recorded until events are enabled
+            f.execute_apply(&[], env).unwrap();
+            assert!(env.take_vm_events().is_empty());
 
-        // {a: principal} -> {a: <trait>}
-        let a_name = ClarityName::from("a");
-        let tuple_ty = TypeSignature::TupleType(
-            TupleTypeSignature::try_from(vec![(a_name.clone(), trait_ty.clone())]).unwrap(),
-        );
-        let contract_tuple_ty = TypeSignature::TupleType(
-            TupleTypeSignature::try_from(vec![(a_name.clone(), TypeSignature::PrincipalType)])
-                .unwrap(),
-        );
-        let mut data_map = BTreeMap::new();
-        data_map.insert(a_name.clone(), contract.clone());
-        let tuple_contract = Value::Tuple(TupleData {
-            type_signature: TupleTypeSignature::try_from(vec![(
-                a_name.clone(),
-                TypeSignature::PrincipalType,
-            )])
-            .unwrap(),
-            data_map,
+            env.global_context.enable_vm_events();
+            assert_eq!(
+                f.execute_apply(&[], env),
+                Ok(Value::okay(Value::UInt(1)).unwrap())
+            );
+            assert_eq!(
+                env.take_vm_events(),
+                vec![VmEvent::Deprecated {
+                    id: f.get_identifier(),
+                    note: "use foo-v2".into(),
+                }]
+            );
+            assert!(env.take_vm_events().is_empty());
         });
-        let cast_tuple = clarity2_implicit_cast(&tuple_ty, &tuple_contract).unwrap();
-        let cast_trait = cast_tuple
-            .expect_tuple()
-            .unwrap()
-            .get(&a_name)
-            .unwrap()
-            .clone()
-            .expect_callable()
-            .unwrap();
-        assert_eq!(&cast_trait.contract_identifier, &contract_identifier);
-        assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
+    }
 
-        // (list (optional principal)) -> (list (optional <trait>))
-        let list_opt_ty = TypeSignature::list_of(optional_ty.clone(), 4).unwrap();
-        let list_opt_contract = Value::list_from(vec![
-            Value::some(contract.clone()).unwrap(),
-            Value::some(contract2.clone()).unwrap(),
-            Value::none(),
-        ])
-        .unwrap();
-        let cast_list = clarity2_implicit_cast(&list_opt_ty, &list_opt_contract).unwrap();
-        let items = cast_list.expect_list().unwrap();
-        for item in items {
-            match item.expect_optional().unwrap() {
-                Some(cast_opt) => {
-                    let cast_trait = cast_opt.expect_callable().unwrap();
-                    assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
-                }
-                None => (),
-            }
-        }
+    #[test]
+    fn test_is_compatible_upgrade() {
+        let epoch = StacksEpochId::Epoch21;
+        let ascii = |len: u32| {
+            TypeSignature::SequenceType(SequenceSubtype::StringType(
+                crate::vm::types::StringSubtype::ASCII(len.try_into().unwrap()),
+            ))
+        };
+        let make_function = |define_type, arg_types: Vec<TypeSignature>| {
+            parse_function(
+                arg_types
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, arg_type)| (format!("a{}", i).as_str().into(), arg_type))
+                    .collect(),
+                define_type,
+                "(ok u1)",
+            )
+        };
+        let old = make_function(DefineType::Public, vec![TypeSignature::UIntType, ascii(10)]);
 
-        // (list (response principal uint)) -> (list (response <trait> uint))
-        let list_res_ty = TypeSignature::list_of(response_ok_ty.clone(), 4).unwrap();
-        let list_res_contract = Value::list_from(vec![
-            Value::okay(contract.clone()).unwrap(),
-            Value::okay(contract2.clone()).unwrap(),
-            Value::okay(contract2.clone()).unwrap(),
-        ])
-        .unwrap();
-        let cast_list = clarity2_implicit_cast(&list_res_ty, &list_res_contract).unwrap();
-        let items = cast_list.expect_list().unwrap();
-        for item in items {
-            let cast_trait = item.expect_result_ok().unwrap().expect_callable().unwrap();
-            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
-        }
+        // widening an argument type is compatible
+        let widened = make_function(DefineType::Public, vec![TypeSignature::UIntType, ascii(20)]);
+        assert_eq!(widened.is_compatible_upgrade(&epoch, &old), Ok(()));
 
-        // (list (response uint principal)) -> (list (response uint <trait>))
-        let list_res_ty = TypeSignature::list_of(response_err_ty.clone(), 4).unwrap();
-        let list_res_contract = Value::list_from(vec![
-            Value::error(contract.clone()).unwrap(),
-            Value::error(contract2.clone()).unwrap(),
-            Value::error(contract2.clone()).unwrap(),
-        ])
-        .unwrap();
-        let cast_list = clarity2_implicit_cast(&list_res_ty, &list_res_contract).unwrap();
-        let items = cast_list.expect_list().unwrap();
-        for item in items {
-            let cast_trait = item.expect_result_err().unwrap().expect_callable().unwrap();
-            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
-        }
+        let narrowed = make_function(DefineType::Public, vec![TypeSignature::UIntType, ascii(5)]);
+        assert_eq!(
+            narrowed.is_compatible_upgrade(&epoch, &old),
+            Err(CheckErrors::IncompatibleUpgradeArgument(
+                1,
+                ascii(5),
+                ascii(10)
+            ))
+        );
 
-        // (optional (list (response uint principal))) -> (optional (list (response uint <trait>)))
-        let list_res_ty = TypeSignature::list_of(response_err_ty.clone(), 4).unwrap();
-        let opt_list_res_ty = TypeSignature::new_option(list_res_ty).unwrap();
-        let list_res_contract = Value::list_from(vec![
-            Value::error(contract.clone()).unwrap(),
-            Value::error(contract2.clone()).unwrap(),
-            Value::error(contract2.clone()).unwrap(),
-        ])
-        .unwrap();
-        let opt_list_res_contract = Value::some(list_res_contract).unwrap();
-        let cast_opt = clarity2_implicit_cast(&opt_list_res_ty, &opt_list_res_contract).unwrap();
-        let inner = cast_opt.expect_optional().unwrap().unwrap();
-        let items = inner.expect_list().unwrap();
-        for item in items {
-            let cast_trait = item.expect_result_err().unwrap().expect_callable().unwrap();
-            assert_eq!(&cast_trait.trait_identifier.unwrap(), &trait_identifier);
-        }
+        let read_only = make_function(
+            DefineType::ReadOnly,
+            vec![TypeSignature::UIntType, ascii(10)],
+        );
+        assert_eq!(
+            read_only.is_compatible_upgrade(&epoch, &old),
+            Err(CheckErrors::IncompatibleUpgradeDefineType(
+                "public".into(),
+                "read-only".into()
+            ))
+        );
+        assert_eq!(
+            old.is_compatible_upgrade(&epoch, &read_only),
+            Err(CheckErrors::IncompatibleUpgradeDefineType(
+                "read-only".into(),
+                "public".into()
+            ))
+        );
 
-        // (optional (optional principal)) -> (optional (optional <trait>))
-        let optional_optional_ty = TypeSignature::new_option(optional_ty.clone()).unwrap();
-        let optional_contract = Value::some(contract.clone()).unwrap();
-        let optional_optional_contract = Value::some(optional_contract.clone()).unwrap();
-        let cast_optional =
-            clarity2_implicit_cast(&optional_optional_ty, &optional_optional_contract).unwrap();
+        let fewer = make_function(DefineType::Public, vec![TypeSignature::UIntType]);
+        assert_eq!(
+            fewer.is_compatible_upgrade(&epoch, &old),
+            Err(CheckErrors::IncorrectArgumentCount(2, 1))
+        );
+    }
 
-        match &cast_optional
-            .expect_optional()
-            .unwrap()
-            .unwrap()
-            .expect_optional()
-            .unwrap()
-            .unwrap()
-        {
-            Value::CallableContract(CallableData {
-                contract_identifier: contract_id,
-                trait_identifier: trait_id,
-            }) => {
-                assert_eq!(contract_id, &contract_identifier);
-                assert_eq!(trait_id.as_ref().unwrap(), &trait_identifier);
-            }
-            other => panic!("expected Value::CallableContract, got {:?}", other),
-        }
+    #[test]
+    fn test_always_errors() {
+        let make_function = |body: &str| {
+            parse_function(
+                vec![("a".into(), TypeSignature::BoolType)],
+                DefineType::Public,
+                body,
+            )
+        };
+
+        assert!(make_function("(err u1)").always_errors());
+        assert!(make_function("(begin (print a) (if a (err u1) (err u2)))").always_errors());
+        assert!(make_function("(let ((b a)) (try! (err u1)))").always_errors());
+        assert!(make_function("(match (some a) value (err u1) (begin (err u2)))").always_errors());
+
+        // a mixed branch
+        assert!(!make_function("(if a (err u1) (ok u2))").always_errors());
+        assert!(!make_function("(match (ok a) value (ok u1) e (err u2))").always_errors());
+        assert!(!make_function("(ok u1)").always_errors());
+        // unsure, since `asserts!` may not fail
+        assert!(!make_function("(begin (asserts! a (err u1)) (ok u1))").always_errors());
     }
 
     #[test]
-    fn test_canonicalize_defined_function() {
-        let trait_id = TraitIdentifier::new(
-            StandardPrincipalData::transient(),
-            "my-contract".into(),
-            "my-trait".into(),
+    fn test_preconditions() {
+        let contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
         );
-        let mut f = DefinedFunction::new(
-            vec![(
-                "a".into(),
-                TypeSignature::TraitReferenceType(trait_id.clone()),
-            )],
-            SymbolicExpression::atom_value(Value::Int(3)),
+        let mut f = parse_function(
+            vec![("a".into(), TypeSignature::IntType)],
             DefineType::Public,
-            &"foo".into(),
-            "testing",
+            "(ok a)",
+        );
+        let too_small = Value::error(Value::UInt(1)).unwrap();
+        let too_large = Value::error(Value::UInt(2)).unwrap();
+        f.set_preconditions(vec![
+            (parse("(> a 0)"), too_small.clone()),
+            (parse("(< a 10)"), too_large.clone()),
+        ]);
+        assert_eq!(f.preconditions().len(), 2);
+
+        with_test_env(&contract_context, StacksEpochId::Epoch21, |env| {
+            assert_eq!(
+                f.execute_apply(&[Value::Int(5)], env),
+                Ok(Value::okay(Value::Int(5)).unwrap())
+            );
+            assert_eq!(f.execute_apply(&[Value::Int(20)], env), Ok(too_large));
+            assert_eq!(
+                f.execute_apply_raw(&[Value::Int(20)], env),
+                Err(Error::ShortReturn(ShortReturnType::AssertionFailed(
+                    Value::error(Value::UInt(2)).unwrap()
+                )))
+            );
+
+            // the first failing precondition wins
+            f.set_preconditions(vec![
+                (parse("(> a 0)"), too_small.clone()),
+                (parse("(< a 0)"), Value::error(Value::UInt(3)).unwrap()),
+            ]);
+            assert_eq!(f.execute_apply(&[Value::Int(-1)], env), Ok(too_small));
+
+            // a precondition must be a bool
+            f.set_preconditions(vec![(parse("a"), Value::none())]);
+            assert_eq!(
+                f.execute_apply(&[Value::Int(1)], env),
+                Err(CheckErrors::TypeValueError(TypeSignature::BoolType, Value::Int(1)).into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_trait_reference_args() {
+        let trait_identifier = TraitIdentifier::parse_fully_qualified(
+            "SP2PABAF9FTAJYNFZH93XENAJ8FVY99RRM50D2JG9.nft-trait.nft-trait",
+        )
+        .unwrap();
+        let f = parse_named_function(
+            "transfer",
+            vec![
+                ("amount".into(), TypeSignature::UIntType),
+                (
+                    "nft".into(),
+                    TypeSignature::CallableType(CallableSubtype::Trait(trait_identifier.clone())),
+                ),
+                (
+                    "legacy".into(),
+                    TypeSignature::TraitReferenceType(trait_identifier),
+                ),
+            ],
+            DefineType::Public,
+            "(ok u1)",
         );
-        f.canonicalize_types(&StacksEpochId::Epoch21);
         assert_eq!(
-            f.arg_types[0],
-            TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone()))
+            f.trait_reference_args(),
+            vec![
+                ("nft".into(), "nft-trait".into()),
+                ("legacy".into(), "nft-trait".into())
+            ]
+        );
+
+        let plain = parse_named_function(
+            "plain",
+            vec![("amount".into(), TypeSignature::UIntType)],
+            DefineType::Public,
+            "(ok u1)",
         );
+        assert!(plain.trait_reference_args().is_empty());
     }
 }
@@ -14,19 +14,26 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt;
 use std::mem::replace;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use serde::Serialize;
 use stacks_common::consts::CHAIN_ID_TESTNET;
 use stacks_common::types::chainstate::StacksBlockId;
 use stacks_common::types::StacksEpochId;
+use stacks_common::util::hash::Sha512Trunc256Sum;
 
 use super::EvalHook;
 use crate::vm::ast::{ASTRules, ContractAST};
-use crate::vm::callables::{DefinedFunction, FunctionIdentifier};
+use crate::vm::callables::{
+    bind_arguments, DefineType, DefinedFunction, FunctionIdentifier, FunctionTracer, HostFunction,
+    TraceEvent,
+};
 use crate::vm::contracts::Contract;
 use crate::vm::costs::cost_functions::ClarityCostFunction;
 use crate::vm::costs::{
@@ -41,11 +48,11 @@ use crate::vm::errors::{
     CheckErrors, InterpreterError, InterpreterResult as Result, RuntimeErrorType,
 };
 use crate::vm::events::*;
-use crate::vm::representations::{ClarityName, ContractName, SymbolicExpression};
+use crate::vm::representations::{ClarityName, ContractName, Span, SymbolicExpression};
 use crate::vm::types::signatures::FunctionSignature;
 use crate::vm::types::{
     AssetIdentifier, BuffData, CallableData, OptionalData, PrincipalData,
-    QualifiedContractIdentifier, TraitIdentifier, TypeSignature, Value,
+    QualifiedContractIdentifier, TraitIdentifier, TupleData, TypeSignature, Value,
 };
 use crate::vm::version::ClarityVersion;
 use crate::vm::{ast, eval, is_reserved, stx_transfer_consolidated};
@@ -202,6 +209,170 @@ pub struct GlobalContext<'a, 'hooks> {
     /// This is the chain ID of the transaction
     pub chain_id: u32,
     pub eval_hooks: Option<Vec<&'hooks mut dyn EvalHook>>,
+    /// How execution departs from consensus, if at all. See
+    /// `set_execution_options`.
+    options: ExecutionOptions,
+    /// Whether `options` are fixed to consensus. See `lock_consensus_options`.
+    options_locked: bool,
+    /// How many times each user function has been applied, for profiling.
+    /// `None` unless enabled with `enable_invocation_counts`.
+    invocation_counts: Option<HashMap<FunctionIdentifier, u64>>,
+    /// Called on entry to and exit from every user function application.
+    function_tracer: Option<FunctionTracer<'hooks>>,
+    /// Whether a contract implements a trait, for contracts passed as trait
+    /// arguments in this transaction while trait checks are eager. See
+    /// `ExecutionOptions::eager_trait_checks`.
+    trait_conformance: HashMap<(QualifiedContractIdentifier, TraitIdentifier), bool>,
+    /// Pure functions whose results are cached. See `enable_memoization`.
+    memoized_functions: HashSet<FunctionIdentifier>,
+    /// The results of memoized functions in this transaction, with the
+    /// arguments they were computed from and what computing them cost.
+    memo_cache: HashMap<FunctionIdentifier, Vec<(Vec<Value>, Value, ExecutionCost)>>,
+    /// Native functions provided by the host. See `register_host_function`.
+    host_functions: HashMap<ClarityName, Rc<HostFunction>>,
+    /// Values resolved by the host for suspended host calls, in order, and how
+    /// many of them this transaction has used. See `resume_host_call`.
+    host_resolutions: Vec<Value>,
+    host_resolutions_used: usize,
+    /// The data vars and maps accessed so far. `None` unless enabled with
+    /// `enable_access_list`.
+    access_list: Option<Vec<StateAccess>>,
+    /// Time spent applying each user function. `None` unless enabled with
+    /// `enable_phase_timings`.
+    phase_timings: Option<HashMap<FunctionIdentifier, PhaseTimings>>,
+    /// Events recorded since the last `take_vm_events`. `None` unless enabled
+    /// with `enable_vm_events`.
+    vm_events: Option<Vec<VmEvent>>,
+}
+
+/// Whether a data var or map was read, or written. See `StateAccess`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateAccessKind {
+    Read,
+    /// Includes `map-insert` and `map-delete`, which also read the entry.
+    Write,
+}
+
+/// A data var or map accessed during evaluation, as recorded by
+/// `GlobalContext::enable_access_list`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StateAccess {
+    pub contract_identifier: QualifiedContractIdentifier,
+    /// The name of the data var or map.
+    pub name: ClarityName,
+    pub kind: StateAccessKind,
+}
+
+/// A notable occurrence during evaluation that is not an error, recorded
+/// for the host to collect with `GlobalContext::take_vm_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmEvent {
+    /// A function marked with `DefinedFunction::set_deprecation` was applied.
+    Deprecated {
+        id: FunctionIdentifier,
+        note: String,
+    },
+}
+
+/// The time spent binding the arguments of a user function and evaluating its
+/// body, summed over its applications, as recorded by
+/// `GlobalContext::enable_phase_timings`. The evaluation time of a function
+/// includes that of the functions it calls.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PhaseTimings {
+    pub calls: u64,
+    pub binding: Duration,
+    pub evaluation: Duration,
+}
+
+/// How a `GlobalContext` executes contracts where a host may depart from
+/// consensus, e.g. for tooling, sandboxes and fuzzing. The default is
+/// consensus execution, which block processing requires: see `is_consensus`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionOptions {
+    /// Whether functions may be passed trait references, for dynamic
+    /// dispatch. While they may not, applying a function to a trait reference
+    /// fails with `CheckErrors::DynamicDispatchDisabled`. Allowed by default.
+    pub allow_dynamic_dispatch: bool,
+    /// Whether naming a function argument like a binding of a context
+    /// enclosing the one it is bound in, such as the base context given to
+    /// `DefinedFunction::execute_apply_in` and its parents, fails with
+    /// `CheckErrors::NameAlreadyUsed`. A binding of the base context itself is
+    /// never allowed. Off by default.
+    pub strict_shadowing: bool,
+    /// Whether a user function may be applied to a `uint` argument for an
+    /// `int` parameter, or the reverse. Such an argument is then converted and
+    /// bound as the parameter's type if it is in range, and fails with
+    /// `CheckErrors::TypeValueErrorAt` otherwise, as it always does while
+    /// this is off, the default.
+    pub coerce_integer_args: bool,
+    /// Whether a user function may be applied to a bare value for an
+    /// `optional` parameter, or for a `response` parameter whose `ok` type
+    /// admits the value and whose `err` type does not. Such a value is then
+    /// bound wrapped in `some` or `ok`, respectively. Off by default, failing
+    /// with `CheckErrors::TypeValueErrorAt`.
+    pub wrap_args: bool,
+    /// Rejects any argument of a user function that is deeper or holds more
+    /// values than allowed with `CheckErrors::ValueTooLarge`, before it is
+    /// bound. Unlike type checking, this bounds the absolute size of a value,
+    /// not its shape. No limit by default.
+    pub arg_size_limit: Option<ArgumentSizeLimit>,
+    /// Whether a contract passed for a trait parameter is checked to implement
+    /// the whole trait as soon as it is bound, failing with
+    /// `CheckErrors::BadTraitImplementation` if it does not. Otherwise only
+    /// the methods called through the reference are checked, when they are
+    /// called, which is what consensus requires. Off by default.
+    pub eager_trait_checks: bool,
+    /// Derives the VRF seed of every block from this seed rather than reading
+    /// it from the chain, so that fuzzing harnesses can reproduce runs of
+    /// contracts that use `vrf-seed` as a source of randomness. See
+    /// `GlobalContext::seeded_vrf_seed`.
+    pub seed: Option<u64>,
+    /// User functions whose call sites have been statically verified by the
+    /// analysis pass, so their arguments need not be re-checked against their
+    /// declared types on every application. See
+    /// `DefinedFunction::execute_apply_trusted`.
+    pub verified_functions: HashSet<FunctionIdentifier>,
+    /// Whether a name that is neither a native nor a contract's own function
+    /// resolves to the host function registered under it with
+    /// `GlobalContext::register_host_function`. A host function is charged
+    /// like the application of a user function of as many arguments. Off by
+    /// default, failing with `CheckErrors::UndefinedFunction`.
+    pub host_functions: bool,
+}
+
+impl Default for ExecutionOptions {
+    fn default() -> ExecutionOptions {
+        ExecutionOptions {
+            allow_dynamic_dispatch: true,
+            strict_shadowing: false,
+            coerce_integer_args: false,
+            wrap_args: false,
+            arg_size_limit: None,
+            eager_trait_checks: false,
+            seed: None,
+            verified_functions: HashSet::new(),
+            host_functions: false,
+        }
+    }
+}
+
+impl ExecutionOptions {
+    /// Whether these options are those of consensus execution, the default.
+    pub fn is_consensus(&self) -> bool {
+        *self == ExecutionOptions::default()
+    }
+}
+
+/// Limits on the structure of each argument applied to a user function, as
+/// set with `ExecutionOptions::arg_size_limit`. The depth of a value is
+/// 1 for an atom, plus 1 for each level of lists, tuples, optionals and
+/// responses it is nested in; its node count is the number of values it holds,
+/// including itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArgumentSizeLimit {
+    pub max_depth: usize,
+    pub max_nodes: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -228,7 +399,11 @@ pub struct LocalContext<'a> {
     pub function_context: Option<&'a LocalContext<'a>>,
     pub parent: Option<&'a LocalContext<'a>>,
     pub variables: HashMap<ClarityName, Value>,
-    pub callable_contracts: HashMap<ClarityName, CallableData>,
+    /// Ordered by name, so that iteration is deterministic.
+    pub callable_contracts: BTreeMap<ClarityName, CallableData>,
+    /// The caller a function was applied by, recorded on the function's root
+    /// context. Nested contexts inherit it from their parent.
+    caller: Option<PrincipalData>,
     depth: u16,
 }
 
@@ -236,6 +411,16 @@ pub struct CallStack {
     stack: Vec<FunctionIdentifier>,
     set: HashSet<FunctionIdentifier>,
     apply_depth: usize,
+    /// The stack as it was when the most recent error was raised, kept after
+    /// the stack itself has unwound. See `remove_after`.
+    failed_stack: Option<Vec<FunctionIdentifier>>,
+    /// The span of the innermost expression whose evaluation failed. See
+    /// `record_failed_span`.
+    failed_span: Option<Span>,
+    /// The frames popped so far while unwinding from an error, innermost
+    /// first, and the span it was raised at.
+    unwound: Vec<FunctionIdentifier>,
+    unwound_span: Option<Span>,
 }
 
 pub type StackTrace = Vec<FunctionIdentifier>;
@@ -845,6 +1030,82 @@ impl<'a, 'hooks> OwnedEnvironment<'a, 'hooks> {
             self.context.eval_hooks = Some(vec![hook]);
         }
     }
+
+    pub fn execution_options(&self) -> &ExecutionOptions {
+        self.context.execution_options()
+    }
+
+    pub fn set_execution_options(&mut self, options: ExecutionOptions) -> Result<()> {
+        self.context.set_execution_options(options)
+    }
+
+    pub fn lock_consensus_options(&mut self) {
+        self.context.lock_consensus_options();
+    }
+
+    pub fn enable_invocation_counts(&mut self) {
+        self.context.enable_invocation_counts();
+    }
+
+    pub fn take_invocation_counts(&mut self) -> HashMap<FunctionIdentifier, u64> {
+        self.context.take_invocation_counts()
+    }
+
+    pub fn invocation_counts(&self) -> Option<&HashMap<FunctionIdentifier, u64>> {
+        self.context.invocation_counts()
+    }
+
+    pub fn reset_profiling(&mut self) {
+        self.context.reset_profiling();
+    }
+
+    pub fn enable_phase_timings(&mut self) {
+        self.context.enable_phase_timings();
+    }
+
+    pub fn take_phase_timings(&mut self) -> HashMap<FunctionIdentifier, PhaseTimings> {
+        self.context.take_phase_timings()
+    }
+
+    pub fn enable_vm_events(&mut self) {
+        self.context.enable_vm_events();
+    }
+
+    pub fn take_vm_events(&mut self) -> Vec<VmEvent> {
+        self.context.take_vm_events()
+    }
+
+    pub fn enable_access_list(&mut self) {
+        self.context.enable_access_list();
+    }
+
+    pub fn take_access_list(&mut self) -> Vec<StateAccess> {
+        self.context.take_access_list()
+    }
+
+    pub fn set_function_tracer(&mut self, tracer: FunctionTracer<'hooks>) {
+        self.context.set_function_tracer(tracer);
+    }
+
+    pub fn enable_memoization(
+        &mut self,
+        function: &DefinedFunction,
+        contract_context: &ContractContext,
+    ) -> bool {
+        self.context.enable_memoization(function, contract_context)
+    }
+
+    pub fn register_host_function(&mut self, function: HostFunction) -> Result<()> {
+        self.context.register_host_function(function)
+    }
+
+    pub fn resume_host_call(&mut self, value: Value) {
+        self.context.resume_host_call(value);
+    }
+
+    pub fn clear_host_resolutions(&mut self) {
+        self.context.clear_host_resolutions();
+    }
 }
 
 impl CostTracker for Environment<'_, '_, '_> {
@@ -966,6 +1227,44 @@ impl<'a, 'b, 'hooks> Environment<'a, 'b, 'hooks> {
         )
     }
 
+    /// Return the user function application counts recorded in this transaction
+    /// and reset them. See `GlobalContext::enable_invocation_counts`.
+    pub fn take_invocation_counts(&mut self) -> HashMap<FunctionIdentifier, u64> {
+        self.global_context.take_invocation_counts()
+    }
+
+    /// See `GlobalContext::reset_profiling`.
+    pub fn reset_profiling(&mut self) {
+        self.global_context.reset_profiling();
+    }
+
+    /// Return the function timings recorded so far and reset them. See
+    /// `GlobalContext::enable_phase_timings`.
+    pub fn take_phase_timings(&mut self) -> HashMap<FunctionIdentifier, PhaseTimings> {
+        self.global_context.take_phase_timings()
+    }
+
+    /// See `GlobalContext::take_vm_events`.
+    pub fn take_vm_events(&mut self) -> Vec<VmEvent> {
+        self.global_context.take_vm_events()
+    }
+
+    /// Return the data vars and maps accessed so far and reset them. See
+    /// `GlobalContext::enable_access_list`.
+    pub fn take_access_list(&mut self) -> Vec<StateAccess> {
+        self.global_context.take_access_list()
+    }
+
+    /// See `CallStack::frames`.
+    pub fn current_call_stack(&self) -> &[FunctionIdentifier] {
+        self.call_stack.frames()
+    }
+
+    /// See `CallStack::failed_span`.
+    pub fn failed_span(&self) -> Option<&Span> {
+        self.call_stack.failed_span()
+    }
+
     pub fn eval_read_only_with_rules(
         &mut self,
         contract_identifier: &QualifiedContractIdentifier,
@@ -1164,7 +1463,7 @@ impl<'a, 'b, 'hooks> Environment<'a, 'b, 'hooks> {
             self.call_stack.insert(&func_identifier, true);
 
             let res = self.execute_function_as_transaction(&func, &args, Some(&contract.contract_context));
-            self.call_stack.remove(&func_identifier, true)?;
+            self.call_stack.remove_after(&func_identifier, true, &res)?;
 
             match res {
                 Ok(value) => {
@@ -1186,6 +1485,40 @@ impl<'a, 'b, 'hooks> Environment<'a, 'b, 'hooks> {
         })
     }
 
+    /// Apply the user function identified by `id`, as in `"contract:name"`, to
+    /// `args`. A function of the current contract is applied as a call from
+    /// within it would be, so it may be private. A function of any other
+    /// contract is called as by `execute_contract`, so it must be public or
+    /// read-only, failing with `CheckErrors::NoSuchPublicFunction` otherwise.
+    pub fn call_by_identifier(&mut self, id: &FunctionIdentifier, args: &[Value]) -> Result<Value> {
+        let (contract, name) = id
+            .parse_user()
+            .ok_or_else(|| CheckErrors::UndefinedFunction(id.to_string()))?;
+        let contract_identifier = QualifiedContractIdentifier::parse(contract)
+            .map_err(|_| CheckErrors::NoSuchContract(contract.to_string()))?;
+        if contract_identifier != self.contract_context.contract_identifier {
+            let args: Vec<SymbolicExpression> = args
+                .iter()
+                .cloned()
+                .map(SymbolicExpression::atom_value)
+                .collect();
+            return self.execute_contract(&contract_identifier, name, &args, false);
+        }
+
+        let func = self
+            .contract_context
+            .lookup_function(name)
+            .ok_or_else(|| CheckErrors::UndefinedFunction(name.to_string()))?;
+        let func_identifier = func.get_identifier();
+        if self.call_stack.contains(&func_identifier) {
+            return Err(CheckErrors::CircularReference(vec![func_identifier.to_string()]).into());
+        }
+        self.call_stack.insert(&func_identifier, true);
+        let res = func.apply(args.to_vec(), self);
+        self.call_stack.remove_after(&func_identifier, true, &res)?;
+        res
+    }
+
     pub fn execute_function_as_transaction(
         &mut self,
         function: &DefinedFunction,
@@ -1223,6 +1556,111 @@ impl<'a, 'b, 'hooks> Environment<'a, 'b, 'hooks> {
         }
     }
 
+    /// Run `f` in a read-only context: any attempt to write within it fails
+    /// with `CheckErrors::WriteAttemptedInReadOnly`, and everything else it
+    /// records, such as printed events, is discarded. The context is left as
+    /// it was before, whether or not `f` succeeds.
+    pub fn with_readonly_guard<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        self.global_context.begin_read_only();
+        let result = f(self);
+        self.global_context.roll_back()?;
+        result
+    }
+
+    /// Apply `function` as `DefinedFunction::execute_apply` does, reporting
+    /// the application to whatever observes it. Only used while
+    /// `GlobalContext::observes_applications`, so that applying a user
+    /// function costs nothing more otherwise.
+    pub(crate) fn apply_observed(
+        &mut self,
+        function: &DefinedFunction,
+        args: Cow<'_, [Value]>,
+        check_types: bool,
+        context: &mut LocalContext,
+        keep_short_return: bool,
+    ) -> Result<Value> {
+        let identifier = function.get_identifier();
+        self.global_context.record_invocation(&identifier);
+        if let Some(note) = function.deprecation() {
+            self.global_context.push_vm_event(VmEvent::Deprecated {
+                id: identifier.clone(),
+                note: note.to_string(),
+            });
+        }
+
+        // an early return is kept by `execute_apply_raw`, but not cached
+        let memo_args = if !keep_short_return && self.global_context.is_memoized(&identifier) {
+            if let Some((result, cost)) = self.global_context.memoized_result(&identifier, &args) {
+                let (result, cost) = (result.clone(), cost.clone());
+                self.global_context.cost_track.add_cost(cost)?;
+                return Ok(result);
+            }
+            Some((args.to_vec(), self.global_context.cost_track.get_total()))
+        } else {
+            None
+        };
+
+        self.global_context
+            .trace_function(&identifier, TraceEvent::Enter { args: &args });
+        let result = if self.global_context.records_phase_timings() {
+            let binding_start = Instant::now();
+            function
+                .bind(args, self, check_types, context)
+                .and_then(|_| {
+                    let evaluation_start = Instant::now();
+                    let result = function.evaluate_body(self, context, keep_short_return);
+                    self.global_context.record_phase_timings(
+                        &identifier,
+                        evaluation_start.duration_since(binding_start),
+                        evaluation_start.elapsed(),
+                    );
+                    result
+                })
+        } else {
+            function.bind_and_evaluate(args, self, check_types, context, keep_short_return)
+        };
+        self.global_context
+            .trace_function(&identifier, TraceEvent::Exit { result: &result });
+
+        if let (Some((args, start)), Ok(value)) = (memo_args, &result) {
+            let mut cost = self.global_context.cost_track.get_total();
+            cost.sub(&start)?;
+            self.global_context
+                .record_memoized_result(&identifier, args, value.clone(), cost);
+        }
+        result
+    }
+
+    /// Run `f` with at most `budget` units of runtime cost to spend on top of
+    /// what has already been spent, returning its result together with the
+    /// part of `budget` left unspent. If `f` spends more, it is stopped with
+    /// `CheckErrors::CostBalanceExceeded`, as it would be by the tracker's own
+    /// limit, which still applies. That limit is restored however `f` returns.
+    pub fn with_runtime_budget<F, R>(&mut self, budget: u64, f: F) -> Result<(R, u64)>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        let start = self.global_context.cost_track.get_total();
+        let limit = self.global_context.cost_track.get_limit();
+        let mut budget_limit = limit.clone();
+        budget_limit.runtime = start.runtime.saturating_add(budget).min(limit.runtime);
+
+        self.global_context.cost_track.set_limit(budget_limit);
+        let result = f(self);
+        self.global_context.cost_track.set_limit(limit);
+
+        let spent = self
+            .global_context
+            .cost_track
+            .get_total()
+            .runtime
+            .saturating_sub(start.runtime);
+        Ok((result?, budget.saturating_sub(spent)))
+    }
+
     pub fn evaluate_at_block(
         &mut self,
         bhh: StacksBlockId,
@@ -1576,6 +2014,19 @@ impl<'a, 'hooks> GlobalContext<'a, 'hooks> {
             epoch_id,
             chain_id,
             eval_hooks: None,
+            options: ExecutionOptions::default(),
+            options_locked: false,
+            invocation_counts: None,
+            function_tracer: None,
+            trait_conformance: HashMap::new(),
+            memoized_functions: HashSet::new(),
+            memo_cache: HashMap::new(),
+            host_functions: HashMap::new(),
+            host_resolutions: Vec::new(),
+            host_resolutions_used: 0,
+            access_list: None,
+            phase_timings: None,
+            vm_events: None,
         }
     }
 
@@ -1583,6 +2034,326 @@ impl<'a, 'hooks> GlobalContext<'a, 'hooks> {
         self.asset_maps.len() == 0
     }
 
+    pub fn execution_options(&self) -> &ExecutionOptions {
+        &self.options
+    }
+
+    /// Replace the options contracts are executed with. Fails once they are
+    /// locked with `lock_consensus_options`.
+    pub fn set_execution_options(&mut self, options: ExecutionOptions) -> Result<()> {
+        if self.options_locked {
+            return Err(InterpreterError::Expect(
+                "Execution options are locked to consensus".into(),
+            )
+            .into());
+        }
+        self.options = options;
+        Ok(())
+    }
+
+    /// Fix the options to the default, consensus options for the rest of
+    /// this context's life, as block processing requires.
+    pub fn lock_consensus_options(&mut self) {
+        self.options = ExecutionOptions::default();
+        self.options_locked = true;
+    }
+
+    pub fn is_function_verified(&self, function: &FunctionIdentifier) -> bool {
+        self.options.verified_functions.contains(function)
+    }
+
+    /// Start counting user function applications. Counting is off by default,
+    /// in which case recording an application is a single branch.
+    pub fn enable_invocation_counts(&mut self) {
+        if self.invocation_counts.is_none() {
+            self.invocation_counts = Some(HashMap::new());
+        }
+    }
+
+    pub fn record_invocation(&mut self, function: &FunctionIdentifier) {
+        if let Some(counts) = self.invocation_counts.as_mut() {
+            if let Some(count) = counts.get_mut(function) {
+                *count = count.saturating_add(1);
+            } else {
+                counts.insert(function.clone(), 1);
+            }
+        }
+    }
+
+    /// The counts recorded so far, or `None` if counting was never enabled.
+    /// Counts accumulate across applications and transactions until they are
+    /// taken or reset.
+    pub fn invocation_counts(&self) -> Option<&HashMap<FunctionIdentifier, u64>> {
+        self.invocation_counts.as_ref()
+    }
+
+    /// Clear the invocation counts and phase timings recorded so far, e.g.
+    /// between test scenarios. Whatever was enabled stays enabled.
+    pub fn reset_profiling(&mut self) {
+        if let Some(counts) = self.invocation_counts.as_mut() {
+            counts.clear();
+        }
+        if let Some(timings) = self.phase_timings.as_mut() {
+            timings.clear();
+        }
+    }
+
+    /// Return the counts recorded so far and reset them. Counting stays
+    /// enabled. Returns an empty map if counting was never enabled.
+    pub fn take_invocation_counts(&mut self) -> HashMap<FunctionIdentifier, u64> {
+        match self.invocation_counts.as_mut() {
+            Some(counts) => std::mem::take(counts),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Start timing argument binding and body evaluation for each user function
+    /// application. Timing is off by default, in which case no clock is read.
+    pub fn enable_phase_timings(&mut self) {
+        if self.phase_timings.is_none() {
+            self.phase_timings = Some(HashMap::new());
+        }
+    }
+
+    pub fn records_phase_timings(&self) -> bool {
+        self.phase_timings.is_some()
+    }
+
+    pub fn record_phase_timings(
+        &mut self,
+        function: &FunctionIdentifier,
+        binding: Duration,
+        evaluation: Duration,
+    ) {
+        if let Some(timings) = self.phase_timings.as_mut() {
+            let entry = timings.entry(function.clone()).or_default();
+            entry.calls = entry.calls.saturating_add(1);
+            entry.binding += binding;
+            entry.evaluation += evaluation;
+        }
+    }
+
+    /// Return the timings recorded so far and reset them. Timing stays
+    /// enabled. Returns an empty map if timing was never enabled.
+    pub fn take_phase_timings(&mut self) -> HashMap<FunctionIdentifier, PhaseTimings> {
+        match self.phase_timings.as_mut() {
+            Some(timings) => std::mem::take(timings),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Start recording `VmEvent`s. Recording is off by default.
+    pub fn enable_vm_events(&mut self) {
+        if self.vm_events.is_none() {
+            self.vm_events = Some(Vec::new());
+        }
+    }
+
+    pub fn push_vm_event(&mut self, event: VmEvent) {
+        if let Some(events) = self.vm_events.as_mut() {
+            events.push(event);
+        }
+    }
+
+    /// Return the events recorded so far, in order, and reset them. Events
+    /// are kept even if the transaction that recorded them rolls back.
+    /// Returns nothing if recording was never enabled.
+    pub fn take_vm_events(&mut self) -> Vec<VmEvent> {
+        match self.vm_events.as_mut() {
+            Some(events) => std::mem::take(events),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether anything observes user function applications: invocation
+    /// counts, phase timings, VM events, a function tracer or memoization.
+    /// Unless something does, user functions are applied without reporting
+    /// to any of them. See `Environment::apply_observed`.
+    pub fn observes_applications(&self) -> bool {
+        self.invocation_counts.is_some()
+            || self.phase_timings.is_some()
+            || self.vm_events.is_some()
+            || self.function_tracer.is_some()
+            || !self.memoized_functions.is_empty()
+    }
+
+    /// Start recording which data vars and maps are read and written, for
+    /// `take_access_list`. Recording stays enabled until the context is dropped.
+    pub fn enable_access_list(&mut self) {
+        if self.access_list.is_none() {
+            self.access_list = Some(Vec::new());
+        }
+    }
+
+    pub fn record_state_access(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        name: &ClarityName,
+        kind: StateAccessKind,
+    ) {
+        if let Some(access_list) = self.access_list.as_mut() {
+            let recorded = access_list.iter().any(|access| {
+                access.kind == kind
+                    && access.name == *name
+                    && access.contract_identifier == *contract_identifier
+            });
+            if !recorded {
+                access_list.push(StateAccess {
+                    contract_identifier: contract_identifier.clone(),
+                    name: name.clone(),
+                    kind,
+                });
+            }
+        }
+    }
+
+    /// Return the accesses recorded so far, each once and in the order they
+    /// first happened, and reset them. Recording stays enabled. Returns an empty
+    /// list if recording was never enabled.
+    pub fn take_access_list(&mut self) -> Vec<StateAccess> {
+        match self.access_list.as_mut() {
+            Some(access_list) => std::mem::take(access_list),
+            None => Vec::new(),
+        }
+    }
+
+    /// Install `tracer` to be called on entry to and exit from every user
+    /// function application, replacing any previous tracer.
+    pub fn set_function_tracer(&mut self, tracer: FunctionTracer<'hooks>) {
+        self.function_tracer = Some(tracer);
+    }
+
+    /// The VRF seed of the block at `block_height` when seeded with
+    /// `ExecutionOptions::seed`: the SHA512/256 hash of the seed and the
+    /// height.
+    pub fn seeded_vrf_seed(&self, block_height: u32) -> Option<Sha512Trunc256Sum> {
+        let seed = self.options.seed?;
+        let mut data = seed.to_be_bytes().to_vec();
+        data.extend_from_slice(&block_height.to_be_bytes());
+        Some(Sha512Trunc256Sum::from_data(&data))
+    }
+
+    /// Cache the results of `function`, a function of `contract_context`, by
+    /// its arguments until the end of each transaction, if it is pure as in
+    /// `DefinedFunction::is_pure`. Returns whether it is. A cached result is
+    /// returned without evaluating the body again, but what computing it cost
+    /// is charged again, so memoization does not change the cost of
+    /// execution.
+    pub fn enable_memoization(
+        &mut self,
+        function: &DefinedFunction,
+        contract_context: &ContractContext,
+    ) -> bool {
+        if !function.is_pure(contract_context) {
+            return false;
+        }
+        self.memoized_functions.insert(function.get_identifier());
+        true
+    }
+
+    pub fn is_memoized(&self, function: &FunctionIdentifier) -> bool {
+        self.memoized_functions.contains(function)
+    }
+
+    /// The result of applying `function` to `args` earlier in this
+    /// transaction, if it is memoized, and what computing it cost.
+    pub fn memoized_result(
+        &self,
+        function: &FunctionIdentifier,
+        args: &[Value],
+    ) -> Option<(&Value, &ExecutionCost)> {
+        self.memo_cache
+            .get(function)?
+            .iter()
+            .find(|(cached_args, _, _)| cached_args.as_slice() == args)
+            .map(|(_, result, cost)| (result, cost))
+    }
+
+    pub fn record_memoized_result(
+        &mut self,
+        function: &FunctionIdentifier,
+        args: Vec<Value>,
+        result: Value,
+        cost: ExecutionCost,
+    ) {
+        if self.is_memoized(function) {
+            self.memo_cache
+                .entry(function.clone())
+                .or_default()
+                .push((args, result, cost));
+        }
+    }
+
+    /// Make `function` callable by name from any contract, like a native
+    /// function, while `ExecutionOptions::host_functions` is set, replacing
+    /// any host function of the same name. A contract's own function of that
+    /// name takes precedence. Names reserved for native functions and
+    /// variables are refused with `CheckErrors::NameAlreadyUsed`.
+    pub fn register_host_function(&mut self, function: HostFunction) -> Result<()> {
+        let name = function.get_name().clone();
+        if is_reserved(&name, &ClarityVersion::latest()) {
+            return Err(CheckErrors::NameAlreadyUsed(name.to_string()).into());
+        }
+        self.host_functions.insert(name, Rc::new(function));
+        Ok(())
+    }
+
+    pub fn lookup_host_function(&self, name: &str) -> Option<Rc<HostFunction>> {
+        self.host_functions.get(name).cloned()
+    }
+
+    /// Resolve the next suspended host call with `value`. Resolutions are
+    /// used in order by each transaction that suspends, from the first, until
+    /// they are cleared with `clear_host_resolutions`. See
+    /// `HostFunction::new_suspending`.
+    pub fn resume_host_call(&mut self, value: Value) {
+        self.host_resolutions.push(value);
+    }
+
+    pub fn clear_host_resolutions(&mut self) {
+        self.host_resolutions.clear();
+        self.host_resolutions_used = 0;
+    }
+
+    pub fn next_host_resolution(&mut self) -> Option<Value> {
+        let value = self
+            .host_resolutions
+            .get(self.host_resolutions_used)?
+            .clone();
+        self.host_resolutions_used += 1;
+        Some(value)
+    }
+
+    pub fn trace_function(&mut self, function: &FunctionIdentifier, event: TraceEvent) {
+        if let Some(tracer) = self.function_tracer.as_mut() {
+            tracer(function, event);
+        }
+    }
+
+    /// Whether `contract` was found to implement `trait_identifier` earlier in
+    /// this transaction, or `None` if that has not been checked yet. Only eager
+    /// trait checks record this; `contract-call?` checks just the method it
+    /// calls.
+    pub fn trait_conformance(
+        &self,
+        contract: &QualifiedContractIdentifier,
+        trait_identifier: &TraitIdentifier,
+    ) -> Option<bool> {
+        self.trait_conformance
+            .get(&(contract.clone(), trait_identifier.clone()))
+            .copied()
+    }
+
+    pub fn record_trait_conformance(
+        &mut self,
+        contract: QualifiedContractIdentifier,
+        trait_identifier: TraitIdentifier,
+        conforms: bool,
+    ) {
+        self.trait_conformance
+            .insert((contract, trait_identifier), conforms);
+    }
+
     fn get_asset_map(&mut self) -> Result<&mut AssetMap> {
         self.asset_maps
             .last_mut()
@@ -1685,6 +2456,12 @@ impl<'a, 'hooks> GlobalContext<'a, 'hooks> {
     }
 
     pub fn begin(&mut self) {
+        if self.is_top_level() {
+            // a new transaction
+            self.trait_conformance.clear();
+            self.memo_cache.clear();
+            self.host_resolutions_used = 0;
+        }
         self.asset_maps.push(AssetMap::new());
         self.event_batches.push(EventBatch::new());
         self.database.begin();
@@ -1693,6 +2470,11 @@ impl<'a, 'hooks> GlobalContext<'a, 'hooks> {
     }
 
     pub fn begin_read_only(&mut self) {
+        if self.is_top_level() {
+            self.trait_conformance.clear();
+            self.memo_cache.clear();
+            self.host_resolutions_used = 0;
+        }
         self.asset_maps.push(AssetMap::new());
         self.event_batches.push(EventBatch::new());
         self.database.begin();
@@ -1811,32 +2593,183 @@ impl ContractContext {
         self.functions.get(name).cloned()
     }
 
-    pub fn lookup_trait_definition(
-        &self,
-        name: &str,
-    ) -> Option<BTreeMap<ClarityName, FunctionSignature>> {
-        self.defined_traits.get(name).cloned()
+    /// Iterate over this contract's public functions, in no particular order.
+    /// Read-only and private functions are skipped.
+    pub fn list_public_functions(&self) -> impl Iterator<Item = &DefinedFunction> {
+        self.functions
+            .values()
+            .filter(|f| f.define_type == DefineType::Public)
     }
 
-    pub fn is_explicitly_implementing_trait(&self, trait_identifier: &TraitIdentifier) -> bool {
-        self.implemented_traits.contains(trait_identifier)
+    /// Iterate over this contract's read-only functions, in no particular order.
+    pub fn list_read_only_functions(&self) -> impl Iterator<Item = &DefinedFunction> {
+        self.functions.values().filter(|f| f.is_read_only())
     }
 
-    pub fn is_name_used(&self, name: &str) -> bool {
-        is_reserved(name, self.get_clarity_version())
-            || self.variables.contains_key(name)
-            || self.functions.contains_key(name)
-            || self.persisted_names.contains(name)
-            || self.defined_traits.contains_key(name)
+    /// The identifiers of every function this contract defines, whether
+    /// public, read-only or private, sorted.
+    pub fn all_function_identifiers(&self) -> Vec<FunctionIdentifier> {
+        let mut identifiers: Vec<FunctionIdentifier> = self
+            .functions
+            .values()
+            .map(|f| f.get_identifier())
+            .collect();
+        identifiers.sort();
+        identifiers
     }
 
-    pub fn get_clarity_version(&self) -> &ClarityVersion {
-        &self.clarity_version
+    /// The private functions that no public or read-only function can reach,
+    /// directly or through other private functions, sorted by name. A function
+    /// is reached wherever its name appears in a body, which covers both
+    /// applying it and passing it to `map`, `filter` or `fold`.
+    pub fn unused_private_functions(&self) -> Vec<ClarityName> {
+        let mut reached: HashSet<&ClarityName> = HashSet::new();
+        let mut pending: Vec<&DefinedFunction> =
+            self.functions.values().filter(|f| f.is_public()).collect();
+        while let Some(function) = pending.pop() {
+            if !reached.insert(function.get_name()) {
+                continue;
+            }
+            pending.extend(self.called_functions(function));
+        }
+
+        let mut unused: Vec<ClarityName> = self
+            .functions
+            .values()
+            .filter(|f| f.is_private() && !reached.contains(f.get_name()))
+            .map(|f| f.get_name().clone())
+            .collect();
+        unused.sort();
+        unused
     }
 
-    /// Canonicalize the types for the specified epoch. Only functions and
-    /// defined traits are exposed externally, so other types are not
-    /// canonicalized.
+    /// Every type used by an argument of a function of this contract, once
+    /// each, for documenting the types a contract's API exposes.
+    pub fn argument_type_set(&self) -> HashSet<TypeSignature> {
+        self.functions
+            .values()
+            .flat_map(|function| function.get_arg_types().iter().cloned())
+            .collect()
+    }
+
+    /// The functions of this contract named in `function`'s body, in the order
+    /// they appear, repeated if they appear more than once. This includes
+    /// functions applied directly and those passed to `map`, `filter` or `fold`.
+    pub fn called_functions(&self, function: &DefinedFunction) -> Vec<&DefinedFunction> {
+        let mut called = vec![];
+        self.push_referenced_functions(function.get_body(), &mut called);
+        called
+    }
+
+    fn push_referenced_functions<'c>(
+        &'c self,
+        expr: &SymbolicExpression,
+        pending: &mut Vec<&'c DefinedFunction>,
+    ) {
+        if let Some(name) = expr.match_atom() {
+            if let Some(function) = self.functions.get(name) {
+                pending.push(function);
+            }
+        } else if let Some(expressions) = expr.match_list() {
+            for expr in expressions.iter() {
+                self.push_referenced_functions(expr, pending);
+            }
+        }
+    }
+
+    /// Check that this contract implements every method of `trait_def`, the
+    /// definition of the trait `trait_identifier`. Each method must be a public
+    /// or read-only function whose arguments comply with the method's signature,
+    /// as in `DefinedFunction::check_trait_expectations`. The first method that
+    /// is missing or does not comply is reported as `BadTraitImplementation`.
+    /// Methods are checked in order of their names, so when several do not
+    /// comply, the same one is reported every time.
+    pub fn implements_trait(
+        &self,
+        epoch: &StacksEpochId,
+        trait_identifier: &TraitIdentifier,
+        trait_def: &BTreeMap<ClarityName, FunctionSignature>,
+    ) -> Result<()> {
+        for (method_name, expected_sig) in trait_def.iter() {
+            self.check_trait_method(epoch, trait_identifier, method_name, expected_sig)?;
+        }
+        Ok(())
+    }
+
+    /// Check every method of `trait_def` as `implements_trait` does, but
+    /// report each method that is missing or does not comply, with its error,
+    /// rather than only the first. Trait authors can use this to find what a
+    /// change to a trait's definition would break in a contract implementing it.
+    pub fn revalidate_against_trait(
+        &self,
+        epoch: &StacksEpochId,
+        trait_identifier: &TraitIdentifier,
+        trait_def: &BTreeMap<ClarityName, FunctionSignature>,
+    ) -> Vec<(ClarityName, CheckErrors)> {
+        trait_def
+            .iter()
+            .filter_map(|(method_name, expected_sig)| {
+                let error = match self.check_trait_method(
+                    epoch,
+                    trait_identifier,
+                    method_name,
+                    expected_sig,
+                ) {
+                    Ok(()) => return None,
+                    Err(crate::vm::errors::Error::Unchecked(error)) => error,
+                    Err(error) => CheckErrors::Expects(error.to_string()),
+                };
+                Some((method_name.clone(), error))
+            })
+            .collect()
+    }
+
+    fn check_trait_method(
+        &self,
+        epoch: &StacksEpochId,
+        trait_identifier: &TraitIdentifier,
+        method_name: &ClarityName,
+        expected_sig: &FunctionSignature,
+    ) -> Result<()> {
+        let trait_name = trait_identifier.name.as_str();
+        match self.functions.get(method_name) {
+            Some(function) if function.is_public() => {
+                function.check_trait_signature(epoch, trait_name, expected_sig)
+            }
+            _ => Err(CheckErrors::BadTraitImplementation(
+                trait_name.to_string(),
+                method_name.to_string(),
+            )
+            .into()),
+        }
+    }
+
+    pub fn lookup_trait_definition(
+        &self,
+        name: &str,
+    ) -> Option<BTreeMap<ClarityName, FunctionSignature>> {
+        self.defined_traits.get(name).cloned()
+    }
+
+    pub fn is_explicitly_implementing_trait(&self, trait_identifier: &TraitIdentifier) -> bool {
+        self.implemented_traits.contains(trait_identifier)
+    }
+
+    pub fn is_name_used(&self, name: &str) -> bool {
+        is_reserved(name, self.get_clarity_version())
+            || self.variables.contains_key(name)
+            || self.functions.contains_key(name)
+            || self.persisted_names.contains(name)
+            || self.defined_traits.contains_key(name)
+    }
+
+    pub fn get_clarity_version(&self) -> &ClarityVersion {
+        &self.clarity_version
+    }
+
+    /// Canonicalize the types for the specified epoch. Only functions and
+    /// defined traits are exposed externally, so other types are not
+    /// canonicalized.
     pub fn canonicalize_types(&mut self, epoch: &StacksEpochId) {
         for (_, function) in self.functions.iter_mut() {
             function.canonicalize_types(epoch);
@@ -1855,12 +2788,82 @@ impl<'a> LocalContext<'a> {
         LocalContext {
             function_context: Option::None,
             parent: Option::None,
-            callable_contracts: HashMap::new(),
+            callable_contracts: BTreeMap::new(),
             variables: HashMap::new(),
+            caller: None,
             depth: 0,
         }
     }
 
+    /// Record `caller` as the principal this context runs on behalf of.
+    pub fn with_caller(mut self, caller: PrincipalData) -> LocalContext<'a> {
+        self.set_caller(caller);
+        self
+    }
+
+    pub fn set_caller(&mut self, caller: PrincipalData) {
+        self.caller = Some(caller);
+    }
+
+    /// The bindings of this context, not including enclosing ones, as a tuple
+    /// with a field for each, for debugging. Trait references are represented
+    /// by the contract's principal, including the variable a Clarity 2 trait
+    /// argument is also bound as. Clarity has no empty tuple, so this fails
+    /// with `CheckErrors::EmptyTuplesNotAllowed` if there are no bindings,
+    /// rather than returning a value that does not reflect the context.
+    pub fn snapshot_as_tuple(&self) -> Result<Value> {
+        let variables = self
+            .variables
+            .iter()
+            .filter(|(name, _)| !self.callable_contracts.contains_key(*name))
+            .map(|(name, value)| (name.clone(), value.clone()));
+        let callables = self.callable_contracts.iter().map(|(name, callable)| {
+            (
+                name.clone(),
+                Value::Principal(PrincipalData::Contract(
+                    callable.contract_identifier.clone(),
+                )),
+            )
+        });
+        Ok(Value::Tuple(TupleData::from_data(
+            variables.chain(callables).collect(),
+        )?))
+    }
+
+    /// Bind `name` to `contract` as a reference to a contract implementing
+    /// `trait_id`, as a trait argument is bound when a function is applied.
+    /// `contract-call?` on `name` then dispatches to `contract`.
+    pub fn with_callable(
+        mut self,
+        name: ClarityName,
+        contract: QualifiedContractIdentifier,
+        trait_id: TraitIdentifier,
+    ) -> LocalContext<'a> {
+        self.callable_contracts.insert(
+            name,
+            CallableData {
+                contract_identifier: contract,
+                trait_identifier: Some(trait_id),
+            },
+        );
+        self
+    }
+
+    /// Remove every binding and the recorded caller, keeping the memory
+    /// already allocated for the bindings.
+    pub fn clear(&mut self) {
+        self.variables.clear();
+        self.callable_contracts.clear();
+        self.caller = None;
+    }
+
+    /// The caller recorded on this context or the nearest enclosing one.
+    pub fn effective_caller(&self) -> Option<&PrincipalData> {
+        self.caller
+            .as_ref()
+            .or_else(|| self.parent.and_then(|parent| parent.effective_caller()))
+    }
+
     pub fn depth(&self) -> u16 {
         self.depth
     }
@@ -1879,8 +2882,9 @@ impl<'a> LocalContext<'a> {
             Ok(LocalContext {
                 function_context: Some(self.function_context()),
                 parent: Some(self),
-                callable_contracts: HashMap::new(),
+                callable_contracts: BTreeMap::new(),
                 variables: HashMap::new(),
+                caller: None,
                 depth: self.depth + 1,
             })
         }
@@ -1905,6 +2909,21 @@ impl<'a> LocalContext<'a> {
             },
         }
     }
+
+    /// Bind `args` to the parameters `arguments` of types `arg_types`, as the
+    /// arguments of a user function are bound when it is applied: a contract
+    /// passed for a trait parameter is bound as a callable contract, and every
+    /// value is checked against its type.
+    pub fn extend_with_args(
+        &mut self,
+        arguments: &[ClarityName],
+        arg_types: &[TypeSignature],
+        args: &[Value],
+        env: &mut Environment,
+    ) -> Result<()> {
+        let values = args.iter().map(Cow::Borrowed).collect();
+        bind_arguments(self, arguments, arg_types, values, env, true)
+    }
 }
 
 impl CallStack {
@@ -1913,6 +2932,10 @@ impl CallStack {
             stack: Vec::new(),
             set: HashSet::new(),
             apply_depth: 0,
+            failed_stack: None,
+            failed_span: None,
+            unwound: Vec::new(),
+            unwound_span: None,
         }
     }
 
@@ -1962,6 +2985,64 @@ impl CallStack {
         }
     }
 
+    /// Like `remove`, once the application of `function` has returned
+    /// `result`. The frames popped while unwinding from an error other than
+    /// an early return are kept, and become the failed stack reported by
+    /// `frames` when the outermost one is popped.
+    pub fn remove_after(
+        &mut self,
+        function: &FunctionIdentifier,
+        tracked: bool,
+        result: &Result<Value>,
+    ) -> Result<()> {
+        match result {
+            Ok(_) | Err(Error::ShortReturn(_)) => {
+                self.unwound.clear();
+                self.unwound_span = None;
+                self.remove(function, tracked)
+            }
+            Err(_) => {
+                self.remove(function, tracked)?;
+                self.unwound.push(function.clone());
+                if self.stack.is_empty() {
+                    let mut failed_stack = std::mem::take(&mut self.unwound);
+                    failed_stack.reverse();
+                    self.failed_stack = Some(failed_stack);
+                    self.failed_span = self.unwound_span.take();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Keep the span of an expression whose evaluation failed, until the
+    /// stack has unwound. Only the first (innermost) one is kept. Spans are
+    /// only captured by the parser under the `developer-mode` feature, and
+    /// are all zero otherwise.
+    pub fn record_failed_span(&mut self, span: &Span) {
+        if self.unwound_span.is_none() {
+            self.unwound_span = Some(span.clone());
+        }
+    }
+
+    pub fn clear_failure(&mut self) {
+        self.failed_stack = None;
+        self.failed_span = None;
+        self.unwound.clear();
+        self.unwound_span = None;
+    }
+
+    /// The source span of the expression that raised the most recent error.
+    pub fn failed_span(&self) -> Option<&Span> {
+        self.failed_span.as_ref()
+    }
+
+    /// The functions currently being applied, outermost first. After an error,
+    /// these are the functions that were being applied when it was raised.
+    pub fn frames(&self) -> &[FunctionIdentifier] {
+        self.failed_stack.as_deref().unwrap_or(&self.stack)
+    }
+
     #[cfg(feature = "developer-mode")]
     pub fn make_stack_trace(&self) -> StackTrace {
         self.stack.clone()
@@ -1976,9 +3057,13 @@ impl CallStack {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::vm::callables::DefineType;
+    use crate::vm::callables::HostCall;
+    use crate::vm::database::MemoryBackingStore;
     use crate::vm::types::signatures::CallableSubtype;
-    use crate::vm::types::{FixedFunction, FunctionArg, FunctionType, StandardPrincipalData};
+    use crate::vm::types::{
+        FixedFunction, FunctionArg, FunctionType, SequenceSubtype, StandardPrincipalData,
+    };
+    use crate::vm::StepLimit;
 
     #[test]
     fn test_asset_map_abort() {
@@ -2189,4 +3274,1546 @@ mod test {
             TypeSignature::CallableType(CallableSubtype::Trait(trait_id.clone()))
         );
     }
+
+    #[test]
+    fn test_implements_trait() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let returns =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap();
+        let mut trait_def = BTreeMap::new();
+        for name in ["foo", "bar"] {
+            trait_def.insert(
+                name.into(),
+                FunctionSignature {
+                    args: vec![TypeSignature::UIntType],
+                    returns: returns.clone(),
+                },
+            );
+        }
+
+        let make_contract = |functions: &[(&str, TypeSignature)]| {
+            let mut contract_context = ContractContext::new(
+                QualifiedContractIdentifier::local("implementing").unwrap(),
+                ClarityVersion::Clarity2,
+            );
+            for (name, arg_type) in functions {
+                let name: ClarityName = (*name).into();
+                contract_context.functions.insert(
+                    name.clone(),
+                    DefinedFunction::new(
+                        vec![("a".into(), arg_type.clone())],
+                        SymbolicExpression::atom_value(Value::okay(Value::UInt(1)).unwrap()),
+                        DefineType::Public,
+                        &name,
+                        "implementing",
+                    ),
+                );
+            }
+            contract_context
+        };
+
+        // every method is implemented
+        let conforming = make_contract(&[
+            ("foo", TypeSignature::UIntType),
+            ("bar", TypeSignature::UIntType),
+        ]);
+        conforming
+            .implements_trait(&StacksEpochId::Epoch21, &trait_id, &trait_def)
+            .unwrap();
+
+        // a method is missing
+        let missing = make_contract(&[("foo", TypeSignature::UIntType)]);
+        assert_eq!(
+            missing
+                .implements_trait(&StacksEpochId::Epoch21, &trait_id, &trait_def)
+                .unwrap_err(),
+            CheckErrors::BadTraitImplementation("my-trait".into(), "bar".into()).into()
+        );
+
+        // a method takes the wrong argument type
+        let mismatched = make_contract(&[
+            ("foo", TypeSignature::IntType),
+            ("bar", TypeSignature::UIntType),
+        ]);
+        assert_eq!(
+            mismatched
+                .implements_trait(&StacksEpochId::Epoch21, &trait_id, &trait_def)
+                .unwrap_err(),
+            CheckErrors::BadTraitImplementation("my-trait".into(), "foo".into()).into()
+        );
+    }
+
+    #[test]
+    fn test_implements_trait_reports_first_method_by_name() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "vault".into(),
+        );
+        let returns =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap();
+        let mut trait_def = BTreeMap::new();
+        for name in ["withdraw", "transfer", "deposit"] {
+            trait_def.insert(
+                name.into(),
+                FunctionSignature {
+                    args: vec![TypeSignature::UIntType],
+                    returns: returns.clone(),
+                },
+            );
+        }
+
+        // `withdraw` and `deposit` both take the wrong argument type. Each
+        // run builds a new contract context, and so new function maps.
+        for _ in 0..16 {
+            let mut contract_context = ContractContext::new(
+                QualifiedContractIdentifier::local("implementing").unwrap(),
+                ClarityVersion::Clarity2,
+            );
+            for (name, arg_type) in [
+                ("withdraw", TypeSignature::IntType),
+                ("transfer", TypeSignature::UIntType),
+                ("deposit", TypeSignature::BoolType),
+            ] {
+                let name: ClarityName = name.into();
+                contract_context.functions.insert(
+                    name.clone(),
+                    DefinedFunction::new(
+                        vec![("a".into(), arg_type)],
+                        SymbolicExpression::atom_value(Value::okay(Value::UInt(1)).unwrap()),
+                        DefineType::Public,
+                        &name,
+                        "implementing",
+                    ),
+                );
+            }
+            assert_eq!(
+                contract_context
+                    .implements_trait(&StacksEpochId::Epoch21, &trait_id, &trait_def)
+                    .unwrap_err(),
+                CheckErrors::BadTraitImplementation("vault".into(), "deposit".into()).into()
+            );
+        }
+    }
+
+    #[test]
+    fn test_argument_type_set() {
+        let memo_type = TypeSignature::OptionalType(Box::new(TypeSignature::SequenceType(
+            SequenceSubtype::BufferType(34u32.try_into().unwrap()),
+        )));
+        let functions = [
+            (
+                "transfer",
+                vec![TypeSignature::UIntType, TypeSignature::PrincipalType],
+            ),
+            ("balance", vec![TypeSignature::PrincipalType]),
+            (
+                "scale",
+                vec![
+                    TypeSignature::UIntType,
+                    TypeSignature::IntType,
+                    memo_type.clone(),
+                ],
+            ),
+            ("total", vec![]),
+        ];
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::local("typed").unwrap(),
+            ClarityVersion::Clarity2,
+        );
+        assert!(contract_context.argument_type_set().is_empty());
+        for (name, arg_types) in functions {
+            let name: ClarityName = name.into();
+            let arguments = arg_types
+                .into_iter()
+                .enumerate()
+                .map(|(i, arg_type)| (format!("a{}", i).as_str().into(), arg_type))
+                .collect();
+            contract_context.functions.insert(
+                name.clone(),
+                DefinedFunction::new(
+                    arguments,
+                    SymbolicExpression::atom_value(Value::UInt(0)),
+                    DefineType::ReadOnly,
+                    &name,
+                    "typed",
+                ),
+            );
+        }
+
+        let expected = HashSet::from([
+            TypeSignature::UIntType,
+            TypeSignature::PrincipalType,
+            TypeSignature::IntType,
+            memo_type,
+        ]);
+        assert_eq!(contract_context.argument_type_set(), expected);
+    }
+
+    #[test]
+    fn test_revalidate_against_trait() {
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let returns =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap();
+        let buff = |len: u32| {
+            TypeSignature::SequenceType(SequenceSubtype::BufferType(len.try_into().unwrap()))
+        };
+        let make_trait = |methods: &[(&str, TypeSignature)]| -> BTreeMap<_, _> {
+            methods
+                .iter()
+                .map(|(name, arg_type)| {
+                    (
+                        ClarityName::from(*name),
+                        FunctionSignature {
+                            args: vec![arg_type.clone()],
+                            returns: returns.clone(),
+                        },
+                    )
+                })
+                .collect()
+        };
+
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::local("implementing").unwrap(),
+            ClarityVersion::Clarity2,
+        );
+        for (name, arg_type) in [("foo", TypeSignature::UIntType), ("bar", buff(10))] {
+            let name: ClarityName = name.into();
+            contract_context.functions.insert(
+                name.clone(),
+                DefinedFunction::new(
+                    vec![("a".into(), arg_type)],
+                    SymbolicExpression::atom_value(Value::okay(Value::UInt(1)).unwrap()),
+                    DefineType::Public,
+                    &name,
+                    "implementing",
+                ),
+            );
+        }
+
+        let original = make_trait(&[("foo", TypeSignature::UIntType), ("bar", buff(10))]);
+        assert!(contract_context
+            .revalidate_against_trait(&StacksEpochId::Epoch21, &trait_id, &original)
+            .is_empty());
+
+        // `bar` must now accept longer buffers, and there is a new method
+        let updated = make_trait(&[
+            ("foo", TypeSignature::UIntType),
+            ("bar", buff(20)),
+            ("baz", TypeSignature::UIntType),
+        ]);
+        let broken =
+            contract_context.revalidate_against_trait(&StacksEpochId::Epoch21, &trait_id, &updated);
+        assert_eq!(
+            broken,
+            vec![
+                (
+                    "bar".into(),
+                    CheckErrors::BadTraitImplementation("my-trait".into(), "bar".into())
+                ),
+                (
+                    "baz".into(),
+                    CheckErrors::BadTraitImplementation("my-trait".into(), "baz".into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_functions_by_define_type() {
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::local("foo").unwrap(),
+            ClarityVersion::Clarity2,
+        );
+        for (name, define_type) in [
+            ("pub-a", DefineType::Public),
+            ("pub-b", DefineType::Public),
+            ("ro", DefineType::ReadOnly),
+            ("priv", DefineType::Private),
+        ] {
+            let name: ClarityName = name.into();
+            contract_context.functions.insert(
+                name.clone(),
+                DefinedFunction::new(
+                    vec![],
+                    SymbolicExpression::atom_value(Value::Int(1)),
+                    define_type,
+                    &name,
+                    "testing",
+                ),
+            );
+        }
+
+        let mut public: Vec<_> = contract_context
+            .list_public_functions()
+            .map(|f| f.get_identifier().to_string())
+            .collect();
+        public.sort();
+        assert_eq!(public, vec!["testing:pub-a", "testing:pub-b"]);
+
+        let read_only: Vec<_> = contract_context
+            .list_read_only_functions()
+            .map(|f| f.get_identifier().to_string())
+            .collect();
+        assert_eq!(read_only, vec!["testing:ro"]);
+    }
+
+    #[test]
+    fn test_eager_trait_checks() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contracts = [
+            (
+                "traits",
+                "(define-trait getter ((get-value () (response uint uint))))",
+            ),
+            ("not-a-getter", "(define-public (other) (ok u1))"),
+            (
+                "user",
+                "(use-trait getter .traits.getter)
+                (define-public (ignore (g <getter>)) (ok u0))",
+            ),
+        ];
+        for (name, contract) in contracts {
+            owned_env
+                .initialize_contract(
+                    QualifiedContractIdentifier::local(name).unwrap(),
+                    contract,
+                    None,
+                    ASTRules::PrecheckSize,
+                )
+                .unwrap();
+        }
+
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let user_id = QualifiedContractIdentifier::local("user").unwrap();
+        let bad_getter = QualifiedContractIdentifier::local("not-a-getter").unwrap();
+        let args = [SymbolicExpression::atom_value(Value::Principal(
+            PrincipalData::Contract(bad_getter),
+        ))];
+
+        // by default, the reference is only checked when a method is called
+        let (result, _, _) = owned_env
+            .execute_transaction(sender.clone(), None, user_id.clone(), "ignore", &args)
+            .unwrap();
+        assert_eq!(result, Value::okay(Value::UInt(0)).unwrap());
+
+        owned_env
+            .set_execution_options(ExecutionOptions {
+                eager_trait_checks: true,
+                ..ExecutionOptions::default()
+            })
+            .unwrap();
+        let err = owned_env
+            .execute_transaction(sender, None, user_id, "ignore", &args)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::BadTraitImplementation("getter".into(), "get-value".into()).into()
+        );
+    }
+
+    #[test]
+    fn test_seeded_vrf_seed() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("dice").unwrap();
+        let contract = "(define-read-only (roll) (get-block-info? vrf-seed u0))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let mut roll = |seed: u64| {
+            owned_env
+                .set_execution_options(ExecutionOptions {
+                    seed: Some(seed),
+                    ..ExecutionOptions::default()
+                })
+                .unwrap();
+            let (result, _, _) = owned_env
+                .execute_transaction(sender.clone(), None, contract_id.clone(), "roll", &[])
+                .unwrap();
+            result
+        };
+
+        let first = roll(7);
+        assert_eq!(first, roll(7));
+        assert_ne!(first, roll(8));
+        let expected = Sha512Trunc256Sum::from_data(&[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0]);
+        assert_eq!(
+            first,
+            Value::some(Value::buff_from(expected.as_bytes().to_vec()).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_execution_options_consensus() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        assert!(owned_env.execution_options().is_consensus());
+
+        owned_env
+            .set_execution_options(ExecutionOptions {
+                wrap_args: true,
+                ..ExecutionOptions::default()
+            })
+            .unwrap();
+        assert!(!owned_env.execution_options().is_consensus());
+
+        // as in block processing
+        owned_env.lock_consensus_options();
+        assert!(owned_env.execution_options().is_consensus());
+        assert!(owned_env
+            .set_execution_options(ExecutionOptions {
+                wrap_args: true,
+                ..ExecutionOptions::default()
+            })
+            .is_err());
+        assert!(owned_env.execution_options().is_consensus());
+
+        assert!(!ExecutionOptions {
+            allow_dynamic_dispatch: false,
+            ..ExecutionOptions::default()
+        }
+        .is_consensus());
+    }
+
+    #[test]
+    fn test_access_list() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("accessed").unwrap();
+        let contract = "(define-data-var bonus uint u10)
+            (define-map balances principal uint)
+            (define-public (deposit (amount uint))
+                (ok (map-set balances tx-sender (+ amount (var-get bonus) (var-get bonus)))))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+        // nothing is recorded until recording is enabled
+        assert!(owned_env.take_access_list().is_empty());
+
+        owned_env.enable_access_list();
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let args = [SymbolicExpression::atom_value(Value::UInt(1))];
+        let (result, _, _) = owned_env
+            .execute_transaction(sender, None, contract_id.clone(), "deposit", &args)
+            .unwrap();
+        assert_eq!(result, Value::okay_true());
+
+        let access = |name: &str, kind| StateAccess {
+            contract_identifier: contract_id.clone(),
+            name: name.into(),
+            kind,
+        };
+        assert_eq!(
+            owned_env.take_access_list(),
+            vec![
+                access("bonus", StateAccessKind::Read),
+                access("balances", StateAccessKind::Write),
+            ]
+        );
+        assert!(owned_env.take_access_list().is_empty());
+    }
+
+    #[test]
+    fn test_invocation_counts() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("counter").unwrap();
+        let contract = "(define-private (bar (x int)) (+ x 1))
+            (define-read-only (foo) (map bar (list 1 2 3)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        // nothing is recorded until counting is enabled
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        assert!(owned_env.take_invocation_counts().is_empty());
+
+        // Clarity has no recursion, so count nested applications instead
+        owned_env.enable_invocation_counts();
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+
+        let counts = owned_env.take_invocation_counts();
+        let count_of = |name: &str| {
+            let identifier = format!("{}:{}", contract_id, name);
+            counts
+                .iter()
+                .find(|(function, _)| function.to_string() == identifier)
+                .map(|(_, count)| *count)
+        };
+        assert_eq!(counts.len(), 2);
+        assert_eq!(count_of("foo"), Some(2));
+        assert_eq!(count_of("bar"), Some(6));
+
+        assert!(owned_env.take_invocation_counts().is_empty());
+    }
+
+    #[test]
+    fn test_reset_profiling() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("counter").unwrap();
+        let contract = "(define-private (bar (x int)) (+ x 1))
+            (define-read-only (foo) (map bar (list 1 2 3)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+        assert!(owned_env.invocation_counts().is_none());
+
+        owned_env.enable_invocation_counts();
+        owned_env.enable_phase_timings();
+        let count_of = |owned_env: &OwnedEnvironment, name: &str| {
+            let identifier = format!("{}:{}", contract_id, name);
+            owned_env
+                .invocation_counts()
+                .unwrap()
+                .iter()
+                .find(|(function, _)| function.to_string() == identifier)
+                .map(|(_, count)| *count)
+        };
+
+        // counts accumulate across transactions
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        assert_eq!(count_of(&owned_env, "foo"), Some(2));
+        assert_eq!(count_of(&owned_env, "bar"), Some(6));
+
+        owned_env.reset_profiling();
+        assert_eq!(count_of(&owned_env, "foo"), None);
+        assert!(owned_env.take_phase_timings().is_empty());
+
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        assert_eq!(count_of(&owned_env, "foo"), Some(1));
+        assert_eq!(count_of(&owned_env, "bar"), Some(3));
+        assert_eq!(owned_env.take_phase_timings().len(), 2);
+    }
+
+    #[test]
+    fn test_phase_timings() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("timed").unwrap();
+        let contract = "(define-private (bar (x int)) (+ x 1))
+            (define-read-only (foo) (map bar (list 1 2 3)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        assert!(owned_env.take_phase_timings().is_empty());
+
+        owned_env.enable_phase_timings();
+        owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        let timings = owned_env.take_phase_timings();
+        let timings_of = |name: &str| {
+            let identifier = format!("{}:{}", contract_id, name);
+            timings
+                .iter()
+                .find(|(function, _)| function.to_string() == identifier)
+                .map(|(_, timings)| timings.clone())
+                .unwrap()
+        };
+        let foo = timings_of("foo");
+        let bar = timings_of("bar");
+        assert_eq!(foo.calls, 1);
+        assert_eq!(bar.calls, 3);
+        // every application of `bar`, binding included, is within `foo`'s body
+        assert!(foo.evaluation >= bar.binding + bar.evaluation);
+
+        assert!(owned_env.take_phase_timings().is_empty());
+    }
+
+    #[test]
+    fn test_all_function_identifiers() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("listed").unwrap();
+        let contract = "(define-constant c 1)
+            (define-private (b) c)
+            (define-public (a) (ok (b)))
+            (define-read-only (d) c)";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        owned_env
+            .execute_in_env(
+                PrincipalData::Standard(StandardPrincipalData::transient()),
+                None,
+                None,
+                |env| {
+                    let contract = env.global_context.database.get_contract(&contract_id)?;
+                    let context = &contract.contract_context;
+                    let identifiers = context.all_function_identifiers();
+                    assert_eq!(identifiers.len(), 3);
+                    let expected: Vec<_> = ["a", "b", "d"]
+                        .into_iter()
+                        .map(|name| context.lookup_function(name).unwrap().get_identifier())
+                        .collect();
+                    assert_eq!(identifiers, expected);
+                    assert_eq!(identifiers[0].to_string(), format!("{}:a", contract_id));
+                    Ok::<_, crate::vm::errors::Error>(())
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unused_private_functions() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("dead-code").unwrap();
+        let contract = "(define-private (double (x int)) (* x 2))
+            (define-private (quadruple (x int)) (double (double x)))
+            (define-private (is-even (x int)) (is-eq (mod x 2) 0))
+            (define-private (unused (x int)) (quadruple x))
+            (define-private (also-unused) (unused 1))
+            (define-public (run (x int)) (ok (quadruple x)))
+            (define-read-only (evens (xs (list 10 int))) (filter is-even xs))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        owned_env
+            .execute_in_env(
+                PrincipalData::Standard(StandardPrincipalData::transient()),
+                None,
+                None,
+                |env| {
+                    let contract = env.global_context.database.get_contract(&contract_id)?;
+                    // `unused` calls a reachable function, but is not reachable itself
+                    assert_eq!(
+                        contract.contract_context.unused_private_functions(),
+                        vec![
+                            ClarityName::from("also-unused"),
+                            ClarityName::from("unused")
+                        ]
+                    );
+                    Ok::<_, crate::vm::errors::Error>(())
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_with_readonly_guard() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("guarded").unwrap();
+        owned_env
+            .initialize_contract(
+                contract_id.clone(),
+                "(define-data-var v int 0)",
+                None,
+                ASTRules::PrecheckSize,
+            )
+            .unwrap();
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+
+        owned_env
+            .execute_in_env(sender, None, None, |env| {
+                let contract = env.global_context.database.get_contract(&contract_id)?;
+                let context = &contract.contract_context;
+                let eval = |env: &mut Environment, program: &str| {
+                    let mut nested_env = Environment::new(
+                        env.global_context,
+                        context,
+                        env.call_stack,
+                        env.sender.clone(),
+                        env.caller.clone(),
+                        env.sponsor.clone(),
+                    );
+                    nested_env.eval_raw(program)
+                };
+
+                assert_eq!(
+                    env.with_readonly_guard(|env| eval(env, "(var-set v 1)")),
+                    Err(CheckErrors::WriteAttemptedInReadOnly.into())
+                );
+                assert!(!env.global_context.is_read_only());
+                assert_eq!(
+                    env.with_readonly_guard(|env| eval(env, "(var-get v)")),
+                    Ok(Value::Int(0))
+                );
+
+                // once the guard is removed, writes are allowed again
+                assert_eq!(eval(env, "(var-set v 1)"), Ok(Value::Bool(true)));
+                assert_eq!(eval(env, "(var-get v)"), Ok(Value::Int(1)));
+                Ok::<_, crate::vm::errors::Error>(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_effective_caller() {
+        struct CallerRecorder(Vec<Option<PrincipalData>>);
+        impl EvalHook for CallerRecorder {
+            fn will_begin_eval(
+                &mut self,
+                _env: &mut Environment,
+                context: &LocalContext,
+                expr: &SymbolicExpression,
+            ) {
+                if expr.match_atom().map(|name| name.as_str()) == Some("contract-caller") {
+                    self.0.push(context.effective_caller().cloned());
+                }
+            }
+            fn did_finish_eval(
+                &mut self,
+                _env: &mut Environment,
+                _context: &LocalContext,
+                _expr: &SymbolicExpression,
+                _res: &core::result::Result<Value, crate::vm::errors::Error>,
+            ) {
+            }
+            fn did_complete(
+                &mut self,
+                _result: core::result::Result<&mut crate::vm::ExecutionResult, String>,
+            ) {
+            }
+        }
+
+        let mut recorder = CallerRecorder(vec![]);
+        let mut marf = MemoryBackingStore::new();
+        let contract_id = QualifiedContractIdentifier::local("callers").unwrap();
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let contract = "(define-private (inner) contract-caller)
+            (define-public (outer) (ok (list (inner) (as-contract (inner)) (inner))))";
+        {
+            let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+            owned_env
+                .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+                .unwrap();
+            owned_env.add_eval_hook(&mut recorder);
+            owned_env
+                .execute_transaction(sender.clone(), None, contract_id.clone(), "outer", &[])
+                .unwrap();
+        }
+
+        // the nested call sees the contract as its caller, and the original
+        // caller is seen again once `as-contract` returns
+        let contract_principal = PrincipalData::Contract(contract_id);
+        assert_eq!(
+            recorder.0,
+            vec![
+                Some(sender.clone()),
+                Some(contract_principal),
+                Some(sender.clone())
+            ]
+        );
+
+        // nested contexts inherit the caller of their function context
+        let root = LocalContext::new();
+        assert_eq!(root.effective_caller(), None);
+        let function_context = LocalContext::new().with_caller(sender.clone());
+        let child = function_context.extend().unwrap();
+        assert_eq!(child.effective_caller(), Some(&sender));
+    }
+
+    #[test]
+    fn test_with_callable() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contracts = [
+            (
+                "traits",
+                "(define-trait getter ((get-value () (response uint uint))))",
+            ),
+            ("getter", "(define-public (get-value) (ok u7))"),
+        ];
+        for (name, contract) in contracts {
+            owned_env
+                .initialize_contract(
+                    QualifiedContractIdentifier::local(name).unwrap(),
+                    contract,
+                    None,
+                    ASTRules::PrecheckSize,
+                )
+                .unwrap();
+        }
+
+        let getter = QualifiedContractIdentifier::local("getter").unwrap();
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "traits".into(),
+            "getter".into(),
+        );
+        let context =
+            LocalContext::new().with_callable("g".into(), getter.clone(), trait_id.clone());
+        assert_eq!(
+            context.lookup_callable_contract("g"),
+            Some(&CallableData {
+                contract_identifier: getter,
+                trait_identifier: Some(trait_id),
+            })
+        );
+        assert_eq!(context.lookup_callable_contract("h"), None);
+
+        let call = ast::parse(
+            &QualifiedContractIdentifier::transient(),
+            "(contract-call? g get-value)",
+            ClarityVersion::Clarity2,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap()
+        .remove(0);
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let initial_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            ClarityVersion::Clarity2,
+        );
+        let (result, _, _) = owned_env
+            .execute_in_env(sender, None, Some(initial_context), |env| {
+                eval(&call, env, &context)
+            })
+            .unwrap();
+        assert_eq!(result, Value::okay(Value::UInt(7)).unwrap());
+    }
+
+    #[test]
+    fn test_call_by_identifier() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let counter = QualifiedContractIdentifier::local("counter").unwrap();
+        owned_env
+            .initialize_contract(
+                counter.clone(),
+                "(define-private (double (n uint)) (* n u2))
+                 (define-read-only (get-double (n uint)) (double n))
+                 (define-public (add (n uint)) (ok (+ n u1)))",
+                None,
+                ASTRules::PrecheckSize,
+            )
+            .unwrap();
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+
+        let (counter_context, _, _) = owned_env
+            .execute_in_env(sender.clone(), None, None, |env| {
+                env.global_context
+                    .database
+                    .get_contract(&counter)
+                    .map(|contract| contract.contract_context)
+            })
+            .unwrap();
+        let identifier = |name: &str| {
+            counter_context
+                .lookup_function(name)
+                .unwrap()
+                .get_identifier()
+        };
+        let missing = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::UInt(1)),
+            DefineType::Public,
+            &"missing".into(),
+            &counter.to_string(),
+        )
+        .get_identifier();
+
+        // from another contract
+        let (results, _, _) = owned_env
+            .execute_in_env(
+                sender.clone(),
+                None,
+                None,
+                |env| -> std::result::Result<_, crate::vm::errors::Error> {
+                    Ok(vec![
+                        env.call_by_identifier(&identifier("add"), &[Value::UInt(1)]),
+                        env.call_by_identifier(&identifier("get-double"), &[Value::UInt(3)]),
+                        env.call_by_identifier(&identifier("double"), &[Value::UInt(3)]),
+                        env.call_by_identifier(&missing, &[]),
+                    ])
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                Ok(Value::okay(Value::UInt(2)).unwrap()),
+                Ok(Value::UInt(6)),
+                Err(CheckErrors::NoSuchPublicFunction(counter.to_string(), "double".into()).into()),
+                Err(CheckErrors::UndefinedFunction("missing".into()).into()),
+            ]
+        );
+
+        // from within the contract, private functions can be called
+        let (result, _, _) = owned_env
+            .execute_in_env(sender, None, Some(counter_context.clone()), |env| {
+                env.call_by_identifier(&identifier("double"), &[Value::UInt(4)])
+            })
+            .unwrap();
+        assert_eq!(result, Value::UInt(8));
+    }
+
+    #[test]
+    fn test_snapshot_as_tuple() {
+        let mut context = LocalContext::new();
+        assert_eq!(
+            context.snapshot_as_tuple(),
+            Err(CheckErrors::EmptyTuplesNotAllowed.into())
+        );
+
+        context.variables.insert("amount".into(), Value::UInt(10));
+        context.variables.insert("memo".into(), Value::none());
+        let contract = QualifiedContractIdentifier::local("getter").unwrap();
+        let context = context.with_callable(
+            "g".into(),
+            contract.clone(),
+            TraitIdentifier::new(
+                StandardPrincipalData::transient(),
+                "traits".into(),
+                "getter".into(),
+            ),
+        );
+
+        let expected = TupleData::from_data(vec![
+            ("amount".into(), Value::UInt(10)),
+            ("memo".into(), Value::none()),
+            (
+                "g".into(),
+                Value::Principal(PrincipalData::Contract(contract)),
+            ),
+        ])
+        .unwrap();
+        assert_eq!(context.snapshot_as_tuple(), Ok(Value::Tuple(expected)));
+
+        // enclosing contexts are not included
+        let child = context.extend().unwrap();
+        assert_eq!(
+            child.snapshot_as_tuple(),
+            Err(CheckErrors::EmptyTuplesNotAllowed.into())
+        );
+    }
+
+    #[test]
+    fn test_snapshot_trait_argument() {
+        // snapshots the context of the first expression evaluated with `g` bound
+        struct SnapshotRecorder(Option<Result<Value>>);
+        impl EvalHook for SnapshotRecorder {
+            fn will_begin_eval(
+                &mut self,
+                _env: &mut Environment,
+                context: &LocalContext,
+                _expr: &SymbolicExpression,
+            ) {
+                if self.0.is_none() && context.variables.contains_key("g") {
+                    self.0 = Some(context.snapshot_as_tuple());
+                }
+            }
+            fn did_finish_eval(
+                &mut self,
+                _env: &mut Environment,
+                _context: &LocalContext,
+                _expr: &SymbolicExpression,
+                _res: &core::result::Result<Value, crate::vm::errors::Error>,
+            ) {
+            }
+            fn did_complete(
+                &mut self,
+                _result: core::result::Result<&mut crate::vm::ExecutionResult, String>,
+            ) {
+            }
+        }
+
+        let mut recorder = SnapshotRecorder(None);
+        let mut marf = MemoryBackingStore::new();
+        let getter = QualifiedContractIdentifier::local("getter").unwrap();
+        let contracts = [
+            (
+                "traits",
+                "(define-trait getter ((get-value () (response uint uint))))",
+            ),
+            ("getter", "(define-public (get-value) (ok u1))"),
+            (
+                "user",
+                "(use-trait getter .traits.getter)
+                (define-public (ignore (g <getter>) (amount uint)) (ok amount))",
+            ),
+        ];
+        {
+            let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+            for (name, contract) in contracts {
+                owned_env
+                    .initialize_versioned_contract(
+                        QualifiedContractIdentifier::local(name).unwrap(),
+                        ClarityVersion::Clarity2,
+                        contract,
+                        None,
+                        ASTRules::PrecheckSize,
+                    )
+                    .unwrap();
+            }
+            owned_env.add_eval_hook(&mut recorder);
+            let args = [
+                SymbolicExpression::atom_value(Value::Principal(PrincipalData::Contract(
+                    getter.clone(),
+                ))),
+                SymbolicExpression::atom_value(Value::UInt(5)),
+            ];
+            let (result, _, _) = owned_env
+                .execute_transaction(
+                    PrincipalData::Standard(StandardPrincipalData::transient()),
+                    None,
+                    QualifiedContractIdentifier::local("user").unwrap(),
+                    "ignore",
+                    &args,
+                )
+                .unwrap();
+            assert_eq!(result, Value::okay(Value::UInt(5)).unwrap());
+        }
+
+        // `g` is bound both as a variable and as a trait reference
+        let expected = TupleData::from_data(vec![
+            ("amount".into(), Value::UInt(5)),
+            (
+                "g".into(),
+                Value::Principal(PrincipalData::Contract(getter)),
+            ),
+        ])
+        .unwrap();
+        assert_eq!(recorder.0, Some(Ok(Value::Tuple(expected))));
+    }
+
+    #[test]
+    fn test_callable_contracts_order() {
+        let mut context = LocalContext::new();
+        for name in ["zeta", "alpha", "mu", "beta"] {
+            context.callable_contracts.insert(
+                name.into(),
+                CallableData {
+                    contract_identifier: QualifiedContractIdentifier::local(name).unwrap(),
+                    trait_identifier: None,
+                },
+            );
+        }
+
+        let names: Vec<_> = context
+            .callable_contracts
+            .keys()
+            .map(|name| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["alpha", "beta", "mu", "zeta"]);
+        assert_eq!(
+            context
+                .lookup_callable_contract("mu")
+                .map(|callable| callable.contract_identifier.name.as_str()),
+            Some("mu")
+        );
+    }
+
+    #[test]
+    fn test_function_tracer() {
+        let mut trace = vec![];
+        let mut marf = MemoryBackingStore::new();
+        let contract_id = QualifiedContractIdentifier::local("traced").unwrap();
+        let contract = "(define-private (check (x int))
+                (begin (asserts! (> x 0) (err u1)) (ok x)))
+            (define-read-only (foo) (check -1))";
+        {
+            let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+            owned_env
+                .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+                .unwrap();
+            let mut tracer = |function: &FunctionIdentifier, event: TraceEvent| {
+                let name = function.parse_user().map(|(_, name)| name).unwrap_or("?");
+                trace.push(match event {
+                    TraceEvent::Enter { args } => format!("enter {} {:?}", name, args),
+                    TraceEvent::Exit { result } => format!("exit {} {:?}", name, result),
+                });
+            };
+            owned_env.set_function_tracer(&mut tracer);
+            owned_env.eval_read_only(&contract_id, "(foo)").unwrap();
+        }
+
+        // the early return from `check` is reported as its result
+        let early_return = Value::error(Value::UInt(1)).unwrap();
+        assert_eq!(
+            trace,
+            vec![
+                "enter foo []".to_string(),
+                format!("enter check {:?}", [Value::Int(-1)]),
+                format!(
+                    "exit check {:?}",
+                    Ok::<_, crate::vm::errors::Error>(early_return.clone())
+                ),
+                format!(
+                    "exit foo {:?}",
+                    Ok::<_, crate::vm::errors::Error>(early_return)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_function() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("hosted").unwrap();
+        let contract = "(define-read-only (quadruple (x int)) (host-double (host-double x)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        let double = |args: &[Value]| -> Result<Value> {
+            match args {
+                [Value::Int(x)] => Ok(Value::Int(x * 2)),
+                _ => Err(CheckErrors::IncorrectArgumentCount(1, args.len()).into()),
+            }
+        };
+        owned_env
+            .register_host_function(HostFunction::new("host-double".into(), Box::new(double)))
+            .unwrap();
+        // natives cannot be replaced
+        assert_eq!(
+            owned_env
+                .register_host_function(HostFunction::new("print".into(), Box::new(double)))
+                .unwrap_err(),
+            CheckErrors::NameAlreadyUsed("print".into()).into()
+        );
+
+        // only resolved while enabled
+        let err = owned_env
+            .eval_read_only(&contract_id, "(quadruple 3)")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::UndefinedFunction("host-double".into()).into()
+        );
+
+        owned_env
+            .set_execution_options(ExecutionOptions {
+                host_functions: true,
+                ..ExecutionOptions::default()
+            })
+            .unwrap();
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_id, "(quadruple 3)")
+            .unwrap();
+        assert_eq!(result, Value::Int(12));
+        let err = owned_env
+            .eval_read_only(&contract_id, "(host-double 1 2)")
+            .unwrap_err();
+        assert_eq!(err, CheckErrors::IncorrectArgumentCount(1, 2).into());
+    }
+
+    #[test]
+    fn test_suspending_host_function() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("priced").unwrap();
+        let contract = "(define-read-only (value (amount uint)) (* amount (get-price u1)))
+            (define-read-only (spread) (- (get-price u1) (get-price u2)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        // a price is always looked up by the host
+        let get_price = |args: &[Value]| -> Result<HostCall> {
+            match args {
+                [asset @ Value::UInt(_)] => Ok(HostCall::Suspend(asset.clone())),
+                _ => Err(CheckErrors::IncorrectArgumentCount(1, args.len()).into()),
+            }
+        };
+        owned_env
+            .register_host_function(HostFunction::new_suspending(
+                "get-price".into(),
+                Box::new(get_price),
+            ))
+            .unwrap();
+        owned_env
+            .set_execution_options(ExecutionOptions {
+                host_functions: true,
+                ..ExecutionOptions::default()
+            })
+            .unwrap();
+
+        let err = owned_env
+            .eval_read_only(&contract_id, "(value u2)")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            RuntimeErrorType::HostCallSuspended(Value::UInt(1)).into()
+        );
+
+        owned_env.resume_host_call(Value::UInt(30));
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_id, "(value u2)")
+            .unwrap();
+        assert_eq!(result, Value::UInt(60));
+
+        // resolutions are used in order, and again by each transaction
+        let err = owned_env
+            .eval_read_only(&contract_id, "(spread)")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            RuntimeErrorType::HostCallSuspended(Value::UInt(2)).into()
+        );
+        owned_env.resume_host_call(Value::UInt(20));
+        let (result, _, _) = owned_env.eval_read_only(&contract_id, "(spread)").unwrap();
+        assert_eq!(result, Value::UInt(10));
+
+        owned_env.clear_host_resolutions();
+        let err = owned_env
+            .eval_read_only(&contract_id, "(spread)")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            RuntimeErrorType::HostCallSuspended(Value::UInt(1)).into()
+        );
+    }
+
+    #[test]
+    fn test_step_limit() {
+        // counts the expressions begun and finished
+        #[derive(Default)]
+        struct EvalCounter(u64, u64);
+        impl EvalHook for EvalCounter {
+            fn will_begin_eval(
+                &mut self,
+                _env: &mut Environment,
+                _context: &LocalContext,
+                _expr: &SymbolicExpression,
+            ) {
+                self.0 += 1;
+            }
+            fn did_finish_eval(
+                &mut self,
+                _env: &mut Environment,
+                _context: &LocalContext,
+                _expr: &SymbolicExpression,
+                _res: &core::result::Result<Value, crate::vm::errors::Error>,
+            ) {
+                self.1 += 1;
+            }
+            fn did_complete(
+                &mut self,
+                _result: core::result::Result<&mut crate::vm::ExecutionResult, String>,
+            ) {
+            }
+        }
+
+        let contract_id = QualifiedContractIdentifier::local("spinning").unwrap();
+        let contract = "(define-private (step (x int) (acc int)) (+ acc x))
+            (define-private (spin-once (x int) (acc int))
+                (fold step (list 1 2 3 4 5 6 7 8 9 10) acc))
+            (define-read-only (spin)
+                (fold spin-once (list 1 2 3 4 5 6 7 8 9 10) 0))";
+        let mut limit = StepLimit::new(100);
+        let mut counter = EvalCounter::default();
+        let mut marf = MemoryBackingStore::new();
+        {
+            let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+            owned_env
+                .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+                .unwrap();
+
+            let (result, _, _) = owned_env.eval_read_only(&contract_id, "(spin)").unwrap();
+            assert_eq!(result, Value::Int(550));
+
+            owned_env.add_eval_hook(&mut limit);
+            owned_env.add_eval_hook(&mut counter);
+            let err = owned_env
+                .eval_read_only(&contract_id, "(spin)")
+                .unwrap_err();
+            assert_eq!(err, RuntimeErrorType::ExecutionTimeExpired.into());
+        }
+        // other hooks still see the aborted expressions finish
+        assert!(counter.0 > 100);
+        assert_eq!(counter.0, counter.1);
+
+        // the limit applies to each top-level expression
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+        let mut limit = StepLimit::new(10_000);
+        owned_env.add_eval_hook(&mut limit);
+        for _ in 0..3 {
+            let (result, _, _) = owned_env.eval_read_only(&contract_id, "(spin)").unwrap();
+            assert_eq!(result, Value::Int(550));
+        }
+    }
+
+    #[test]
+    fn test_memoization() {
+        let mut entered = vec![];
+        let mut marf = MemoryBackingStore::new();
+        let contract_id = QualifiedContractIdentifier::local("memo").unwrap();
+        let contract = "(define-data-var offset int 1)
+            (define-private (square (x int)) (* x x))
+            (define-private (sum-squares (x int)) (+ (square x) (square x) (square (+ x 1))))
+            (define-private (shifted (x int)) (+ x (var-get offset)))
+            (define-read-only (squares) (sum-squares 3))
+            (define-read-only (shifts) (+ (shifted 1) (shifted 1)))";
+        {
+            let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+            owned_env
+                .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+                .unwrap();
+            let mut tracer = |function: &FunctionIdentifier, event: TraceEvent| {
+                if let TraceEvent::Enter { .. } = event {
+                    let name = function.parse_user().map(|(_, name)| name).unwrap_or("?");
+                    entered.push(name.to_string());
+                }
+            };
+            owned_env.set_function_tracer(&mut tracer);
+
+            let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+            owned_env
+                .execute_in_env(sender, None, None, |env| {
+                    let contract = env.global_context.database.get_contract(&contract_id)?;
+                    let context = &contract.contract_context;
+                    let function = |name: &str| context.lookup_function(name).unwrap();
+
+                    assert!(function("sum-squares").is_pure(context));
+                    // not private, or reads a data var
+                    assert!(!function("squares").is_pure(context));
+                    assert!(!env
+                        .global_context
+                        .enable_memoization(&function("shifted"), context));
+                    assert!(env
+                        .global_context
+                        .enable_memoization(&function("square"), context));
+                    Ok::<_, crate::vm::errors::Error>(())
+                })
+                .unwrap();
+
+            for _ in 0..2 {
+                let (result, _, _) = owned_env.eval_read_only(&contract_id, "(squares)").unwrap();
+                assert_eq!(result, Value::Int(34));
+            }
+            let (result, _, _) = owned_env.eval_read_only(&contract_id, "(shifts)").unwrap();
+            assert_eq!(result, Value::Int(4));
+        }
+
+        // `square` is computed once per argument in each transaction, but
+        // `shifted` every time
+        assert_eq!(
+            entered,
+            vec![
+                "squares",
+                "sum-squares",
+                "square",
+                "square",
+                "squares",
+                "sum-squares",
+                "square",
+                "square",
+                "shifts",
+                "shifted",
+                "shifted",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trait_conformance_cache() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contracts = [
+            (
+                "defining",
+                "(define-trait my-trait ((get-one () (response uint uint))))",
+            ),
+            ("implementing", "(define-public (get-one) (ok u1))"),
+            (
+                "caller",
+                "(use-trait my-trait .defining.my-trait)
+                (define-public (call (t <my-trait>)) (contract-call? t get-one))",
+            ),
+        ];
+        for (name, contract) in contracts {
+            owned_env
+                .initialize_versioned_contract(
+                    QualifiedContractIdentifier::local(name).unwrap(),
+                    ClarityVersion::Clarity2,
+                    contract,
+                    None,
+                    ASTRules::PrecheckSize,
+                )
+                .unwrap();
+        }
+
+        let caller = QualifiedContractIdentifier::local("caller").unwrap();
+        let implementing = QualifiedContractIdentifier::local("implementing").unwrap();
+        let trait_id = TraitIdentifier::new(
+            StandardPrincipalData::transient(),
+            "defining".into(),
+            "my-trait".into(),
+        );
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let call = |env: &mut Environment| {
+            env.execute_contract(
+                &caller,
+                "call",
+                &[SymbolicExpression::atom_value(Value::from(
+                    PrincipalData::Contract(implementing.clone()),
+                ))],
+                false,
+            )
+        };
+        owned_env
+            .execute_in_env(sender.clone(), None, None, |env| {
+                // `contract-call?` checks only the method it calls, and does
+                // not record conformance
+                assert_eq!(call(env).unwrap(), Value::okay(Value::UInt(1)).unwrap());
+                assert_eq!(
+                    env.global_context
+                        .trait_conformance(&implementing, &trait_id),
+                    None
+                );
+
+                // eager checks record it when the argument is bound
+                env.global_context
+                    .set_execution_options(ExecutionOptions {
+                        eager_trait_checks: true,
+                        ..ExecutionOptions::default()
+                    })
+                    .unwrap();
+                for _ in 0..2 {
+                    assert_eq!(call(env).unwrap(), Value::okay(Value::UInt(1)).unwrap());
+                    assert_eq!(
+                        env.global_context
+                            .trait_conformance(&implementing, &trait_id),
+                        Some(true)
+                    );
+                }
+                Ok::<_, crate::vm::errors::Error>(())
+            })
+            .unwrap();
+
+        // the next transaction starts with an empty cache
+        owned_env
+            .execute_in_env(sender, None, None, |env| {
+                assert_eq!(
+                    env.global_context
+                        .trait_conformance(&implementing, &trait_id),
+                    None
+                );
+                Ok::<_, crate::vm::errors::Error>(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_current_call_stack() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("nested").unwrap();
+        let contract = "(define-private (inner (x int)) (/ 1 x))
+            (define-private (middle (x int)) (+ 1 (inner x)))
+            (define-read-only (outer (x int)) (middle x))
+            (define-read-only (early) (begin (asserts! false (err u1)) (ok u1)))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let (frames, _, _) = owned_env
+            .execute_in_env(sender, None, None, |env| {
+                let frame_names = |env: &Environment| -> Vec<String> {
+                    env.current_call_stack()
+                        .iter()
+                        .map(|frame| frame.to_string())
+                        .collect()
+                };
+                env.eval_read_only(&contract_id, "(outer 1)").unwrap();
+                assert!(env.current_call_stack().is_empty());
+                // an early return is not a failure
+                env.eval_read_only(&contract_id, "(early)").unwrap();
+                assert!(env.current_call_stack().is_empty());
+
+                env.eval_read_only(&contract_id, "(outer 0)").unwrap_err();
+                let frames = frame_names(env);
+
+                // the next successful application forgets the failure
+                env.eval_read_only(&contract_id, "(outer 1)").unwrap();
+                assert!(env.current_call_stack().is_empty());
+                Ok::<_, crate::vm::errors::Error>(frames)
+            })
+            .unwrap();
+
+        assert_eq!(
+            frames,
+            vec![
+                format!("{}:outer", contract_id),
+                format!("{}:middle", contract_id),
+                format!("{}:inner", contract_id),
+                "_native_:native_div".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "developer-mode")]
+    fn test_failed_span() {
+        let mut marf = MemoryBackingStore::new();
+        let mut owned_env = OwnedEnvironment::new(marf.as_clarity_db(), StacksEpochId::Epoch21);
+        let contract_id = QualifiedContractIdentifier::local("spans").unwrap();
+        let contract = "(define-private (add-one (x int))
+  (+ x u1))
+(define-read-only (call) (add-one 1))";
+        owned_env
+            .initialize_contract(contract_id.clone(), contract, None, ASTRules::PrecheckSize)
+            .unwrap();
+
+        let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+        let (span, _, _) = owned_env
+            .execute_in_env(sender, None, None, |env| {
+                let err = env.eval_read_only(&contract_id, "(call)").unwrap_err();
+                assert!(matches!(
+                    err,
+                    crate::vm::errors::Error::Unchecked(CheckErrors::TypeError(..))
+                ));
+                Ok::<_, crate::vm::errors::Error>(env.failed_span().cloned())
+            })
+            .unwrap();
+
+        assert_eq!(
+            span,
+            Some(Span {
+                start_line: 2,
+                start_column: 3,
+                end_line: 2,
+                end_column: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn test_local_context_depth() {
+        let root = LocalContext::new();
+        assert_eq!(root.depth(), 0);
+        let child = root.extend().unwrap();
+        assert_eq!(child.depth(), 1);
+        let grandchild = child.extend().unwrap();
+        assert_eq!(grandchild.depth(), 2);
+        assert_eq!(grandchild.function_context().depth(), 0);
+
+        let deepest = LocalContext {
+            depth: MAX_CONTEXT_DEPTH,
+            ..LocalContext::new()
+        };
+        assert!(matches!(
+            deepest.extend(),
+            Err(crate::vm::errors::Error::Runtime(
+                RuntimeErrorType::MaxContextDepthReached,
+                _
+            ))
+        ));
+    }
 }
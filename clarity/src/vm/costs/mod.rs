@@ -890,6 +890,14 @@ impl LimitedCostTracker {
             Self::Free => ExecutionCost::max_value(),
         }
     }
+    /// Replace the limit costs are checked against. A free tracker has no
+    /// limit, so this does nothing to it. Only for scoped budgets, which
+    /// restore the limit, see `Environment::with_runtime_budget`.
+    pub(crate) fn set_limit(&mut self, limit: ExecutionCost) {
+        if let Self::Limited(ref mut data) = self {
+            data.limit = limit;
+        }
+    }
     pub fn get_memory(&self) -> u64 {
         match self {
             Self::Limited(TrackerData { memory, .. }) => *memory,
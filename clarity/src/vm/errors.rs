@@ -26,9 +26,10 @@ pub use crate::vm::analysis::errors::{
     check_argument_count, check_arguments_at_least, check_arguments_at_most, CheckErrors,
 };
 use crate::vm::ast::errors::ParseError;
+use crate::vm::callables::FunctionIdentifier;
 use crate::vm::contexts::StackTrace;
 use crate::vm::costs::CostErrors;
-use crate::vm::types::{TypeSignature, Value};
+use crate::vm::types::{ResponseData, TypeSignature, Value};
 
 #[derive(Debug)]
 pub struct IncomparableError<T> {
@@ -46,6 +47,15 @@ pub enum Error {
     ShortReturn(ShortReturnType),
 }
 
+/// An error raised while applying a user function, together with the
+/// function it was raised in. Returned by
+/// `DefinedFunction::execute_apply_with_context`.
+#[derive(Debug, PartialEq)]
+pub struct FunctionError {
+    function: FunctionIdentifier,
+    error: Error,
+}
+
 /// InterpreterErrors are errors that *should never* occur.
 /// Test executions may trigger these errors.
 #[derive(Debug, PartialEq)]
@@ -102,6 +112,12 @@ pub enum RuntimeErrorType {
     UnwrapFailure,
     DefunctPoxContract,
     PoxAlreadyLocked,
+    /// A host function suspended with this request. See
+    /// `HostFunction::new_suspending`.
+    HostCallSuspended(Value),
+    /// More expressions were evaluated than a step limit allows. See
+    /// `StepLimit`.
+    ExecutionTimeExpired,
 }
 
 #[derive(Debug, PartialEq)]
@@ -151,6 +167,36 @@ impl fmt::Display for Error {
     }
 }
 
+impl FunctionError {
+    pub fn new(function: FunctionIdentifier, error: Error) -> FunctionError {
+        FunctionError { function, error }
+    }
+
+    pub fn function(&self) -> &FunctionIdentifier {
+        &self.function
+    }
+
+    pub fn inner(&self) -> &Error {
+        &self.error
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.error
+    }
+}
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.function, self.error)
+    }
+}
+
+impl error::Error for FunctionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 impl fmt::Display for RuntimeErrorType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -223,6 +269,29 @@ impl From<Error> for () {
     fn from(err: Error) -> Self {}
 }
 
+impl ShortReturnType {
+    /// The value the enclosing function returns.
+    pub fn value(&self) -> &Value {
+        match self {
+            ShortReturnType::ExpectedValue(v) => v,
+            ShortReturnType::AssertionFailed(v) => v,
+        }
+    }
+
+    /// Whether the function returns early with an `err` response, as in
+    /// `(try! (err u1))`. The value thrown by `unwrap!` or `asserts!` may be
+    /// any value, so an early return is not necessarily an error.
+    pub fn is_err(&self) -> bool {
+        matches!(
+            self.value(),
+            Value::Response(ResponseData {
+                committed: false,
+                ..
+            })
+        )
+    }
+}
+
 impl Into<Value> for ShortReturnType {
     fn into(self) -> Value {
         match self {
@@ -249,6 +318,15 @@ _native_:native_div
         assert_eq!(format!("{}", execute(t).unwrap_err()), expected);
     }
 
+    #[test]
+    fn short_return_is_err() {
+        let err = ShortReturnType::ExpectedValue(Value::error(Value::UInt(1)).unwrap());
+        assert!(err.is_err());
+        let ok = ShortReturnType::AssertionFailed(Value::okay(Value::UInt(1)).unwrap());
+        assert!(!ok.is_err());
+        assert!(!ShortReturnType::ExpectedValue(Value::none()).is_err());
+    }
+
     #[test]
     fn equality() {
         assert_eq!(
@@ -21,6 +21,7 @@ use stacks_common::types::chainstate::StacksBlockId;
 use stacks_common::types::StacksEpochId;
 
 use crate::vm::callables::DefineType;
+use crate::vm::contexts::StateAccessKind;
 use crate::vm::costs::cost_functions::ClarityCostFunction;
 use crate::vm::costs::{
     constants as cost_constants, cost_functions, runtime_cost, CostTracker, MemoryConsumer,
@@ -245,6 +246,8 @@ pub fn special_fetch_variable_v200(
         data_types.value_type.size()?,
     )?;
 
+    env.global_context
+        .record_state_access(contract, var_name, StateAccessKind::Read);
     let epoch = *env.epoch();
     env.global_context
         .database
@@ -270,6 +273,8 @@ pub fn special_fetch_variable_v205(
         .get(var_name)
         .ok_or(CheckErrors::NoSuchDataVariable(var_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, var_name, StateAccessKind::Read);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -317,6 +322,8 @@ pub fn special_set_variable_v200(
 
     env.add_memory(value.get_memory_use()?)?;
 
+    env.global_context
+        .record_state_access(contract, var_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     env.global_context
         .database
@@ -349,6 +356,8 @@ pub fn special_set_variable_v205(
         .get(var_name)
         .ok_or(CheckErrors::NoSuchDataVariable(var_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, var_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -392,6 +401,8 @@ pub fn special_fetch_entry_v200(
         data_types.value_type.size()? + data_types.key_type.size()?,
     )?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Read);
     let epoch = *env.epoch();
     env.global_context
         .database
@@ -419,6 +430,8 @@ pub fn special_fetch_entry_v205(
         .get(map_name)
         .ok_or(CheckErrors::NoSuchMap(map_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Read);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -496,6 +509,8 @@ pub fn special_set_entry_v200(
     env.add_memory(key.get_memory_use()?)?;
     env.add_memory(value.get_memory_use()?)?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     env.global_context
         .database
@@ -530,6 +545,8 @@ pub fn special_set_entry_v205(
         .get(map_name)
         .ok_or(CheckErrors::NoSuchMap(map_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -582,6 +599,8 @@ pub fn special_insert_entry_v200(
     env.add_memory(key.get_memory_use()?)?;
     env.add_memory(value.get_memory_use()?)?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
 
     env.global_context
@@ -617,6 +636,8 @@ pub fn special_insert_entry_v205(
         .get(map_name)
         .ok_or(CheckErrors::NoSuchMap(map_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -666,6 +687,8 @@ pub fn special_delete_entry_v200(
 
     env.add_memory(key.get_memory_use()?)?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     env.global_context
         .database
@@ -698,6 +721,8 @@ pub fn special_delete_entry_v205(
         .get(map_name)
         .ok_or(CheckErrors::NoSuchMap(map_name.to_string()))?;
 
+    env.global_context
+        .record_state_access(contract, map_name, StateAccessKind::Write);
     let epoch = *env.epoch();
     let result = env
         .global_context
@@ -760,13 +785,16 @@ pub fn special_get_block_info(
             Value::UInt(u128::from(block_time))
         }
         BlockInfoProperty::VrfSeed => {
-            let vrf_seed = env
-                .global_context
-                .database
-                .get_block_vrf_seed(height_value)?;
-            Value::Sequence(SequenceData::Buffer(BuffData {
-                data: vrf_seed.as_bytes().to_vec(),
-            }))
+            let data = match env.global_context.seeded_vrf_seed(height_value) {
+                Some(seeded) => seeded.as_bytes().to_vec(),
+                None => env
+                    .global_context
+                    .database
+                    .get_block_vrf_seed(height_value)?
+                    .as_bytes()
+                    .to_vec(),
+            };
+            Value::Sequence(SequenceData::Buffer(BuffData { data }))
         }
         BlockInfoProperty::HeaderHash => {
             let header_hash = env
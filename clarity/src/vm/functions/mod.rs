@@ -19,7 +19,7 @@ use stacks_common::types::chainstate::StacksAddress;
 use stacks_common::types::StacksEpochId;
 use stacks_common::util::hash;
 
-use crate::vm::callables::{cost_input_sized_vararg, CallableType, NativeHandle};
+use crate::vm::callables::{cost_input_sized_vararg, CallableType, NativeHandle, SpecialFnMeta};
 use crate::vm::costs::cost_functions::ClarityCostFunction;
 use crate::vm::costs::{
     constants as cost_constants, cost_functions, runtime_cost, CostTracker, MemoryConsumer,
@@ -192,6 +192,60 @@ define_versioned_named_enum!(NativeFunctions(ClarityVersion) {
 });
 
 impl NativeFunctions {
+    /// Returns the arity and state effects of this function if it is
+    ///   implemented as a `CallableType::SpecialFunction`.
+    pub fn special_fn_meta(&self) -> Option<SpecialFnMeta> {
+        use crate::vm::functions::NativeFunctions::*;
+        // (min_args, max_args, reads_state, writes_state)
+        let (min_args, max_args, reads_state, writes_state) = match self {
+            CmpGeq | CmpLeq | CmpLess | CmpGreater => (2, Some(2), false, false),
+            And | Or => (1, None, false, false),
+            If => (3, Some(3), false, false),
+            Let => (2, None, false, false),
+            FetchVar => (1, Some(1), true, false),
+            SetVar => (2, Some(2), false, true),
+            Map => (2, None, false, false),
+            Filter => (2, Some(2), false, false),
+            Fold => (3, Some(3), false, false),
+            Concat | AsMaxLen | Append => (2, Some(2), false, false),
+            Slice | ReplaceAt => (3, Some(3), false, false),
+            ListCons => (0, None, false, false),
+            IsStandard | PrincipalDestruct => (1, Some(1), false, false),
+            PrincipalConstruct => (2, Some(3), false, false),
+            FetchEntry => (2, Some(2), true, false),
+            SetEntry => (3, Some(3), false, true),
+            InsertEntry => (3, Some(3), true, true),
+            DeleteEntry => (2, Some(2), true, true),
+            TupleCons => (1, None, false, false),
+            TupleGet => (2, Some(2), false, false),
+            Secp256k1Recover => (2, Some(2), false, false),
+            Secp256k1Verify => (3, Some(3), false, false),
+            Print | AsContract | ContractOf | PrincipalOf => (1, Some(1), false, false),
+            // the called contract may do anything
+            ContractCall => (2, None, true, true),
+            GetBlockInfo | GetBurnBlockInfo => (2, Some(2), true, false),
+            AtBlock => (2, Some(2), true, false),
+            Asserts => (2, Some(2), false, false),
+            // 4 arguments to match an optional, 5 to match a response
+            Match => (4, Some(5), false, false),
+            MintAsset | MintToken | BurnAsset | BurnToken => (3, Some(3), true, true),
+            TransferAsset | TransferToken => (4, Some(4), true, true),
+            GetTokenBalance | GetAssetOwner => (2, Some(2), true, false),
+            GetTokenSupply | GetStxBalance | StxGetAccount => (1, Some(1), true, false),
+            StxTransfer => (3, Some(3), true, true),
+            StxTransferMemo => (4, Some(4), true, true),
+            StxBurn => (2, Some(2), true, true),
+            FromConsensusBuff => (2, Some(2), false, false),
+            _ => return None,
+        };
+        Some(SpecialFnMeta {
+            min_args,
+            max_args,
+            reads_state,
+            writes_state,
+        })
+    }
+
     pub fn lookup_by_name_at_version(
         name: &str,
         version: &ClarityVersion,
@@ -87,6 +87,9 @@ use crate::vm::types::{
 };
 pub use crate::vm::version::ClarityVersion;
 
+/// The consensus bound on nested function applications, including user
+/// functions, native functions and cross-contract calls. `apply` returns
+/// `RuntimeErrorType::MaxStackDepthReached` once it is reached.
 pub const MAX_CALL_STACK_DEPTH: usize = 64;
 
 #[derive(Debug, Clone)]
@@ -163,6 +166,74 @@ pub trait EvalHook {
 
     // Called upon completion of the execution
     fn did_complete(&mut self, _result: core::result::Result<&mut ExecutionResult, String>);
+
+    // Called before the expression is evaluated, after `will_begin_eval`. An
+    // error aborts the evaluation.
+    fn check_eval(
+        &mut self,
+        _env: &mut Environment,
+        _context: &LocalContext,
+        _expr: &SymbolicExpression,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An eval hook that aborts evaluation with
+/// `RuntimeErrorType::ExecutionTimeExpired` once more than `limit` expressions
+/// have been evaluated under one top-level expression. Unlike a wall-clock
+/// timeout, a step limit aborts at the same point on every run, e.g. when
+/// sandboxing untrusted contracts. Not for use in consensus, which relies on
+/// costs.
+pub struct StepLimit {
+    limit: u64,
+    steps_taken: u64,
+}
+
+impl StepLimit {
+    pub fn new(limit: u64) -> StepLimit {
+        StepLimit {
+            limit,
+            steps_taken: 0,
+        }
+    }
+}
+
+impl EvalHook for StepLimit {
+    fn will_begin_eval(
+        &mut self,
+        _env: &mut Environment,
+        _context: &LocalContext,
+        _expr: &SymbolicExpression,
+    ) {
+    }
+
+    fn did_finish_eval(
+        &mut self,
+        _env: &mut Environment,
+        _context: &LocalContext,
+        _expr: &SymbolicExpression,
+        _res: &core::result::Result<Value, crate::vm::errors::Error>,
+    ) {
+    }
+
+    fn did_complete(&mut self, _result: core::result::Result<&mut ExecutionResult, String>) {}
+
+    fn check_eval(
+        &mut self,
+        env: &mut Environment,
+        _context: &LocalContext,
+        _expr: &SymbolicExpression,
+    ) -> Result<()> {
+        if env.call_stack.depth() == 0 {
+            self.steps_taken = 0;
+        }
+        if self.steps_taken >= self.limit {
+            return Err(RuntimeErrorType::ExecutionTimeExpired.into());
+        }
+        self.steps_taken += 1;
+        Ok(())
+    }
 }
 
 fn lookup_variable(name: &str, context: &LocalContext, env: &mut Environment) -> Result<Value> {
@@ -211,11 +282,18 @@ pub fn lookup_function(name: &str, env: &mut Environment) -> Result<CallableType
     {
         Ok(result)
     } else {
-        let user_function = env
-            .contract_context
-            .lookup_function(name)
-            .ok_or(CheckErrors::UndefinedFunction(name.to_string()))?;
-        Ok(CallableType::UserFunction(user_function))
+        if let Some(user_function) = env.contract_context.lookup_function(name) {
+            Ok(CallableType::UserFunction(user_function))
+        } else {
+            let host_function = if env.global_context.execution_options().host_functions {
+                env.global_context.lookup_host_function(name)
+            } else {
+                None
+            };
+            let host_function =
+                host_function.ok_or(CheckErrors::UndefinedFunction(name.to_string()))?;
+            Ok(CallableType::HostFunction(host_function))
+        }
     }
 }
 
@@ -251,11 +329,17 @@ pub fn apply(
         return Err(RuntimeErrorType::MaxStackDepthReached.into());
     }
 
+    // a new top-level application starts without a recorded failure
+    if env.call_stack.depth() == 0 {
+        env.call_stack.clear_failure();
+    }
+
     if let CallableType::SpecialFunction(_, function) = function {
         env.call_stack.insert(&identifier, track_recursion);
         let mut resp = function(args, env, context);
         add_stack_trace(&mut resp, env);
-        env.call_stack.remove(&identifier, track_recursion)?;
+        env.call_stack
+            .remove_after(&identifier, track_recursion, &resp)?;
         resp
     } else {
         let mut used_memory = 0;
@@ -301,12 +385,20 @@ pub fn apply(
                     .map_err(Error::from)
                     .and_then(|_| function.apply(evaluated_args, env))
             }
-            CallableType::UserFunction(function) => function.apply(&evaluated_args, env),
+            CallableType::UserFunction(function) => function.apply(evaluated_args, env),
+            CallableType::HostFunction(function) => runtime_cost(
+                ClarityCostFunction::UserFunctionApplication,
+                env,
+                evaluated_args.len(),
+            )
+            .map_err(Error::from)
+            .and_then(|_| function.apply(&evaluated_args, env)),
             _ => return Err(InterpreterError::Expect("Should be unreachable.".into()).into()),
         };
         add_stack_trace(&mut resp, env);
         env.drop_memory(used_memory)?;
-        env.call_stack.remove(&identifier, track_recursion)?;
+        env.call_stack
+            .remove_after(&identifier, track_recursion, &resp)?;
         resp
     }
 }
@@ -320,34 +412,52 @@ pub fn eval<'a>(
         Atom, AtomValue, Field, List, LiteralValue, TraitReference,
     };
 
-    if let Some(mut eval_hooks) = env.global_context.eval_hooks.take() {
-        for hook in eval_hooks.iter_mut() {
-            hook.will_begin_eval(env, context, exp);
+    let checked = match env.global_context.eval_hooks.take() {
+        Some(mut eval_hooks) => {
+            for hook in eval_hooks.iter_mut() {
+                hook.will_begin_eval(env, context, exp);
+            }
+            let checked = eval_hooks
+                .iter_mut()
+                .try_for_each(|hook| hook.check_eval(env, context, exp));
+            env.global_context.eval_hooks = Some(eval_hooks);
+            checked
         }
-        env.global_context.eval_hooks = Some(eval_hooks);
-    }
+        None => Ok(()),
+    };
 
-    let res = match exp.expr {
-        AtomValue(ref value) | LiteralValue(ref value) => Ok(value.clone()),
-        Atom(ref value) => lookup_variable(&value, context, env),
-        List(ref children) => {
-            let (function_variable, rest) = children
-                .split_first()
-                .ok_or(CheckErrors::NonFunctionApplication)?;
-
-            let function_name = function_variable
-                .match_atom()
-                .ok_or(CheckErrors::BadFunctionName)?;
-            let f = lookup_function(&function_name, env)?;
-            apply(&f, &rest, env, context)
-        }
-        TraitReference(_, _) | Field(_) => {
-            return Err(InterpreterError::BadSymbolicRepresentation(
-                "Unexpected trait reference".into(),
-            )
-            .into())
-        }
+    // a failed check still finishes the evaluation for every hook
+    let res = match checked {
+        Ok(()) => match exp.expr {
+            AtomValue(ref value) | LiteralValue(ref value) => Ok(value.clone()),
+            Atom(ref value) => lookup_variable(&value, context, env),
+            List(ref children) => {
+                let (function_variable, rest) = children
+                    .split_first()
+                    .ok_or(CheckErrors::NonFunctionApplication)?;
+
+                let function_name = function_variable
+                    .match_atom()
+                    .ok_or(CheckErrors::BadFunctionName)?;
+                let f = lookup_function(&function_name, env)?;
+                apply(&f, &rest, env, context)
+            }
+            TraitReference(_, _) | Field(_) => {
+                return Err(InterpreterError::BadSymbolicRepresentation(
+                    "Unexpected trait reference".into(),
+                )
+                .into())
+            }
+        },
+        Err(e) => Err(e),
     };
+    // keep the span of a failed expression for `Environment::failed_span`.
+    // An early return is not a failure.
+    if let Err(ref e) = res {
+        if !matches!(e, Error::ShortReturn(_)) {
+            env.call_stack.record_failed_span(exp.span());
+        }
+    }
 
     if let Some(mut eval_hooks) = env.global_context.eval_hooks.take() {
         for hook in eval_hooks.iter_mut() {
@@ -83,6 +83,15 @@ guarded_string!(
     RuntimeErrorType::BadNameValue
 );
 
+impl ClarityName {
+    /// Whether this name is a valid Clarity identifier. Names built with
+    /// `try_from` always are, but a name deserialized from elsewhere, e.g. as
+    /// part of an AST, is not checked.
+    pub fn is_valid(&self) -> bool {
+        self.as_str().len() <= MAX_STRING_LEN as usize && CLARITY_NAME_REGEX.is_match(self.as_str())
+    }
+}
+
 impl StacksMessageCodec for ClarityName {
     fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
         // ClarityName can't be longer than vm::representations::MAX_STRING_LEN, which itself is
@@ -622,7 +622,7 @@ fn test_simple_token_system(
 
     assert!(matches!(
         err,
-        Error::Unchecked(CheckErrors::TypeValueError(_, _))
+        Error::Unchecked(CheckErrors::TypeValueErrorAt(_, _, _))
     ));
 
     let (result, asset_map, _events) = execute_transaction(
@@ -983,7 +983,7 @@ fn test_factorial_contract(epoch: StacksEpochId, mut env_factory: MemoryEnvironm
         .unwrap_err();
     assert!(matches!(
         err_result,
-        Error::Unchecked(CheckErrors::TypeValueError(_, _))
+        Error::Unchecked(CheckErrors::TypeValueErrorAt(0, _, _))
     ));
 }
 
@@ -561,7 +561,9 @@ fn lists_system() {
         println!("{:#?}", test);
         assert!(matches!(
             test,
-            Err(Error::Unchecked(CheckErrors::TypeValueError(_, _)))
+            Err(Error::Unchecked(
+                CheckErrors::TypeValueError(_, _) | CheckErrors::TypeValueErrorAt(_, _, _)
+            ))
         ));
     }
 }
@@ -624,7 +626,9 @@ fn tuples_system() {
 
     for test in type_error_tests.iter() {
         let expected_type_error = match execute(test) {
-            Err(Error::Unchecked(CheckErrors::TypeValueError(_, _))) => true,
+            Err(Error::Unchecked(
+                CheckErrors::TypeValueError(_, _) | CheckErrors::TypeValueErrorAt(_, _, _),
+            )) => true,
             _ => {
                 println!("{:?}", execute(test));
                 false
@@ -770,7 +774,9 @@ fn test_non_tuple_map_get_set() {
 
     for test in type_error_tests.iter() {
         let expected_type_error = match execute(test) {
-            Err(Error::Unchecked(CheckErrors::TypeValueError(_, _))) => true,
+            Err(Error::Unchecked(
+                CheckErrors::TypeValueError(_, _) | CheckErrors::TypeValueErrorAt(_, _, _),
+            )) => true,
             _ => {
                 println!("{:?}", execute(test));
                 false
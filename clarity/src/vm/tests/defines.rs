@@ -65,7 +65,8 @@ fn test_accept_options(#[case] version: ClarityVersion, #[case] epoch: StacksEpo
     let expectations: &[Result<_, Error>] = &[
         Ok(Some(Value::Int(0))),
         Ok(Some(Value::Int(10))),
-        Err(CheckErrors::TypeValueError(
+        Err(CheckErrors::TypeValueErrorAt(
+            0,
             TypeSignature::from_string("(optional int)", version, epoch),
             Value::some(Value::Bool(true)).unwrap(),
         )
@@ -206,6 +207,33 @@ fn test_recursive_panic(#[case] version: ClarityVersion, #[case] epoch: StacksEp
     assert!(matches!(err.err, ParseErrors::CircularReference(_)));
 }
 
+#[apply(test_clarity_versions)]
+fn test_mutual_recursion_rejected(#[case] version: ClarityVersion, #[case] epoch: StacksEpochId) {
+    // Clarity forbids recursion, so mutually-recursive functions are rejected
+    //  by the definition sorter even when they have a terminating base case.
+    let mutual = "(define-private (foo (a int))
+          (if (is-eq a 0) 0 (bar (- a 1))))
+         (define-private (bar (a int))
+          (if (is-eq a 0) 1 (foo (- a 1))))
+         (foo 10)";
+
+    let err = build_ast(
+        &QualifiedContractIdentifier::transient(),
+        mutual,
+        &mut (),
+        version,
+        epoch,
+    )
+    .unwrap_err();
+    assert!(matches!(err.err, ParseErrors::CircularReference(_)));
+
+    // forward references without a cycle are already resolved
+    let forward = "(define-private (foo (a int)) (bar a))
+         (define-private (bar (a int)) (+ a 1))
+         (foo 10)";
+    assert_eq!(Ok(Some(Value::Int(11))), execute(forward));
+}
+
 #[test]
 fn test_bad_variables() {
     let test0 = "(+ a 1)";
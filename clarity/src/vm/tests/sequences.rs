@@ -52,7 +52,7 @@ fn test_simple_list_admission() {
     );
     let err = execute(&t3).unwrap_err();
     assert!(match err {
-        Error::Unchecked(CheckErrors::TypeValueError(_, _)) => true,
+        Error::Unchecked(CheckErrors::TypeValueErrorAt(_, _, _)) => true,
         _ => {
             eprintln!("Expected TypeError, but found: {:?}", err);
             false
@@ -25,11 +25,12 @@ use stacks_common::types::StacksEpochId;
 use stacks_common::util::hash::{hex_bytes, to_hex};
 
 use crate::vm::ast::{parse, ASTRules};
-use crate::vm::callables::DefinedFunction;
+use crate::vm::callables::{CallableType, DefinedFunction, SpecialFnMeta};
 use crate::vm::contexts::OwnedEnvironment;
 use crate::vm::costs::LimitedCostTracker;
 use crate::vm::database::MemoryBackingStore;
 use crate::vm::errors::{CheckErrors, Error, RuntimeErrorType, ShortReturnType};
+use crate::vm::functions::{lookup_reserved_functions, NativeFunctions};
 use crate::vm::tests::{execute, test_clarity_versions};
 use crate::vm::types::signatures::*;
 use crate::vm::types::{
@@ -1763,3 +1764,54 @@ fn test_chain_id() {
             )
         });
 }
+
+#[test]
+fn test_special_fn_meta() {
+    let meta = |name| {
+        NativeFunctions::lookup_by_name(name)
+            .unwrap()
+            .special_fn_meta()
+            .unwrap()
+    };
+    assert_eq!(
+        meta("if"),
+        SpecialFnMeta {
+            min_args: 3,
+            max_args: Some(3),
+            reads_state: false,
+            writes_state: false,
+        }
+    );
+    assert_eq!(
+        meta("let"),
+        SpecialFnMeta {
+            min_args: 2,
+            max_args: None,
+            reads_state: false,
+            writes_state: false,
+        }
+    );
+    assert_eq!(
+        meta("map-set"),
+        SpecialFnMeta {
+            min_args: 3,
+            max_args: Some(3),
+            reads_state: false,
+            writes_state: true,
+        }
+    );
+
+    // exactly the special functions have metadata
+    for function in NativeFunctions::ALL {
+        let is_special = matches!(
+            lookup_reserved_functions(function.get_name_str(), &ClarityVersion::Clarity2),
+            Some(CallableType::SpecialFunction(..))
+        );
+        assert_eq!(
+            function.special_fn_meta().is_some(),
+            is_special,
+            "{}",
+            function
+        );
+    }
+}
@@ -36,7 +36,7 @@ use crate::vm::representations::{
     ClarityName, ContractName, SymbolicExpression, SymbolicExpressionType,
 };
 pub use crate::vm::types::signatures::{
-    parse_name_type_pairs, AssetIdentifier, BufferLength, FixedFunction, FunctionArg,
+    parse_name_type_pairs, AdmitError, AssetIdentifier, BufferLength, FixedFunction, FunctionArg,
     FunctionSignature, FunctionType, ListTypeData, SequenceSubtype, StringSubtype,
     StringUTF8Length, TupleTypeSignature, TypeSignature, BUFF_1, BUFF_20, BUFF_21, BUFF_32,
     BUFF_33, BUFF_64, BUFF_65,
@@ -1334,6 +1334,90 @@ impl fmt::Display for Value {
     }
 }
 
+impl Value {
+    /// Render this value in Clarity literal syntax for display, e.g. of a
+    /// result in a REPL. Tuples and non-empty lists are broken over several
+    /// lines, with their contents indented two spaces past `indent`. The first
+    /// line is not indented, so the result can follow a prompt or a label.
+    pub fn pretty_print(&self, indent: usize) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, indent);
+        out
+    }
+
+    /// Compare this value with `other` under the typing rule of Clarity's
+    /// `is-eq`: the two values must have a common supertype, as of epoch 2.1,
+    /// or they cannot be compared. This fails with `CheckErrors::TypeError`
+    /// for e.g. an `int` and a `uint`, where `==` would only answer `false`.
+    pub fn type_aware_eq(&self, other: &Value) -> std::result::Result<bool, CheckErrors> {
+        let self_type = TypeSignature::type_of(self)?;
+        let other_type = TypeSignature::type_of(other)?;
+        TypeSignature::least_supertype_v2_1(&self_type, &other_type)?;
+        Ok(self == other)
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let inner = indent + 2;
+        match self {
+            Value::Tuple(data) => {
+                out.push_str("{\n");
+                for (ix, (name, value)) in data.data_map.iter().enumerate() {
+                    if ix > 0 {
+                        out.push_str(",\n");
+                    }
+                    out.push_str(&" ".repeat(inner));
+                    out.push_str(name);
+                    out.push_str(": ");
+                    value.write_pretty(out, inner);
+                }
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                out.push('}');
+            }
+            Value::Sequence(SequenceData::List(list_data)) if list_data.data.is_empty() => {
+                out.push_str("(list)");
+            }
+            Value::Sequence(SequenceData::List(list_data)) => {
+                out.push_str("(list\n");
+                for value in list_data.data.iter() {
+                    out.push_str(&" ".repeat(inner));
+                    value.write_pretty(out, inner);
+                    out.push('\n');
+                }
+                out.push_str(&" ".repeat(indent));
+                out.push(')');
+            }
+            Value::Optional(OptionalData { data: Some(value) }) => {
+                out.push_str("(some ");
+                value.write_pretty(out, indent);
+                out.push(')');
+            }
+            Value::Response(ResponseData { committed, data }) => {
+                out.push_str(if *committed { "(ok " } else { "(err " });
+                data.write_pretty(out, indent);
+                out.push(')');
+            }
+            Value::Principal(principal_data) => {
+                out.push('\'');
+                out.push_str(&principal_data.to_string());
+            }
+            Value::CallableContract(CallableData {
+                contract_identifier,
+                ..
+            }) => {
+                out.push('\'');
+                out.push_str(&contract_identifier.to_string());
+            }
+            Value::Int(_)
+            | Value::UInt(_)
+            | Value::Bool(_)
+            | Value::Optional(OptionalData { data: None })
+            | Value::Sequence(SequenceData::Buffer(_))
+            | Value::Sequence(SequenceData::String(_)) => out.push_str(&self.to_string()),
+        }
+    }
+}
+
 impl PrincipalData {
     pub fn version(&self) -> u8 {
         match self {
@@ -1769,6 +1853,113 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_type_aware_eq() {
+        assert_eq!(Value::Int(1).type_aware_eq(&Value::Int(1)), Ok(true));
+        assert_eq!(Value::Int(1).type_aware_eq(&Value::Int(2)), Ok(false));
+        // an `ok` and an `err` may be compared if their other sides are unknown
+        assert_eq!(
+            Value::okay(Value::UInt(1))
+                .unwrap()
+                .type_aware_eq(&Value::error(Value::Int(1)).unwrap()),
+            Ok(false)
+        );
+        assert_eq!(
+            Value::some(Value::Int(1))
+                .unwrap()
+                .type_aware_eq(&Value::none()),
+            Ok(false)
+        );
+
+        assert_eq!(
+            Value::Int(1).type_aware_eq(&Value::UInt(1)),
+            Err(CheckErrors::TypeError(
+                TypeSignature::IntType,
+                TypeSignature::UIntType
+            ))
+        );
+        assert!(Value::some(Value::Int(1))
+            .unwrap()
+            .type_aware_eq(&Value::some(Value::UInt(1)).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let principal = PrincipalData::parse("S1G2081040G2081040G2081040G208105NK8PE5").unwrap();
+        let nested = Value::from(
+            TupleData::from_data(vec![
+                ("owner".into(), Value::from(principal)),
+                (
+                    "balance".into(),
+                    Value::from(
+                        TupleData::from_data(vec![
+                            ("locked".into(), Value::UInt(10)),
+                            ("unlocked".into(), Value::none()),
+                        ])
+                        .unwrap(),
+                    ),
+                ),
+                ("memo".into(), Value::buff_from(vec![1, 2]).unwrap()),
+            ])
+            .unwrap(),
+        );
+        assert_eq!(
+            nested.pretty_print(0),
+            "{
+  balance: {
+    locked: u10,
+    unlocked: none
+  },
+  memo: 0x0102,
+  owner: 'S1G2081040G2081040G2081040G208105NK8PE5
+}"
+        );
+
+        let responses = Value::cons_list_unsanitized(vec![
+            Value::okay(Value::some(Value::Int(1)).unwrap()).unwrap(),
+            Value::error(Value::from(
+                TupleData::from_data(vec![("code".into(), Value::UInt(2))]).unwrap(),
+            ))
+            .unwrap(),
+            Value::okay(Value::none()).unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(
+            responses.pretty_print(2),
+            "(list
+    (ok (some 1))
+    (err {
+      code: u2
+    })
+    (ok none)
+  )"
+        );
+
+        assert_eq!(
+            Value::cons_list_unsanitized(vec![])
+                .unwrap()
+                .pretty_print(0),
+            "(list)"
+        );
+
+        let contract =
+            QualifiedContractIdentifier::parse("S1G2081040G2081040G2081040G208105NK8PE5.foo")
+                .unwrap();
+        assert_eq!(
+            Value::from(PrincipalData::Contract(contract.clone())).pretty_print(0),
+            "'S1G2081040G2081040G2081040G208105NK8PE5.foo"
+        );
+        assert_eq!(
+            Value::CallableContract(CallableData {
+                contract_identifier: contract,
+                trait_identifier: None,
+            })
+            .pretty_print(0),
+            "'S1G2081040G2081040G2081040G208105NK8PE5.foo"
+        );
+    }
+
     #[test]
     #[should_panic]
     fn expect_buff_too_small() {
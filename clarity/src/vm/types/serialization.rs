@@ -1180,6 +1180,62 @@ impl Value {
         })?;
         Ok(counter.count)
     }
+
+    /// The length of this value's consensus serialization, computed from its
+    /// structure without serializing it. This agrees with `serialized_size`,
+    /// but cannot overflow, so it can be used to size values before they are
+    /// checked against the serialization limits.
+    pub fn estimated_memory_size(&self) -> u64 {
+        use super::CharType::*;
+        use super::PrincipalData::*;
+        use super::SequenceData::*;
+        use super::Value::*;
+
+        // every value starts with its type prefix, and every variable-length
+        //  value with a 4-byte length
+        let prefix_len = 1;
+        let length_len = 4;
+        let contract_len = |contract_identifier: &QualifiedContractIdentifier| {
+            21 + 1 + u64::from(contract_identifier.name.len())
+        };
+
+        prefix_len
+            + match self {
+                Int(_) | UInt(_) => 16,
+                Principal(Standard(_)) => 21,
+                Principal(Contract(contract_identifier))
+                | CallableContract(CallableData {
+                    contract_identifier,
+                    trait_identifier: _,
+                }) => contract_len(contract_identifier),
+                Response(response) => response.data.estimated_memory_size(),
+                Bool(_) | Optional(OptionalData { data: None }) => 0,
+                Optional(OptionalData { data: Some(value) }) => value.estimated_memory_size(),
+                Sequence(List(data)) => {
+                    length_len
+                        + data
+                            .data
+                            .iter()
+                            .map(Value::estimated_memory_size)
+                            .sum::<u64>()
+                }
+                Sequence(Buffer(value)) => length_len + value.data.len() as u64,
+                Sequence(String(UTF8(value))) => {
+                    length_len + value.data.iter().map(|c| c.len() as u64).sum::<u64>()
+                }
+                Sequence(String(ASCII(value))) => length_len + value.data.len() as u64,
+                Tuple(data) => {
+                    length_len
+                        + data
+                            .data_map
+                            .iter()
+                            .map(|(key, value)| {
+                                1 + u64::from(key.len()) + value.estimated_memory_size()
+                            })
+                            .sum::<u64>()
+                }
+            }
+    }
 }
 
 /// A writer that just counts the bytes written
@@ -1412,6 +1468,10 @@ pub mod tests {
             v.serialize_to_hex().unwrap().len() as u32 / 2,
             "serialized_size() should return the byte length of the serialization (half the length of the hex encoding)",
         );
+        assert_eq!(
+            v.estimated_memory_size(),
+            u64::from(v.serialized_size().unwrap())
+        );
     }
 
     fn test_deser_u32_helper(num: u32) {
@@ -2207,6 +2267,46 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_estimated_memory_size() {
+        let int_size = 17;
+
+        // (list (list 1 2) (list 3))
+        let nested_list = Value::cons_list_unsanitized(vec![
+            Value::cons_list_unsanitized(vec![Value::Int(1), Value::Int(2)]).unwrap(),
+            Value::cons_list_unsanitized(vec![Value::Int(3)]).unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(
+            nested_list.estimated_memory_size(),
+            5 + (5 + 2 * int_size) + (5 + int_size)
+        );
+
+        // (tuple (a 1) (bb (some u2)))
+        let tuple = Value::from(
+            TupleData::from_data(vec![
+                ("a".into(), Value::Int(1)),
+                ("bb".into(), Value::some(Value::UInt(2)).unwrap()),
+            ])
+            .unwrap(),
+        );
+        assert_eq!(
+            tuple.estimated_memory_size(),
+            5 + (2 + int_size) + (3 + 1 + int_size)
+        );
+
+        // (list (tuple (a 1) (bb (some u2))))
+        let list_of_tuples = Value::cons_list_unsanitized(vec![tuple.clone()]).unwrap();
+        assert_eq!(
+            list_of_tuples.estimated_memory_size(),
+            5 + tuple.estimated_memory_size()
+        );
+
+        for value in [nested_list, tuple, list_of_tuples] {
+            test_deser_ser(value);
+        }
+    }
+
     #[test]
     fn test_principals() {
         let issuer =
@@ -73,15 +73,15 @@ impl AssetIdentifier {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TupleTypeSignature {
     type_map: BTreeMap<ClarityName, TypeSignature>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct BufferLength(u32);
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct StringUTF8Length(u32);
 
 // INVARIANTS enforced by the Type Signatures.
@@ -119,7 +119,7 @@ pub enum TypeSignature {
     TraitReferenceType(TraitIdentifier),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SequenceSubtype {
     BufferType(BufferLength),
     ListType(ListTypeData),
@@ -143,7 +143,7 @@ impl SequenceSubtype {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StringSubtype {
     ASCII(BufferLength),
     UTF8(StringUTF8Length),
@@ -160,6 +160,24 @@ use self::TypeSignature::{
     ResponseType, SequenceType, TraitReferenceType, TupleType, UIntType,
 };
 
+// `ListUnionType` holds a `HashSet`, which is not `Hash`, so this can't be
+// derived. Equal sets have equal lengths, which is enough for a valid hash.
+impl Hash for TypeSignature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            NoType | IntType | UIntType | BoolType | PrincipalType => {}
+            SequenceType(subtype) => subtype.hash(state),
+            TupleType(tuple_type) => tuple_type.hash(state),
+            OptionalType(inner_type) => inner_type.hash(state),
+            ResponseType(inner_types) => inner_types.hash(state),
+            CallableType(subtype) => subtype.hash(state),
+            ListUnionType(subtypes) => subtypes.len().hash(state),
+            TraitReferenceType(trait_identifier) => trait_identifier.hash(state),
+        }
+    }
+}
+
 lazy_static! {
     pub static ref BUFF_64: TypeSignature = {
         #[allow(clippy::expect_used)]
@@ -218,12 +236,47 @@ pub const UTF8_40: TypeSignature = SequenceType(SequenceSubtype::StringType(Stri
     StringUTF8Length(40),
 )));
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ListTypeData {
     max_len: u32,
     entry_type: Box<TypeSignature>,
 }
 
+/// Why `TypeSignature::admits_with_reason` rejected a value. Compound types
+/// report the first subcomponent that failed.
+#[derive(Debug, PartialEq)]
+pub enum AdmitError {
+    /// The value's type is not admitted by the expected type.
+    TypeMismatch {
+        expected: TypeSignature,
+        found: TypeSignature,
+    },
+    /// A list, buffer or string is longer than the expected type allows.
+    LengthExceeded { max_len: u32, found: u32 },
+    /// A list element was rejected.
+    ListElement {
+        index: usize,
+        reason: Box<AdmitError>,
+    },
+    /// A tuple field was rejected.
+    TupleField {
+        name: ClarityName,
+        reason: Box<AdmitError>,
+    },
+    /// The tuple is missing a field of the expected type.
+    MissingTupleField(ClarityName),
+    /// The tuple has a field the expected type does not declare.
+    UnexpectedTupleField(ClarityName),
+    /// The value inside a `some` was rejected.
+    OptionalSome(Box<AdmitError>),
+    /// The value inside an `ok` was rejected.
+    ResponseOk(Box<AdmitError>),
+    /// The value inside an `err` was rejected.
+    ResponseErr(Box<AdmitError>),
+    /// Admission could not be determined.
+    Check(CheckErrors),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FunctionSignature {
     pub args: Vec<TypeSignature>,
@@ -547,6 +600,132 @@ impl TypeSignature {
         self.admits_type(epoch, &x_type)
     }
 
+    /// Like `admits`, but a rejection describes the first part of `x` that did
+    /// not match. Admission itself is always decided by `admits`; the reason is
+    /// only worked out once `x` has been rejected.
+    pub fn admits_with_reason(
+        &self,
+        epoch: &StacksEpochId,
+        x: &Value,
+    ) -> std::result::Result<(), AdmitError> {
+        if self.admits(epoch, x).map_err(AdmitError::Check)? {
+            return Ok(());
+        }
+        Err(self.explain_rejection(epoch, x))
+    }
+
+    fn explain_rejection(&self, epoch: &StacksEpochId, x: &Value) -> AdmitError {
+        let found = match TypeSignature::type_of(x) {
+            Ok(found) => found,
+            Err(e) => return AdmitError::Check(e),
+        };
+        let mismatch = AdmitError::TypeMismatch {
+            expected: self.clone(),
+            found: found.clone(),
+        };
+
+        // a nested check that errors is reported as the failing component
+        let rejects = |expected: &TypeSignature, value: &Value| {
+            !matches!(expected.admits(epoch, value), Ok(true))
+        };
+
+        match (self, x) {
+            (
+                SequenceType(SequenceSubtype::ListType(list_type)),
+                Value::Sequence(SequenceData::List(list)),
+            ) => {
+                if list.data.len() > list_type.max_len as usize {
+                    return AdmitError::LengthExceeded {
+                        max_len: list_type.max_len,
+                        found: list.data.len().try_into().unwrap_or(u32::MAX),
+                    };
+                }
+                list.data
+                    .iter()
+                    .enumerate()
+                    .find(|(_, item)| rejects(&list_type.entry_type, item))
+                    .map(|(index, item)| AdmitError::ListElement {
+                        index,
+                        reason: Box::new(list_type.entry_type.explain_rejection(epoch, item)),
+                    })
+                    .unwrap_or(mismatch)
+            }
+            (
+                SequenceType(SequenceSubtype::BufferType(max_len)),
+                Value::Sequence(SequenceData::Buffer(_)),
+            )
+            | (
+                SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(max_len))),
+                Value::Sequence(SequenceData::String(CharType::ASCII(_))),
+            ) => match found {
+                SequenceType(SequenceSubtype::BufferType(len))
+                | SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(len)))
+                    if len.0 > max_len.0 =>
+                {
+                    AdmitError::LengthExceeded {
+                        max_len: max_len.0,
+                        found: len.0,
+                    }
+                }
+                _ => mismatch,
+            },
+            (
+                SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(max_len))),
+                Value::Sequence(SequenceData::String(CharType::UTF8(_))),
+            ) => match found {
+                SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(len)))
+                    if len.0 > max_len.0 =>
+                {
+                    AdmitError::LengthExceeded {
+                        max_len: max_len.0,
+                        found: len.0,
+                    }
+                }
+                _ => mismatch,
+            },
+            (OptionalType(inner_type), Value::Optional(optional)) => match &optional.data {
+                Some(inner) if rejects(inner_type, inner) => {
+                    AdmitError::OptionalSome(Box::new(inner_type.explain_rejection(epoch, inner)))
+                }
+                _ => mismatch,
+            },
+            (ResponseType(inner_types), Value::Response(response)) => {
+                let (expected, wrap): (_, fn(Box<AdmitError>) -> AdmitError) = if response.committed
+                {
+                    (&inner_types.0, AdmitError::ResponseOk)
+                } else {
+                    (&inner_types.1, AdmitError::ResponseErr)
+                };
+                if rejects(expected, &response.data) {
+                    wrap(Box::new(expected.explain_rejection(epoch, &response.data)))
+                } else {
+                    mismatch
+                }
+            }
+            (TupleType(tuple_sig), Value::Tuple(tuple)) => {
+                for (name, field_type) in tuple_sig.type_map.iter() {
+                    match tuple.data_map.get(name) {
+                        None => return AdmitError::MissingTupleField(name.clone()),
+                        Some(field) if rejects(field_type, field) => {
+                            return AdmitError::TupleField {
+                                name: name.clone(),
+                                reason: Box::new(field_type.explain_rejection(epoch, field)),
+                            }
+                        }
+                        Some(_) => {}
+                    }
+                }
+                tuple
+                    .data_map
+                    .keys()
+                    .find(|name| !tuple_sig.type_map.contains_key(*name))
+                    .map(|name| AdmitError::UnexpectedTupleField(name.clone()))
+                    .unwrap_or(mismatch)
+            }
+            _ => mismatch,
+        }
+    }
+
     pub fn admits_type(&self, epoch: &StacksEpochId, other: &TypeSignature) -> Result<bool> {
         match epoch {
             StacksEpochId::Epoch20 | StacksEpochId::Epoch2_05 => self.admits_type_v2_0(other),
@@ -1996,6 +2175,33 @@ impl fmt::Display for TypeSignature {
     }
 }
 
+impl fmt::Display for AdmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdmitError::TypeMismatch { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            AdmitError::LengthExceeded { max_len, found } => {
+                write!(f, "length {} exceeds maximum {}", found, max_len)
+            }
+            AdmitError::ListElement { index, reason } => {
+                write!(f, "list element {}: {}", index, reason)
+            }
+            AdmitError::TupleField { name, reason } => {
+                write!(f, "tuple field {}: {}", name, reason)
+            }
+            AdmitError::MissingTupleField(name) => write!(f, "missing tuple field {}", name),
+            AdmitError::UnexpectedTupleField(name) => {
+                write!(f, "unexpected tuple field {}", name)
+            }
+            AdmitError::OptionalSome(reason) => write!(f, "some value: {}", reason),
+            AdmitError::ResponseOk(reason) => write!(f, "ok value: {}", reason),
+            AdmitError::ResponseErr(reason) => write!(f, "err value: {}", reason),
+            AdmitError::Check(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 impl fmt::Display for BufferLength {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -2631,4 +2837,78 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_admits_with_reason() {
+        let epoch = StacksEpochId::Epoch21;
+        let check = |type_repr: &str, value_repr: &str| {
+            let type_sig = TypeSignature::from_string(type_repr, ClarityVersion::Clarity2, epoch);
+            let value = execute(value_repr).unwrap().unwrap();
+            let result = type_sig.admits_with_reason(&epoch, &value);
+            assert_eq!(result.is_ok(), type_sig.admits(&epoch, &value).unwrap());
+            result
+        };
+
+        assert_eq!(check("(list 3 int)", "(list 1 2)"), Ok(()));
+        assert_eq!(
+            check("int", "u1"),
+            Err(AdmitError::TypeMismatch {
+                expected: TypeSignature::IntType,
+                found: TypeSignature::UIntType,
+            })
+        );
+        assert_eq!(
+            check("(list 2 int)", "(list 1 2 3)"),
+            Err(AdmitError::LengthExceeded {
+                max_len: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            check("(buff 2)", "0x010203"),
+            Err(AdmitError::LengthExceeded {
+                max_len: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            check("(list 2 (string-ascii 2))", "(list \"ab\" \"abc\")"),
+            Err(AdmitError::ListElement {
+                index: 1,
+                reason: Box::new(AdmitError::LengthExceeded {
+                    max_len: 2,
+                    found: 3
+                }),
+            })
+        );
+        assert_eq!(
+            check("(response int uint)", "(ok u1)"),
+            Err(AdmitError::ResponseOk(Box::new(AdmitError::TypeMismatch {
+                expected: TypeSignature::IntType,
+                found: TypeSignature::UIntType,
+            })))
+        );
+        assert_eq!(
+            check("(optional (buff 1))", "(some 0x0102)"),
+            Err(AdmitError::OptionalSome(Box::new(
+                AdmitError::LengthExceeded {
+                    max_len: 1,
+                    found: 2
+                }
+            )))
+        );
+        assert_eq!(
+            check("(tuple (a int) (b int))", "{ a: 1 }"),
+            Err(AdmitError::MissingTupleField("b".into()))
+        );
+        assert_eq!(
+            check("(tuple (a int))", "{ a: 1, b: 2 }"),
+            Err(AdmitError::UnexpectedTupleField("b".into()))
+        );
+        let err = check("(tuple (a (list 1 int)))", "{ a: (list u1) }").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "tuple field a: list element 0: expected int, found uint"
+        );
+    }
 }
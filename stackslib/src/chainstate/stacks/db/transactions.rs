@@ -9723,7 +9723,7 @@ pub mod test {
         assert!(tx_receipt.vm_error.is_some());
         let err_str = tx_receipt.vm_error.unwrap();
         assert!(err_str
-            .find("TypeValueError(OptionalType(CallableType(Trait(TraitIdentifier ")
+            .find("TypeValueErrorAt(0, OptionalType(CallableType(Trait(TraitIdentifier ")
             .is_some());
 
         let (fee, tx_receipt) = StacksChainState::process_transaction(
@@ -9747,7 +9747,7 @@ pub mod test {
         assert!(tx_receipt.vm_error.is_some());
         let err_str = tx_receipt.vm_error.unwrap();
         assert!(err_str
-            .find("TypeValueError(OptionalType(CallableType(Trait(TraitIdentifier ")
+            .find("TypeValueErrorAt(0, OptionalType(CallableType(Trait(TraitIdentifier ")
             .is_some());
 
         conn.commit_block();
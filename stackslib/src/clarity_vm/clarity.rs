@@ -1431,6 +1431,7 @@ impl<'a, 'b> TransactionConnection for ClarityTransactionConnection<'a, 'b> {
                     cost_track,
                     self.epoch,
                 );
+                vm_env.lock_consensus_options();
                 let result = to_do(&mut vm_env);
                 let (mut db, cost_track) = vm_env
                     .destruct()
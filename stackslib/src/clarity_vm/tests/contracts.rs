@@ -468,10 +468,15 @@ fn trait_invocation_cross_epoch() {
                 )
                 .unwrap_err();
 
-            if let ClarityError::Interpreter(Error::Unchecked(CheckErrors::TypeValueError(TypeSignature::TraitReferenceType(_), value))) = error {
+            if let ClarityError::Interpreter(Error::Unchecked(CheckErrors::TypeValueErrorAt(
+                _,
+                TypeSignature::TraitReferenceType(_),
+                value,
+            ))) = error
+            {
                 // pass
             } else {
-                panic!("Expected an Interpreter(UncheckedError(TypeValue(TraitReferenceType, Principal))) during Epoch-2.2");
+                panic!("Expected an Interpreter(UncheckedError(TypeValueErrorAt(_, TraitReferenceType, Principal))) during Epoch-2.2");
             };
         });
     });
@@ -491,10 +496,15 @@ fn trait_invocation_cross_epoch() {
                 )
                 .unwrap_err();
 
-            if let ClarityError::Interpreter(Error::Unchecked(CheckErrors::TypeValueError(TypeSignature::TraitReferenceType(_), value))) = error {
+            if let ClarityError::Interpreter(Error::Unchecked(CheckErrors::TypeValueErrorAt(
+                _,
+                TypeSignature::TraitReferenceType(_),
+                value,
+            ))) = error
+            {
                 // pass
             } else {
-                panic!("Expected an Interpreter(UncheckedError(TypeValue(TraitReferenceType, Principal))) during Epoch-2.2");
+                panic!("Expected an Interpreter(UncheckedError(TypeValueErrorAt(_, TraitReferenceType, Principal))) during Epoch-2.2");
             };
         });
     });
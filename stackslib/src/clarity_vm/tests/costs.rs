@@ -17,9 +17,10 @@
 use std::collections::HashMap;
 
 use clarity::vm::ast::ASTRules;
+use clarity::vm::callables::HostFunction;
 use clarity::vm::clarity::TransactionConnection;
 use clarity::vm::contexts::{
-    AssetMap, AssetMapEntry, Environment, GlobalContext, OwnedEnvironment,
+    AssetMap, AssetMapEntry, Environment, ExecutionOptions, GlobalContext, OwnedEnvironment,
 };
 use clarity::vm::contracts::Contract;
 use clarity::vm::costs::cost_functions::ClarityCostFunction;
@@ -35,7 +36,8 @@ use clarity::vm::test_util::{
 };
 use clarity::vm::tests::test_only_mainnet_to_chain_id;
 use clarity::vm::types::{
-    AssetIdentifier, OptionalData, PrincipalData, QualifiedContractIdentifier, ResponseData, Value,
+    AssetIdentifier, OptionalData, PrincipalData, QualifiedContractIdentifier, ResponseData,
+    TraitIdentifier, Value,
 };
 use clarity::vm::{ClarityVersion, ContractName};
 use stacks_common::types::chainstate::{BlockHeaderHash, StacksBlockId};
@@ -1683,3 +1685,254 @@ fn test_cost_voting_integration_testnet() {
     test_cost_voting_integration(false, ClarityVersion::Clarity1);
     test_cost_voting_integration(false, ClarityVersion::Clarity2);
 }
+
+#[test]
+fn test_trait_conformance_metered() {
+    with_owned_env(StacksEpochId::Epoch21, false, |mut owned_env| {
+        let deep_type =
+            "(list 10 (optional (tuple (a (list 10 (response int uint))) (b (buff 32)))))";
+        let trait_contract = format!(
+            "(define-trait shallow ((run (int) (response bool bool))))
+            (define-trait deep ((run ({ty} {ty} {ty}) (response bool bool))))",
+            ty = deep_type
+        );
+        let shallow_contract = "(define-public (run (a int)) (ok true))".to_string();
+        let deep_contract = format!(
+            "(define-public (run (a {ty}) (b {ty}) (c {ty})) (ok true))",
+            ty = deep_type
+        );
+
+        let issuer = match execute("'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR") {
+            Value::Principal(PrincipalData::Standard(data)) => data,
+            _ => panic!(),
+        };
+        let contract_id =
+            |name: &str| QualifiedContractIdentifier::new(issuer.clone(), name.into());
+        for (name, source) in [
+            ("traits", &trait_contract),
+            ("shallow", &shallow_contract),
+            ("deep", &deep_contract),
+        ] {
+            owned_env
+                .initialize_versioned_contract(
+                    contract_id(name),
+                    ClarityVersion::Clarity2,
+                    source,
+                    None,
+                    ASTRules::PrecheckSize,
+                )
+                .unwrap();
+        }
+
+        owned_env
+            .execute_in_env(issuer.clone().into(), None, None, |env| {
+                let traits = env
+                    .global_context
+                    .database
+                    .get_contract(&contract_id("traits"))
+                    .unwrap()
+                    .contract_context;
+                let metered_cost = |env: &mut Environment, name: &str| {
+                    let function = env
+                        .global_context
+                        .database
+                        .get_contract(&contract_id(name))
+                        .unwrap()
+                        .contract_context
+                        .lookup_function("run")
+                        .unwrap();
+                    let trait_id =
+                        TraitIdentifier::new(issuer.clone(), "traits".into(), name.into());
+                    let start = env.global_context.cost_track.get_total();
+                    let result = function.check_trait_expectations_metered(env, &traits, &trait_id);
+                    let mut cost = env.global_context.cost_track.get_total();
+                    cost.sub(&start).unwrap();
+                    (result, cost.runtime)
+                };
+
+                // the cost grows with the size of the compared types
+                let (result, shallow_cost) = metered_cost(env, "shallow");
+                result.unwrap();
+                let (result, deep_cost) = metered_cost(env, "deep");
+                result.unwrap();
+                assert!(shallow_cost > 0);
+                assert!(deep_cost > 3 * shallow_cost);
+
+                // and is charged against the transaction's budget
+                let limit = ExecutionCost {
+                    runtime: deep_cost / 2,
+                    ..ExecutionCost::max_value()
+                };
+                env.global_context.cost_track = LimitedCostTracker::new_mid_block(
+                    false,
+                    test_only_mainnet_to_chain_id(false),
+                    limit,
+                    &mut env.global_context.database,
+                    StacksEpochId::Epoch21,
+                )
+                .unwrap();
+                let (result, _) = metered_cost(env, "deep");
+                assert!(matches!(
+                    result,
+                    Err(Error::Unchecked(CheckErrors::CostBalanceExceeded(..)))
+                ));
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+    })
+}
+
+#[test]
+fn test_execute_apply_metered() {
+    with_owned_env(StacksEpochId::Epoch21, false, |mut owned_env| {
+        let issuer = match execute("'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR") {
+            Value::Principal(PrincipalData::Standard(data)) => data,
+            _ => panic!(),
+        };
+        let contract_id = QualifiedContractIdentifier::new(issuer.clone(), "metered".into());
+        owned_env
+            .initialize_contract(
+                contract_id.clone(),
+                "(define-read-only (sum (n int)) (fold + (list 1 2 3 4 5 6 7 8 9 10) n))",
+                None,
+                ASTRules::PrecheckSize,
+            )
+            .unwrap();
+
+        owned_env
+            .execute_in_env(issuer.into(), None, None, |env| {
+                let function = env
+                    .global_context
+                    .database
+                    .get_contract(&contract_id)
+                    .unwrap()
+                    .contract_context
+                    .lookup_function("sum")
+                    .unwrap();
+                let limit = env.global_context.cost_track.get_limit();
+
+                let start = env.global_context.cost_track.get_total();
+                let (result, remaining) = function
+                    .execute_apply_metered(&[Value::Int(0)], env, u64::MAX)
+                    .unwrap();
+                assert_eq!(result, Value::Int(55));
+                let mut cost = env.global_context.cost_track.get_total();
+                cost.sub(&start).unwrap();
+                assert!(cost.runtime > 0);
+                assert_eq!(remaining, u64::MAX - cost.runtime);
+
+                // under budget
+                let budget = 2 * cost.runtime;
+                assert_eq!(
+                    function.execute_apply_metered(&[Value::Int(0)], env, budget),
+                    Ok((Value::Int(55), budget - cost.runtime))
+                );
+
+                // over budget
+                let result =
+                    function.execute_apply_metered(&[Value::Int(0)], env, cost.runtime / 2);
+                assert!(matches!(
+                    result,
+                    Err(Error::Unchecked(CheckErrors::CostBalanceExceeded(..)))
+                ));
+                // the tracker's own limit is restored
+                assert_eq!(env.global_context.cost_track.get_limit(), limit);
+
+                // ... including when leaving a nested budget early
+                let result = env.with_runtime_budget(budget, |env| {
+                    function.execute_apply_metered(&[Value::Int(0)], env, cost.runtime / 2)?;
+                    Ok(())
+                });
+                assert!(matches!(
+                    result,
+                    Err(Error::Unchecked(CheckErrors::CostBalanceExceeded(..)))
+                ));
+                assert_eq!(env.global_context.cost_track.get_limit(), limit);
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+    })
+}
+
+#[test]
+fn test_memoized_cost() {
+    with_owned_env(StacksEpochId::Epoch21, false, |mut owned_env| {
+        let contract_id = QualifiedContractIdentifier::local("memoized").unwrap();
+        owned_env
+            .initialize_contract(
+                contract_id.clone(),
+                "(define-private (square (x int)) (* x x))
+                (define-read-only (squares) (+ (square 3) (square 3)))",
+                None,
+                ASTRules::PrecheckSize,
+            )
+            .unwrap();
+        let cost_of_squares = |owned_env: &mut OwnedEnvironment| {
+            let start = owned_env.get_cost_total();
+            let (result, _, _) = owned_env.eval_read_only(&contract_id, "(squares)").unwrap();
+            assert_eq!(result, Value::Int(18));
+            let mut cost = owned_env.get_cost_total();
+            cost.sub(&start).unwrap();
+            cost
+        };
+        let unmemoized = cost_of_squares(&mut owned_env);
+        assert!(unmemoized.runtime > 0);
+
+        let contract = owned_env
+            .execute_in_env(
+                PrincipalData::from(contract_id.clone()),
+                None,
+                None,
+                |env| env.global_context.database.get_contract(&contract_id),
+            )
+            .unwrap()
+            .0;
+        let square = contract.contract_context.lookup_function("square").unwrap();
+        assert!(owned_env.enable_memoization(&square, &contract.contract_context));
+
+        // the second `(square 3)` is cached, but still charged
+        assert_eq!(cost_of_squares(&mut owned_env), unmemoized);
+    })
+}
+
+#[test]
+fn test_host_function_cost() {
+    with_owned_env(StacksEpochId::Epoch21, false, |mut owned_env| {
+        let contract_id = QualifiedContractIdentifier::local("hosted").unwrap();
+        owned_env
+            .initialize_contract(
+                contract_id.clone(),
+                "(define-read-only (nothing) true)",
+                None,
+                ASTRules::PrecheckSize,
+            )
+            .unwrap();
+        let double = |args: &[Value]| -> Result<Value, Error> {
+            match args {
+                [Value::Int(x)] => Ok(Value::Int(x * 2)),
+                _ => Err(CheckErrors::IncorrectArgumentCount(1, args.len()).into()),
+            }
+        };
+        owned_env
+            .register_host_function(HostFunction::new("host-double".into(), Box::new(double)))
+            .unwrap();
+        owned_env
+            .set_execution_options(ExecutionOptions {
+                host_functions: true,
+                ..ExecutionOptions::default()
+            })
+            .unwrap();
+
+        let mut cost_of = |program: &str| {
+            let start = owned_env.get_cost_total();
+            let _ = owned_env.eval_read_only(&contract_id, program);
+            let mut cost = owned_env.get_cost_total();
+            cost.sub(&start).unwrap();
+            cost
+        };
+        // an undefined function of a name as long is only charged its lookup
+        let undefined = cost_of("(host-triple 3)");
+        let applied = cost_of("(host-double 3)");
+        assert!(applied.runtime > undefined.runtime);
+    })
+}